@@ -0,0 +1,39 @@
+# VIBE - shell integration for ~/.bashrc (paste at the end)
+# Emits the OSC 133 markers vibe parses; inert outside a vibe session
+if [ -n "$VIBE_NONCE" ] && [ -z "$__VIBE_HOOKED" ]; then
+    __VIBE_HOOKED=1
+    __vibe_at_prompt=
+    __vibe_ran=
+
+    __vibe_osc() {
+        printf "\033]133;%s;vibe=%s\007" "$1" "${VIBE_NONCE}"
+    }
+
+    # Runs first in PROMPT_COMMAND so $? is still the command's status
+    __vibe_precmd() {
+        local exit_code=$?
+        [ -n "$__vibe_ran" ] && __vibe_osc "D;${exit_code}"
+        __vibe_ran=
+        __vibe_osc "A"
+    }
+
+    # Runs last, arming the DEBUG trap for the next command line
+    __vibe_ready() {
+        __vibe_at_prompt=1
+        __vibe_osc "B"
+    }
+
+    __vibe_preexec() {
+        [ -n "$__vibe_at_prompt" ] || return
+        [ -n "$COMP_LINE" ] && return
+        __vibe_at_prompt=
+        __vibe_ran=1
+        local cmd
+        cmd=$(HISTTIMEFORMAT= history 1 | sed 's/^ *[0-9]* *//')
+        __vibe_osc "C"
+        printf "\033]133;VIBE;CMD;%s;vibe=%s\007" "$(printf "%s" "$cmd" | base64 | tr -d '\n')" "${VIBE_NONCE}"
+    }
+
+    PROMPT_COMMAND="__vibe_precmd${PROMPT_COMMAND:+;$PROMPT_COMMAND};__vibe_ready"
+    trap '__vibe_preexec' DEBUG
+fi
@@ -0,0 +1,23 @@
+# VIBE - shell integration for ~/.config/fish/config.fish
+# Emits the OSC 133 markers vibe parses; inert outside a vibe session
+if set -q VIBE_NONCE; and not set -q __VIBE_HOOKED
+    set -g __VIBE_HOOKED 1
+
+    function __vibe_osc
+        printf "\e]133;%s;vibe=%s\a" $argv[1] $VIBE_NONCE
+    end
+
+    function __vibe_prompt --on-event fish_prompt
+        __vibe_osc A
+        __vibe_osc B
+    end
+
+    function __vibe_preexec --on-event fish_preexec
+        __vibe_osc C
+        printf "\e]133;VIBE;CMD;%s;vibe=%s\a" (printf "%s" $argv[1] | base64 | tr -d '\n') $VIBE_NONCE
+    end
+
+    function __vibe_postexec --on-event fish_postexec
+        __vibe_osc "D;$status"
+    end
+end
@@ -0,0 +1,162 @@
+// Allocations per output chunk and per write_input call on a live session, for
+// 16 MB of output and 100k 16-byte writes:
+//   cargo bench --bench pty_allocations
+// Its own target so the counting allocator below doesn't slow every unit test.
+// With Vec payloads (before Bytes) this measured about 1.04 per chunk and 2.03 per
+// call; with Bytes, about 0.17 and 1.03 (the one left is the caller's Vec).
+//
+// The app is a binary crate, so its modules are compiled in from src/ directly.
+// Most of them go unused here, and `cargo clippy --all-targets` checks this target
+// with cfg(test) but no test harness, which leaves the unit tests' imports unused.
+
+#![allow(dead_code, unused_imports)]
+
+#[path = "../src/abbrev.rs"]
+mod abbrev;
+#[path = "../src/agg.rs"]
+mod agg;
+#[path = "../src/autotag.rs"]
+mod autotag;
+#[path = "../src/cast.rs"]
+mod cast;
+#[path = "../src/context.rs"]
+mod context;
+#[path = "../src/db.rs"]
+mod db;
+#[path = "../src/echo.rs"]
+mod echo;
+#[path = "../src/envfile.rs"]
+mod envfile;
+#[path = "../src/errors.rs"]
+mod errors;
+#[path = "../src/export.rs"]
+mod export;
+#[path = "../src/fifo.rs"]
+mod fifo;
+#[path = "../src/focus.rs"]
+mod focus;
+#[path = "../src/hooks.rs"]
+mod hooks;
+#[path = "../src/integration.rs"]
+mod integration;
+#[path = "../src/mirror.rs"]
+mod mirror;
+#[path = "../src/osc.rs"]
+mod osc;
+#[path = "../src/paths.rs"]
+mod paths;
+#[path = "../src/pty.rs"]
+mod pty;
+#[path = "../src/screen.rs"]
+mod screen;
+#[path = "../src/secrets.rs"]
+mod secrets;
+#[path = "../src/settings.rs"]
+mod settings;
+#[path = "../src/signing.rs"]
+mod signing;
+#[cfg(test)]
+#[path = "../src/test_util.rs"]
+mod test_util;
+#[path = "../src/threads.rs"]
+mod threads;
+#[path = "../src/urls.rs"]
+mod urls;
+#[path = "../src/vt.rs"]
+mod vt;
+#[path = "../src/ws.rs"]
+mod ws;
+#[path = "../src/zdotdir.rs"]
+mod zdotdir;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use pty::{PtySession, PtySize, ReadTuning};
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+// A scratch HOME whose shell integration points at the checkout, since an optimized
+// build looks for it in ~/.vibecodings/shell-integration
+fn scratch_home() -> PathBuf {
+    let home = std::env::temp_dir().join(format!("vibe-bench-{}", std::process::id()));
+    std::fs::create_dir_all(home.join(".vibecodings")).unwrap();
+    let integration = Path::new(env!("CARGO_MANIFEST_DIR")).join("../shell-integration");
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(integration, home.join(".vibecodings/shell-integration")).unwrap();
+    home
+}
+
+fn main() {
+    let home = scratch_home();
+    std::env::set_var("HOME", &home);
+
+    let size = PtySize { rows: 50, cols: 200, pixel_width: 0, pixel_height: 0 };
+    let session = PtySession::new(
+        format!("bench-{}", std::process::id()),
+        "/bin/sh",
+        &std::env::temp_dir(),
+        &[],
+        size,
+        None,
+        ReadTuning::default(),
+    )
+    .unwrap();
+    std::thread::sleep(Duration::from_millis(500));
+    while session.read_output().is_some() {}
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    session
+        .write_input(&b"head -c 16000000 /dev/zero | tr '\\0' a; echo; echo END''MARK\n"[..])
+        .unwrap();
+    let (mut chunks, mut tail) = (0usize, Vec::new());
+    let started = Instant::now();
+    while !String::from_utf8_lossy(&tail).contains("ENDMARK") {
+        assert!(started.elapsed() < Duration::from_secs(60), "output never finished");
+        match session.read_output() {
+            Some(chunk) => {
+                chunks += 1;
+                tail.extend_from_slice(&chunk);
+                tail.drain(..tail.len().saturating_sub(64));
+            }
+            None => std::thread::sleep(Duration::from_micros(200)),
+        }
+    }
+    let read = ALLOCATIONS.load(Ordering::Relaxed) - before;
+
+    let calls = 100_000;
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    for _ in 0..calls {
+        session.write_input(vec![b':'; 16]).unwrap();
+    }
+    let write = ALLOCATIONS.load(Ordering::Relaxed) - before;
+
+    println!("read:  {} allocations over {} chunks ({:.2} per chunk)", read, chunks, read as f64 / chunks as f64);
+    println!("write: {} allocations over {} calls ({:.2} per call)", write, calls, write as f64 / calls as f64);
+
+    drop(session);
+    std::fs::remove_dir_all(&home).ok();
+}
@@ -0,0 +1,178 @@
+// Abbreviations expanded as they're typed, from the abbreviations setting
+// e.g. {";gs": "git status"}: typing `;gs` then space sends `git status `
+// Only a word typed key by key at the end of the line is expanded. Pastes, arrows and
+// editing keys leave the line's contents unknown, so nothing expands until the next
+// space or Enter. The typed abbreviation is erased with one DEL per character.
+// Expansion needs the shell integration: only input at a prompt, between OSC 133;B
+// and 133;C, is rewritten. Programs reading the terminal, and full-screen apps on the
+// alternate screen, get exactly what was typed.
+
+use anyhow::{bail, Result};
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::osc::OscEvent;
+use crate::screen;
+use crate::vt;
+
+const ERASE: char = '\x7f';
+const DELIMITERS: [char; 3] = [' ', '\r', '\n'];
+
+/// Parse the setting, a map of abbreviation to expansion; null means none
+pub fn from_setting(value: &Value) -> Result<HashMap<String, String>> {
+    if value.is_null() {
+        return Ok(HashMap::new());
+    }
+    let abbreviations: HashMap<String, String> = serde_json::from_value(value.clone())?;
+    for (abbreviation, expansion) in &abbreviations {
+        if abbreviation.is_empty() || abbreviation.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            bail!("Abbreviation {:?} must be a single word", abbreviation);
+        }
+        if expansion.is_empty() {
+            bail!("Abbreviation {:?} needs an expansion", abbreviation);
+        }
+    }
+    Ok(abbreviations)
+}
+
+#[derive(Debug)]
+pub struct Expander {
+    abbreviations: HashMap<String, String>,
+    enabled: bool,
+    word: Option<String>, // Typed since the last delimiter; None while the line is unknown
+    at_prompt: bool, // Between OSC 133;B and 133;C
+    alt_screen: bool,
+}
+
+impl Default for Expander {
+    fn default() -> Self {
+        Expander {
+            abbreviations: HashMap::new(),
+            enabled: true,
+            word: Some(String::new()),
+            at_prompt: false,
+            alt_screen: false,
+        }
+    }
+}
+
+impl Expander {
+    pub fn set_abbreviations(&mut self, abbreviations: HashMap<String, String>) {
+        self.abbreviations = abbreviations;
+    }
+
+    /// Input is still tracked while disabled, so turning it back on mid-line is safe
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Follow the shell integration's marks for where the prompt is
+    pub fn osc(&mut self, event: &OscEvent) {
+        match event {
+            OscEvent::PromptEnd => {
+                self.at_prompt = true;
+                self.word = Some(String::new());
+            }
+            OscEvent::PromptStart | OscEvent::CommandStart => self.at_prompt = false,
+            _ => {}
+        }
+    }
+
+    /// Follow switches to and from the alternate screen in the session's output
+    pub fn output(&mut self, output: &[u8]) {
+        if !output.windows(3).any(|w| w == b"\x1b[?") {
+            return;
+        }
+        for token in vt::tokenize(&String::from_utf8_lossy(output)) {
+            if let Some(on) = screen::alt_screen_switch(&token) {
+                self.alt_screen = on;
+            }
+        }
+    }
+
+    /// What to write instead of `data`, if it's the space or Enter ending an
+    /// abbreviation. `typed` is false for input not sent key by key from the keyboard.
+    pub fn input(&mut self, data: &str, typed: bool) -> Option<String> {
+        let mut chars = data.chars();
+        let key = match (chars.next(), chars.next()) {
+            (Some(key), None) if typed => key,
+            _ => {
+                // Pasted or sent for the user: only a trailing delimiter says where the line stands
+                self.word = data.ends_with(DELIMITERS).then(String::new);
+                return None;
+            }
+        };
+
+        match key {
+            _ if DELIMITERS.contains(&key) => {
+                let word = self.word.replace(String::new())?;
+                let active = self.enabled && self.at_prompt && !self.alt_screen;
+                let expansion = self.abbreviations.get(&word).filter(|_| active)?;
+                let mut replacement: String = std::iter::repeat_n(ERASE, word.chars().count()).collect();
+                replacement.push_str(expansion);
+                replacement.push(key);
+                Some(replacement)
+            }
+            '\x03' | '\x15' => {
+                // Ctrl-C and Ctrl-U leave an empty line
+                self.word = Some(String::new());
+                None
+            }
+            _ if key.is_control() => {
+                self.word = None;
+                None
+            }
+            _ => {
+                if let Some(ref mut word) = self.word {
+                    word.push(key);
+                }
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn type_keys(expander: &mut Expander, keys: &str) -> Vec<String> {
+        keys.chars()
+            .filter_map(|key| expander.input(&key.to_string(), true))
+            .collect()
+    }
+
+    #[test]
+    fn test_expander() {
+        let mut expander = Expander::default();
+        expander.set_abbreviations(from_setting(&json!({";gs": "git status"})).unwrap());
+        expander.osc(&OscEvent::PromptEnd);
+
+        assert_eq!(type_keys(&mut expander, ";gs "), vec!["\x7f\x7f\x7fgit status "]);
+        assert_eq!(type_keys(&mut expander, "echo ;gs\r"), vec!["\x7f\x7f\x7fgit status\r"]);
+        // Only a whole word, typed fresh
+        assert!(type_keys(&mut expander, "x;gs ").is_empty());
+        assert!(type_keys(&mut expander, ";g\x7fgs ").is_empty());
+        assert!(expander.input(";gs", false).is_none());
+        assert!(type_keys(&mut expander, " ").is_empty());
+        assert!(expander.input("\x1b[D", true).is_none());
+        assert_eq!(type_keys(&mut expander, ";gs\x15;gs ").len(), 1);
+
+        expander.set_enabled(false);
+        assert!(type_keys(&mut expander, ";gs ").is_empty());
+        expander.set_enabled(true);
+
+        // Not at a prompt: a running command or a full-screen app gets the keys as typed
+        expander.osc(&OscEvent::CommandStart);
+        assert!(type_keys(&mut expander, ";gs ").is_empty());
+        expander.osc(&OscEvent::PromptEnd);
+        expander.output(b"\x1b[?1049h");
+        assert!(type_keys(&mut expander, ";gs ").is_empty());
+        expander.output(b"\x1b[?1049l$ ");
+        assert_eq!(type_keys(&mut expander, ";gs ").len(), 1);
+
+        assert!(from_setting(&json!({"g s": "git status"})).is_err());
+        assert!(from_setting(&json!({";gs": ""})).is_err());
+    }
+}
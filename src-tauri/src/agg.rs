@@ -0,0 +1,82 @@
+// GIF rendering through the external `agg` tool (asciinema gif generator)
+// Nothing is bundled: the cast is always written, and agg only runs if it's on PATH
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::export::shell_quote;
+
+const AGG: &str = "agg";
+
+// Keep agg's error output short enough to show in the UI
+const MAX_OUTPUT_CHARS: usize = 2000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AggExport {
+    pub cast_path: String,
+    pub gif_path: String,
+    pub command: String, // agg command line that renders the GIF, for running by hand
+    pub ran: bool, // agg was found and run
+    pub rendered: bool, // ...and exited successfully
+    pub rendering: bool, // agg is still running; an "agg-rendered" event carries the outcome
+    pub message: Option<String>, // Why agg didn't run, or what it printed on failure
+}
+
+/// `agg` in a PATH directory, if installed
+pub fn find_agg() -> Option<PathBuf> {
+    let name = if cfg!(windows) { "agg.exe" } else { AGG };
+    std::env::var_os("PATH").and_then(|paths| {
+        std::env::split_paths(&paths)
+            .map(|dir| dir.join(name))
+            .find(|candidate| candidate.is_file())
+    })
+}
+
+/// Suggested command line, with both paths quoted for the shell
+pub fn command_line(cast_path: &Path, gif_path: &Path) -> String {
+    format!(
+        "{} {} {}",
+        AGG,
+        shell_quote(&cast_path.to_string_lossy()),
+        shell_quote(&gif_path.to_string_lossy())
+    )
+}
+
+/// Run agg on an already-written cast. Ok(None) on success, Ok(Some(output)) when
+/// agg exited non-zero, Err when it couldn't be started.
+pub fn render(agg: &Path, cast_path: &Path, gif_path: &Path) -> Result<Option<String>> {
+    let output = Command::new(agg)
+        .arg(cast_path)
+        .arg(gif_path)
+        .output()
+        .with_context(|| format!("Failed to run {}", agg.display()))?;
+    if output.status.success() {
+        return Ok(None);
+    }
+
+    let mut text = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    if let Some((idx, _)) = text.char_indices().nth(MAX_OUTPUT_CHARS) {
+        text.truncate(idx);
+        text.push('…');
+    }
+    let status = match output.status.code() {
+        Some(code) => format!("agg exited with {}", code),
+        None => "agg was terminated by a signal".to_string(),
+    };
+    Ok(Some(if text.is_empty() { status } else { format!("{}: {}", status, text) }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_line() {
+        assert_eq!(
+            command_line(Path::new("/tmp/it's.cast"), Path::new("/tmp/out.gif")),
+            "agg '/tmp/it'\\''s.cast' '/tmp/out.gif'"
+        );
+    }
+}
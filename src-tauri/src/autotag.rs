@@ -0,0 +1,93 @@
+// Tags applied to sessions automatically, from rules in the auto_tag_rules setting
+// A rule matches on the session's cwd or on any of its recorded commands
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::Path;
+
+/// One rule: `{"cwd_prefix": "...", "tag": "..."}` or `{"command_contains": "...", "tag": "..."}`.
+/// cwd_prefix matches whole path components: "/work/acme" covers "/work/acme/api" but
+/// not "/work/acme-old".
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Rule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd_prefix: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command_contains: Option<String>,
+    pub tag: String,
+}
+
+// Tags a session got from a rules pass that it didn't already have
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AutoTagged {
+    pub session_id: String,
+    pub tags: Vec<String>,
+}
+
+/// Parse the setting, a list of rules; null means none
+pub fn rules_from_setting(value: &Value) -> Result<Vec<Rule>> {
+    if value.is_null() {
+        return Ok(Vec::new());
+    }
+    let rules: Vec<Rule> = serde_json::from_value(value.clone())?;
+    for rule in &rules {
+        if rule.tag.trim().is_empty() {
+            bail!("Auto-tag rule needs a tag");
+        }
+        let non_empty = |s: &Option<String>| s.as_deref().is_some_and(|s| !s.is_empty());
+        if non_empty(&rule.cwd_prefix) == non_empty(&rule.command_contains) {
+            bail!("Auto-tag rule for {:?} needs exactly one of cwd_prefix or command_contains", rule.tag);
+        }
+    }
+    Ok(rules)
+}
+
+/// Tags whose rules match, in rule order without repeats
+pub fn matching_tags(rules: &[Rule], cwd: &str, commands: &[String]) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for rule in rules {
+        let matched = match (&rule.cwd_prefix, &rule.command_contains) {
+            (Some(prefix), _) => Path::new(cwd).starts_with(prefix),
+            (None, Some(needle)) => commands.iter().any(|c| c.contains(needle.as_str())),
+            (None, None) => false,
+        };
+        if matched && !tags.contains(&rule.tag) {
+            tags.push(rule.tag.clone());
+        }
+    }
+    tags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_matching_tags() {
+        let rules = rules_from_setting(&json!([
+            {"cwd_prefix": "/work/acme", "tag": "acme"},
+            {"command_contains": "kubectl", "tag": "k8s"},
+            {"command_contains": "helm", "tag": "k8s"},
+            {"cwd_prefix": "/tmp", "tag": "scratch"},
+        ]))
+        .unwrap();
+        let commands = vec!["kubectl get pods".to_string(), "helm list".to_string()];
+
+        assert_eq!(matching_tags(&rules, "/work/acme/api", &commands), vec!["acme", "k8s"]);
+        assert!(matching_tags(&rules, "/home/me", &[]).is_empty());
+        assert!(rules_from_setting(&Value::Null).unwrap().is_empty());
+        assert!(rules_from_setting(&json!([{"tag": "x"}])).is_err());
+        assert!(rules_from_setting(&json!([{"cwd_prefix": "/a", "command_contains": "b", "tag": "x"}])).is_err());
+    }
+
+    #[test]
+    fn test_cwd_prefix_matches_components() {
+        let rules = rules_from_setting(&json!([{"cwd_prefix": "/work/acme/", "tag": "acme"}])).unwrap();
+        assert_eq!(matching_tags(&rules, "/work/acme", &[]), vec!["acme"]);
+        assert_eq!(matching_tags(&rules, "/work/acme/api", &[]), vec!["acme"]);
+        assert!(matching_tags(&rules, "/work/acme-old", &[]).is_empty());
+        assert!(matching_tags(&rules, "/work/acmeish/api", &[]).is_empty());
+    }
+}
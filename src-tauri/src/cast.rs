@@ -0,0 +1,201 @@
+// asciinema v2 cast export and import
+// Header line followed by [time, code, data] event lines, time in seconds from start
+
+// Latest event time accepted, in seconds: anything later can't be a real recording
+// and would overflow the millisecond offsets
+const MAX_EVENT_SECS: f64 = 100.0 * 365.25 * 86_400.0;
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, FixedOffset};
+
+use crate::db::{Event, Session};
+
+const DEFAULT_COLS: u16 = 80;
+const DEFAULT_ROWS: u16 = 24;
+
+/// Build a .cast document from a session and its events (ordered by ts)
+pub fn build_cast(session: &Session, events: &[Event], title: Option<&str>) -> Result<String> {
+    let started = parse_ts(&session.started_at)?;
+
+    let mut header = serde_json::json!({
+        "version": 2,
+        "width": session.cols.unwrap_or(DEFAULT_COLS),
+        "height": session.rows.unwrap_or(DEFAULT_ROWS),
+        "timestamp": started.timestamp(),
+        "env": {
+            "SHELL": session.shell,
+            "TERM": "xterm-256color",
+        },
+    });
+    if let Some(title) = title {
+        header["title"] = serde_json::json!(title);
+    }
+
+    let mut lines = vec![header.to_string()];
+
+    for event in events {
+        // Resize events carry "<cols>x<rows>", exactly the v2 "r" payload
+        let code = match event.kind.as_str() {
+            "pty_out" => "o",
+            "resize" => "r",
+            _ => continue,
+        };
+
+        let offset = (parse_ts(&event.ts)? - started).num_microseconds().unwrap_or(0);
+        let time = offset.max(0) as f64 / 1_000_000.0;
+
+        lines.push(serde_json::json!([time, code, event.data]).to_string());
+    }
+
+    Ok(lines.join("\n") + "\n")
+}
+
+/// A parsed .cast file
+#[derive(Debug, Clone)]
+pub struct Recording {
+    pub cols: u16,
+    pub rows: u16,
+    pub timestamp: Option<i64>, // Unix seconds the recording started, if the header has it
+    pub shell: Option<String>, // From the header's env.SHELL
+    pub title: Option<String>,
+    pub events: Vec<RecordedEvent>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedEvent {
+    pub offset_ms: i64,
+    pub kind: &'static str, // Event kind it's stored as: pty_out, user_in, resize or marker
+    pub data: String,
+}
+
+/// Parse an asciinema v2 cast. "o", "i", "r" and "m" events map to pty_out,
+/// user_in, resize and marker; other codes are skipped. Event times must be finite,
+/// not negative and under a century.
+pub fn parse_cast(text: &str) -> Result<Recording> {
+    let mut lines = text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty());
+    let (_, header) = lines.next().context("Empty cast file")?;
+    let header: serde_json::Value = serde_json::from_str(header).context("Invalid cast header")?;
+    if header["version"] != 2 {
+        bail!("Unsupported cast version {} (only v2 is supported)", header["version"]);
+    }
+    let size = |key: &str| {
+        header[key]
+            .as_u64()
+            .and_then(|n| u16::try_from(n).ok())
+            .filter(|n| *n > 0)
+            .with_context(|| format!("Cast header has no valid {}", key))
+    };
+
+    let mut recording = Recording {
+        cols: size("width")?,
+        rows: size("height")?,
+        timestamp: header["timestamp"].as_i64(),
+        shell: header["env"]["SHELL"].as_str().map(str::to_string),
+        title: header["title"].as_str().map(str::to_string),
+        events: Vec::new(),
+    };
+
+    for (idx, line) in lines {
+        let parsed: Option<(f64, String, String)> = serde_json::from_str(line).ok();
+        let Some((time, code, data)) = parsed.filter(|(time, _, _)| (0.0..=MAX_EVENT_SECS).contains(time)) else {
+            bail!("Invalid cast event on line {}", idx + 1);
+        };
+        let kind = match code.as_str() {
+            "o" => "pty_out",
+            "i" => "user_in",
+            "r" => "resize",
+            "m" => "marker",
+            _ => continue,
+        };
+        recording.events.push(RecordedEvent {
+            offset_ms: (time * 1000.0).round() as i64,
+            kind,
+            data,
+        });
+    }
+    Ok(recording)
+}
+
+fn parse_ts(ts: &str) -> Result<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc3339(ts).with_context(|| format!("Invalid timestamp: {}", ts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::event;
+
+    fn event_at(ts: &str, kind: &str, data: &str) -> Event {
+        Event { ts: ts.to_string(), kind: kind.to_string(), ..event("e", data) }
+    }
+
+    #[test]
+    fn test_resize_events_interleaved() {
+        let session = Session {
+            id: "s".to_string(),
+            started_at: "2026-01-01T00:00:00+00:00".to_string(),
+            ended_at: None,
+            cwd: "/".to_string(),
+            shell: "/bin/zsh".to_string(),
+            cols: Some(100),
+            rows: Some(30),
+            env_file: None,
+            is_template: false,
+            parent_session_id: None,
+            favorite: false,
+        };
+        let events = vec![
+            event_at("2026-01-01T00:00:00.500+00:00", "pty_out", "hello"),
+            event_at("2026-01-01T00:00:01+00:00", "resize", "120x40"),
+            event_at("2026-01-01T00:00:01.250+00:00", "user_in", "ls\r"),
+            event_at("2026-01-01T00:00:02+00:00", "pty_out", "world"),
+        ];
+
+        let cast = build_cast(&session, &events, None).unwrap();
+        let lines: Vec<serde_json::Value> = cast
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0]["width"], 100);
+        assert_eq!(lines[0]["height"], 30);
+        assert_eq!(lines[1], serde_json::json!([0.5, "o", "hello"]));
+        assert_eq!(lines[2], serde_json::json!([1.0, "r", "120x40"]));
+        assert_eq!(lines[3], serde_json::json!([2.0, "o", "world"]));
+    }
+
+    #[test]
+    fn test_parse_cast() {
+        let text = concat!(
+            r#"{"version": 2, "width": 90, "height": 20, "timestamp": 1700000000, "env": {"SHELL": "/bin/bash"}}"#,
+            "\n",
+            r#"[0.25, "o", "$ "]"#,
+            "\n\n",
+            r#"[1.5, "i", "ls\r"]"#,
+            "\n",
+            r#"[1.75, "x", "custom"]"#,
+            "\n",
+            r#"[2.0, "r", "100x30"]"#,
+            "\n",
+        );
+        let recording = parse_cast(text).unwrap();
+        assert_eq!((recording.cols, recording.rows), (90, 20));
+        assert_eq!(recording.timestamp, Some(1_700_000_000));
+        assert_eq!(recording.shell.as_deref(), Some("/bin/bash"));
+        let kinds: Vec<(i64, &str)> = recording.events.iter().map(|e| (e.offset_ms, e.kind)).collect();
+        assert_eq!(kinds, vec![(250, "pty_out"), (1500, "user_in"), (2000, "resize")]);
+
+        assert!(parse_cast(r#"{"version": 1, "width": 80, "height": 24}"#).is_err());
+        assert!(parse_cast("{\"version\": 2, \"width\": 80, \"height\": 24}\n[\"bad\"]").is_err());
+    }
+
+    #[test]
+    fn test_parse_cast_rejects_bad_times() {
+        let header = r#"{"version": 2, "width": 80, "height": 24}"#;
+        for time in ["-1", "1e300", "1e16", "NaN"] {
+            assert!(parse_cast(&format!("{}\n[{}, \"o\", \"x\"]", header, time)).is_err(), "{}", time);
+        }
+        assert!(parse_cast(&format!("{}\n[3153600000, \"o\", \"x\"]", header)).is_ok());
+    }
+}
@@ -0,0 +1,133 @@
+// Best-guess language/tool context of a session, for suggested names and tags
+// A fixed rule set over the programs a session ran and marker files in its cwd
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+struct Rule {
+    tag: &'static str,
+    programs: &'static [&'static str], // Also match versioned names, e.g. python3.12
+    files: &'static [&'static str],
+}
+
+const RULES: &[Rule] = &[
+    Rule { tag: "rust", programs: &["cargo", "rustc", "rustup"], files: &["Cargo.toml"] },
+    Rule { tag: "node", programs: &["npm", "npx", "node", "yarn", "pnpm", "bun"], files: &["package.json"] },
+    Rule {
+        tag: "python",
+        programs: &["python", "pip", "pytest", "poetry", "uv", "pipenv"],
+        files: &["pyproject.toml", "requirements.txt", "setup.py"],
+    },
+    Rule { tag: "go", programs: &["go"], files: &["go.mod"] },
+    Rule { tag: "ruby", programs: &["ruby", "bundle", "gem", "rails", "rake"], files: &["Gemfile"] },
+    Rule { tag: "java", programs: &["java", "javac", "mvn", "gradle", "gradlew"], files: &["pom.xml", "build.gradle"] },
+    Rule {
+        tag: "docker",
+        programs: &["docker", "docker-compose", "podman"],
+        files: &["Dockerfile", "docker-compose.yml", "compose.yaml"],
+    },
+    Rule { tag: "kubernetes", programs: &["kubectl", "helm", "k9s"], files: &[] },
+    Rule { tag: "terraform", programs: &["terraform", "tofu"], files: &["main.tf"] },
+];
+
+// A marker file says more about the project than any single command
+const FILE_WEIGHT: f64 = 3.0;
+const COMMAND_WEIGHT: f64 = 1.0;
+
+// Wrappers skipped to find the program a command actually runs
+const PREFIXES: &[&str] = &["sudo", "time", "env", "exec", "nohup"];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContextGuess {
+    pub tag: String,
+    pub confidence: f64, // score / (score + 2): one command ~0.33, a marker file 0.6
+    pub evidence: Vec<String>,
+}
+
+/// Ranked guesses, most confident first; empty when nothing matched.
+/// `cwd_files` are the names of entries in the session's working directory.
+pub fn infer_context(commands: &[String], cwd_files: &[String]) -> Vec<ContextGuess> {
+    let mut runs: HashMap<&str, usize> = HashMap::new();
+    for command in commands {
+        for program in programs(command) {
+            *runs.entry(program).or_default() += 1;
+        }
+    }
+
+    let mut guesses: Vec<ContextGuess> = RULES
+        .iter()
+        .filter_map(|rule| {
+            let mut score = 0.0;
+            let mut evidence = Vec::new();
+            for file in rule.files.iter().filter(|f| cwd_files.iter().any(|name| name == *f)) {
+                score += FILE_WEIGHT;
+                evidence.push(format!("{} in cwd", file));
+            }
+            let mut ran: Vec<(&str, usize)> = runs
+                .iter()
+                .filter(|(program, _)| rule.programs.iter().any(|p| is_program(program, p)))
+                .map(|(program, count)| (*program, *count))
+                .collect();
+            ran.sort();
+            for (program, count) in ran {
+                score += COMMAND_WEIGHT * count as f64;
+                evidence.push(format!("ran {} ({}x)", program, count));
+            }
+            (score > 0.0).then(|| ContextGuess {
+                tag: rule.tag.to_string(),
+                confidence: score / (score + 2.0),
+                evidence,
+            })
+        })
+        .collect();
+
+    // Stable, so ties keep rule order
+    guesses.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+    guesses
+}
+
+// Program names in a command line: the first word of each `&&`, `||`, `;` or `|`
+// segment, past VAR=value assignments and wrappers like sudo, without any directory
+fn programs(command: &str) -> Vec<&str> {
+    command
+        .split(['&', '|', ';'])
+        .filter_map(|segment| {
+            segment
+                .split_whitespace()
+                .find(|word| !word.contains('=') && !PREFIXES.contains(word))
+                .map(|word| word.rsplit('/').next().unwrap_or(word))
+        })
+        .collect()
+}
+
+fn is_program(name: &str, program: &str) -> bool {
+    name.strip_prefix(program)
+        .is_some_and(|rest| rest.chars().all(|c| c.is_ascii_digit() || c == '.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_infer_context() {
+        let commands: Vec<String> = [
+            "cargo build",
+            "RUST_LOG=debug cargo run && ./target/debug/app",
+            "sudo docker compose up | tee log",
+            "python3.12 -m venv .venv",
+            "gopher --help",
+        ]
+        .iter()
+        .map(|c| c.to_string())
+        .collect();
+        let files = vec!["Cargo.toml".to_string(), "README.md".to_string()];
+
+        let guesses = infer_context(&commands, &files);
+        let tags: Vec<&str> = guesses.iter().map(|g| g.tag.as_str()).collect();
+        assert_eq!(tags, vec!["rust", "python", "docker"]);
+        assert_eq!(guesses[0].evidence, vec!["Cargo.toml in cwd", "ran cargo (2x)"]);
+        assert!((guesses[0].confidence - 5.0 / 7.0).abs() < 1e-9);
+        assert!(infer_context(&[], &[]).is_empty());
+    }
+}
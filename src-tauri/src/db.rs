@@ -1,10 +1,24 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use chrono::Utc;
-use rusqlite::{params, Connection, OptionalExtension};
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension, Row};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::cell::{Cell, OnceCell};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar};
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 
+use crate::autotag::{self, AutoTagged};
+use crate::cast::Recording;
+use crate::mirror::{self, Mirror};
+use crate::osc;
+use crate::paths;
+use crate::settings;
+use crate::vt;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Session {
     pub id: String,
@@ -12,6 +26,86 @@ pub struct Session {
     pub ended_at: Option<String>,
     pub cwd: String,
     pub shell: String,
+    pub cols: Option<u16>,
+    pub rows: Option<u16>,
+    pub env_file: Option<String>, // .env file applied to the shell's environment
+    pub is_template: bool, // Reusable starting point for create_from_template
+    pub parent_session_id: Option<String>, // Session this one was spawned from
+    pub favorite: bool, // Pinned: listed first and never pruned
+}
+
+const SESSION_COLUMNS: &str =
+    "id, started_at, ended_at, cwd, shell, cols, rows, env_file, is_template, parent_session_id, favorite";
+const SESSION_COLUMN_COUNT: usize = 11;
+
+const EVENT_COLUMNS: &str = "id, session_id, ts, kind, data, ts_ms, seq, display";
+const EVENT_COLUMN_COUNT: usize = 8;
+
+const COMMAND_COLUMNS: &str =
+    "id, session_id, input, exit_code, started_at, ended_at, output_first_seq, output_last_seq";
+const COMMAND_COLUMN_COUNT: usize = 8;
+
+// SET clause filling a finished command's output range from the pty_out events
+// logged within its [started_at, ended_at] window
+const COMMAND_OUTPUT_RANGE: &str = "output_first_seq = (
+        SELECT MIN(seq) FROM events
+        WHERE events.session_id = commands.session_id AND kind = 'pty_out'
+            AND ts_ms BETWEEN commands.started_at AND commands.ended_at
+    ),
+    output_last_seq = (
+        SELECT MAX(seq) FROM events
+        WHERE events.session_id = commands.session_id AND kind = 'pty_out'
+            AND ts_ms BETWEEN commands.started_at AND commands.ended_at
+    )";
+
+// Stored in PRAGMA user_version once Database::new has migrated the file. Migrations
+// are additive and idempotent (CREATE ... IF NOT EXISTS, ensure_column) and all run on
+// every launch, so the version records which schema the file was last brought up to.
+// Bump it, and add a line below, with every table, column or index change.
+//   1: baseline, when the version was first recorded
+//   2: commands.output_first_seq / output_last_seq, idx_events_session_ts_ms
+//   3: sessions.sparkline
+//   4: prune_notices
+//   5: events.repeat_count, idx_events_repeats
+pub const SCHEMA_VERSION: i64 = 5;
+
+// Tables whose session_id references sessions(id)
+const SESSION_CHILD_TABLES: &[&str] = &["events", "commands", "interactions", "session_tags", "prune_notices"];
+
+// Session columns that change too often to write on every update; they go through
+// queue_session_metadata and are written by flush_session_metadata
+pub const LAST_ACTIVITY_AT: &str = "last_activity_at";
+const METADATA_COLUMNS: &[&str] = &[LAST_ACTIVITY_AT];
+
+// Keys accepted by get_sessions_sorted and the SQL each orders by
+const SESSION_SORT_KEYS: &[(&str, &str)] = &[
+    ("started_at", "started_at"),
+    ("duration", "julianday(COALESCE(ended_at, 'now')) - julianday(started_at)"),
+    ("event_count", "event_count"),
+    ("command_count", "command_count"),
+    ("last_activity_at", "last_activity_at"),
+];
+
+// Upper bounds (ms) of the idle histogram's buckets; the last bucket is open-ended
+const IDLE_BUCKET_BOUNDS_MS: &[i64] = &[5_000, 30_000, 120_000, 300_000, 1_800_000];
+
+// Time buckets in a session's activity sparkline
+const SPARKLINE_BUCKETS: usize = 60;
+
+// Output tail shown in the session list
+const PREVIEW_EVENTS: usize = 5;
+const PREVIEW_LINES: usize = 3;
+const PREVIEW_CHARS: usize = 200;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionDetail {
+    #[serde(flatten)]
+    pub session: Session,
+    pub event_count: i64,
+    pub command_count: i64,
+    pub duration_ms: Option<i64>, // None while the session is still running
+    pub last_activity_at: Option<String>, // Latest input or output, as of the last metadata flush
+    pub preview: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -19,8 +113,11 @@ pub struct Event {
     pub id: String,
     pub session_id: String,
     pub ts: String,
-    pub kind: String, // 'pty_out' | 'user_in' | 'marker'
+    pub kind: String, // 'pty_out' | 'user_in' | 'marker' | 'resize' | 'clipboard'
     pub data: String,
+    pub ts_ms: Option<i64>, // Epoch millis, for timing-faithful replay of input
+    pub seq: Option<i64>, // Per-session insertion order, stable when timestamps tie
+    pub display: Option<String>, // Readable form of user_in data, when readable_input is on
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -31,6 +128,18 @@ pub struct Command {
     pub exit_code: Option<i32>,
     pub started_at: i64,
     pub ended_at: Option<i64>,
+    // Seq range of its output events, for fetching them without a time scan; set
+    // when it ends, None while running or if it printed nothing
+    pub output_first_seq: Option<i64>,
+    pub output_last_seq: Option<i64>,
+}
+
+// A moment in a session: ms from its start, or an RFC3339 timestamp
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum TimePoint {
+    Offset(i64),
+    Timestamp(String),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -44,6 +153,121 @@ pub struct SessionSummary {
     pub commands: Vec<Command>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageMetrics {
+    pub total_sessions: i64,
+    pub total_commands: i64,
+    pub total_output_bytes: i64,
+    pub total_active_ms: i64, // Sum of ended session durations
+    pub busiest_hour: Option<u32>, // Local hour-of-day with the most commands
+}
+
+// Per-kind event totals used to estimate export sizes without building them
+#[derive(Debug, Clone, Default)]
+pub struct EventKindStats {
+    pub count: u64,
+    pub bytes: u64,       // UTF-8 length of data
+    pub escapes: u64,     // ESC characters, each 6 bytes once JSON-escaped
+    pub line_breaks: u64, // \r and \n, each 2 bytes once JSON-escaped
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ExportStats {
+    pub by_kind: HashMap<String, EventKindStats>,
+    pub command_count: u64,
+}
+
+// How a session's shell was started, recorded at spawn; see also the env snapshot
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionLaunchRecord {
+    pub argv: Vec<String>,
+    pub cwd: String,
+    pub cols: u16,
+    pub rows: u16,
+    pub requested_shell: Option<String>, // Set when the requested shell was missing and argv[0] replaced it
+    pub env_file: Option<String>,
+    pub tee: bool, // Output was also teed to a file from the start
+    #[serde(default)]
+    pub prompt: Option<String>, // Synthetic prompt the session was started with, if any
+}
+
+// Whether any of a session's events failed to save, until acknowledged
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordingStatus {
+    pub degraded: bool,
+    pub dropped_writes: i64,
+    pub last_dropped_at: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimedCommand {
+    #[serde(flatten)]
+    pub command: Command,
+    pub duration_ms: i64,
+}
+
+// Gaps between consecutive command starts that fell in [min_ms, max_ms)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdleBucket {
+    pub min_ms: i64,
+    pub max_ms: Option<i64>, // None for the last, open-ended bucket
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdleHistogram {
+    pub buckets: Vec<IdleBucket>,
+    pub gaps: usize, // One fewer than the commands, or 0
+}
+
+// One table-of-contents entry: a recorded command or a marker, in time order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutlineEntry {
+    pub kind: String, // "command" | "marker"
+    pub id: String, // Command id, or the marker's event id
+    pub title: String, // Command input or marker text
+    pub at_ms: i64, // Epoch millis
+    pub offset_ms: i64, // From session start, for seeking the replay
+    pub exit_code: Option<i32>,
+    pub duration_ms: Option<i64>, // None for markers and unfinished commands
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PathSessionMatch {
+    #[serde(flatten)]
+    pub session: Session,
+    pub mentions: usize,
+    pub last_mentioned_at: String,
+}
+
+// A session and, recursively, the sessions spawned from it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionNode {
+    #[serde(flatten)]
+    pub session: Session,
+    pub children: Vec<SessionNode>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionTree {
+    pub ancestors: Vec<Session>, // Oldest first, ending with the root's parent
+    pub root: SessionNode,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DuplicateGroup {
+    pub content_hash: String,
+    pub session_ids: Vec<String>, // Oldest first
+}
+
+// Criteria for bulk deletion; all set fields must match
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SessionFilter {
+    pub tag: Option<String>,
+    pub cwd_prefix: Option<String>, // The directory and everything under it
+    pub failed_only: Option<bool>, // Sessions with at least one non-zero exit
+}
+
 // Interaction tracking - captures the latent space of behavior
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Interaction {
@@ -57,14 +281,153 @@ pub struct Interaction {
     pub metadata: Option<String>, // JSON blob for rich context
 }
 
+// Outcome of enforcing max_db_bytes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EvictionReport {
+    pub at: String,
+    pub max_bytes: u64,
+    pub bytes_before: u64, // File size, before eviction
+    pub bytes_after: u64, // File size, after the VACUUM
+    pub evicted_session_ids: Vec<String>, // Oldest first
+    pub over_limit: bool, // Still too big: only favorites and the live session are left
+}
+
+// Outcome of collapse_repeats
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CollapseReport {
+    pub runs_collapsed: usize,
+    pub events_removed: usize,
+    pub bytes_saved: u64, // Event data no longer stored; the file shrinks after a VACUUM
+}
+
+// Timestamp that doesn't parse as RFC3339
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BadTimestamp {
+    pub table: String,
+    pub id: String,
+    pub column: String,
+    pub value: String,
+}
+
+// Outcome of validate_timestamps / normalize_timestamps
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TimestampReport {
+    pub checked: usize,
+    pub non_canonical: usize, // Parseable, but not UTC as Utc::now().to_rfc3339() writes it
+    pub rewritten: usize, // Always 0 when only validating
+    pub stale_ts_ms: usize, // Events whose ts_ms disagrees with their ts; recomputed when rewriting
+    pub unparseable: Vec<BadTimestamp>,
+}
+
+// Outcome of a WAL checkpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckpointReport {
+    pub at: String,
+    pub busy: bool, // Another connection blocked it from finishing
+    pub wal_pages: u64, // Pages in the WAL when it ran
+    pub pages_moved: u64, // Pages written back to the database file
+}
+
+// A session a prune pass found past retention, kept until its grace period is over
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingPrune {
+    pub session_id: String,
+    pub noticed_at: String, // RFC3339, when a prune pass first found it due
+    pub delete_after: String, // RFC3339; the first prune pass after this deletes it
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PruneOutcome {
+    pub deleted: usize,
+    pub newly_pending: Vec<PendingPrune>, // Found due by this pass, deleted by a later one
+}
+
+// Rows in one table whose session no longer exists
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrphanReport {
+    pub table: String,
+    pub rows: usize,
+    pub missing_session_ids: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IntegrityReport {
+    pub ok: bool,
+    pub messages: Vec<String>, // Output of PRAGMA integrity_check ("ok" when healthy)
+    pub quarantined_path: Option<String>, // Corrupt file set aside at startup, if any
+}
+
+// The connection, which close_if_idle may close and the next query reopens
+struct LazyConnection {
+    path: PathBuf,
+    conn: OnceCell<Connection>,
+}
+
+impl LazyConnection {
+    // Per-connection setup; the rest (WAL mode, schema) is stored in the file. The
+    // file must still exist: a reopen never creates an empty database in its place.
+    fn open(path: &Path) -> rusqlite::Result<Connection> {
+        let flags = OpenFlags::default() - OpenFlags::SQLITE_OPEN_CREATE;
+        let conn = Connection::open_with_flags(path, flags)?;
+        conn.pragma_update(None, "foreign_keys", true)?;
+        Ok(conn)
+    }
+
+    // The open connection, reopening it if closed. A failed reopen is returned to
+    // the caller and tried again on the next call.
+    fn get(&self) -> Result<&Connection> {
+        if let Some(conn) = self.conn.get() {
+            return Ok(conn);
+        }
+        let conn = Self::open(&self.path).context("Failed to reopen database")?;
+        Ok(self.conn.get_or_init(|| conn))
+    }
+
+    fn is_open(&self) -> bool {
+        self.conn.get().is_some()
+    }
+
+    fn close(&mut self) -> Result<()> {
+        if let Some(conn) = self.conn.take() {
+            if let Err((conn, e)) = conn.close() {
+                self.conn.set(conn).ok();
+                return Err(e.into());
+            }
+        }
+        Ok(())
+    }
+}
+
 pub struct Database {
-    conn: Connection,
+    connection: LazyConnection,
+    quarantined_path: Option<PathBuf>,
+    settings: HashMap<String, serde_json::Value>, // Stored settings, loaded at startup
+    persistence_enabled: bool, // Master privacy switch; when off, recording writes are skipped
+    mirror: Option<Mirror>, // Copies new sessions and events elsewhere, per the mirror setting
+    pending_metadata: HashMap<(String, &'static str), String>, // (session, column) -> latest value
+    written_since_size_check: Cell<u64>, // Event bytes added since enforce_size_limit last ran
+    written_since_checkpoint: Cell<u64>, // Event bytes added since checkpoint last ran
+    last_event_at: Cell<Instant>, // For close_if_idle
+    last_eviction: Option<EvictionReport>,
+    // Notified when the closed connection reopens or metadata is staged while it's
+    // closed, for maintenance threads that sleep until then
+    maintenance_signal: Option<Arc<Condvar>>,
 }
 
 impl Database {
     pub fn new() -> Result<Self> {
-        let db_path = Self::get_db_path()?;
-        let conn = Connection::open(db_path)?;
+        Self::open_at(Self::get_db_path()?)
+    }
+
+    // Open (creating and migrating as needed) the database file at `db_path`
+    fn open_at(db_path: PathBuf) -> Result<Self> {
+        let (conn, quarantined_path) = Self::open_checked(&db_path)?;
+        // SQLite's default depends on how it was built, so don't rely on it.
+        // Rows that already dangle are left to repair_orphans. Reopens set it again.
+        conn.pragma_update(None, "foreign_keys", true)?;
+        // Appends to the WAL are cheaper than rewriting pages for every event;
+        // spawn_checkpointer keeps the -wal file from growing without bound
+        conn.pragma_update(None, "journal_mode", "WAL")?;
 
         conn.execute(
             "CREATE TABLE IF NOT EXISTS sessions (
@@ -77,6 +440,21 @@ impl Database {
             [],
         )?;
 
+        // Terminal size at spawn (later sizes are logged as 'resize' events)
+        Self::ensure_column(&conn, "sessions", "cols", "INTEGER")?;
+        Self::ensure_column(&conn, "sessions", "rows", "INTEGER")?;
+        Self::ensure_column(&conn, "sessions", "env_file", "TEXT")?;
+        Self::ensure_column(&conn, "sessions", "env_snapshot", "TEXT")?; // JSON [[key, value], ...]
+        Self::ensure_column(&conn, "sessions", "is_template", "INTEGER NOT NULL DEFAULT 0")?;
+        Self::ensure_column(&conn, "sessions", "content_hash", "TEXT")?; // Output hash, once ended
+        Self::ensure_column(&conn, "sessions", "parent_session_id", "TEXT")?;
+        Self::ensure_column(&conn, "sessions", "dropped_writes", "INTEGER NOT NULL DEFAULT 0")?;
+        Self::ensure_column(&conn, "sessions", "last_dropped_at", "TEXT")?;
+        Self::ensure_column(&conn, "sessions", "launch_argv", "TEXT")?; // JSON SessionLaunchRecord
+        Self::ensure_column(&conn, "sessions", LAST_ACTIVITY_AT, "TEXT")?;
+        Self::ensure_column(&conn, "sessions", "favorite", "INTEGER NOT NULL DEFAULT 0")?;
+        Self::ensure_column(&conn, "sessions", "sparkline", "TEXT")?; // JSON byte counts, once ended
+
         conn.execute(
             "CREATE TABLE IF NOT EXISTS events (
                 id TEXT PRIMARY KEY,
@@ -95,6 +473,78 @@ impl Database {
             [],
         )?;
 
+        // Millisecond timestamps; derive them for rows written before the column existed
+        if Self::ensure_column(&conn, "events", "ts_ms", "INTEGER")? {
+            conn.execute(
+                "UPDATE events SET ts_ms = CAST((julianday(ts) - 2440587.5) * 86400000 AS INTEGER)
+                 WHERE ts_ms IS NULL",
+                [],
+            )?;
+        }
+
+        // Per-session sequence numbers; number existing rows by timestamp then insertion order.
+        // One pass: the numbering is computed once and joined in (UPDATE ... FROM, SQLite 3.33+,
+        // which the bundled library is), not re-run per row by a correlated subquery.
+        if Self::ensure_column(&conn, "events", "seq", "INTEGER")? {
+            conn.execute(
+                "UPDATE events SET seq = numbered.rn
+                 FROM (
+                     SELECT rowid AS r,
+                         ROW_NUMBER() OVER (PARTITION BY session_id ORDER BY ts, rowid) AS rn
+                     FROM events
+                 ) AS numbered
+                 WHERE numbered.r = events.rowid",
+                [],
+            )?;
+        }
+        Self::ensure_column(&conn, "events", "display", "TEXT")?;
+        // Copies of this event merged into it by collapse_repeats, counting itself
+        Self::ensure_column(&conn, "events", "repeat_count", "INTEGER NOT NULL DEFAULT 1")?;
+
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_events_session_seq
+             ON events(session_id, seq)",
+            [],
+        )?;
+
+        // Finding the output logged during a command's window as it ends
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_events_session_ts_ms
+             ON events(session_id, ts_ms)",
+            [],
+        )?;
+
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_events_repeats
+             ON events(session_id) WHERE repeat_count > 1",
+            [],
+        )?;
+
+        // Full-text index over event data, kept in sync by triggers. External content,
+        // so it can drift if rowids change (e.g. VACUUM); rebuild_search_index repairs it.
+        let fts_exists: bool = conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'events_fts')",
+            [],
+            |row| row.get(0),
+        )?;
+        conn.execute_batch(
+            "CREATE VIRTUAL TABLE IF NOT EXISTS events_fts
+                USING fts5(data, content='events', content_rowid='rowid');
+             CREATE TRIGGER IF NOT EXISTS events_fts_insert AFTER INSERT ON events BEGIN
+                INSERT INTO events_fts(rowid, data) VALUES (new.rowid, new.data);
+             END;
+             CREATE TRIGGER IF NOT EXISTS events_fts_delete AFTER DELETE ON events BEGIN
+                INSERT INTO events_fts(events_fts, rowid, data) VALUES ('delete', old.rowid, old.data);
+             END;
+             CREATE TRIGGER IF NOT EXISTS events_fts_update AFTER UPDATE OF data ON events BEGIN
+                INSERT INTO events_fts(events_fts, rowid, data) VALUES ('delete', old.rowid, old.data);
+                INSERT INTO events_fts(rowid, data) VALUES (new.rowid, new.data);
+             END;",
+        )?;
+        if !fts_exists {
+            conn.execute("INSERT INTO events_fts(events_fts) VALUES ('rebuild')", [])?;
+        }
+
         // Commands table for shell integration markers
         conn.execute(
             "CREATE TABLE IF NOT EXISTS commands (
@@ -115,6 +565,29 @@ impl Database {
             [],
         )?;
 
+        let output_first_added = Self::ensure_column(&conn, "commands", "output_first_seq", "INTEGER")?;
+        Self::ensure_column(&conn, "commands", "output_last_seq", "INTEGER")?;
+        if output_first_added {
+            conn.execute(
+                &format!("UPDATE commands SET {} WHERE ended_at IS NOT NULL", COMMAND_OUTPUT_RANGE),
+                [],
+            )?;
+        }
+
+        // Supports "slowest commands" ordering over finished commands
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_commands_duration
+             ON commands((ended_at - started_at)) WHERE ended_at IS NOT NULL",
+            [],
+        )?;
+
+        // Partial index over failures only, already in recency order
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_commands_failed
+             ON commands(started_at) WHERE exit_code != 0",
+            [],
+        )?;
+
         // Interactions table - captures behavioral patterns for UI evolution
         conn.execute(
             "CREATE TABLE IF NOT EXISTS interactions (
@@ -143,7 +616,311 @@ impl Database {
             [],
         )?;
 
-        Ok(Database { conn })
+        // Free-form session tags
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS session_tags (
+                session_id TEXT NOT NULL,
+                tag TEXT NOT NULL,
+                PRIMARY KEY(session_id, tag),
+                FOREIGN KEY(session_id) REFERENCES sessions(id)
+            )",
+            [],
+        )?;
+
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_session_tags_tag
+             ON session_tags(tag)",
+            [],
+        )?;
+
+        // Sessions a prune pass will delete once prune_grace_hours have passed
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS prune_notices (
+                session_id TEXT PRIMARY KEY,
+                noticed_at TEXT NOT NULL,
+                FOREIGN KEY(session_id) REFERENCES sessions(id)
+            )",
+            [],
+        )?;
+
+        // Small key/value store for app settings that survive restarts
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS settings (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            )",
+            [],
+        )?;
+
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+
+        let settings = Self::load_settings(&conn)?;
+        let mut db = Database {
+            connection: LazyConnection {
+                path: db_path,
+                conn: OnceCell::from(conn),
+            },
+            quarantined_path,
+            settings,
+            persistence_enabled: true,
+            mirror: None,
+            pending_metadata: HashMap::new(),
+            written_since_size_check: Cell::new(0),
+            written_since_checkpoint: Cell::new(0),
+            last_event_at: Cell::new(Instant::now()),
+            last_eviction: None,
+            maintenance_signal: None,
+        };
+        db.persistence_enabled = db.get_setting(settings::PERSISTENCE_ENABLED)?.as_bool().unwrap_or(true);
+
+        // An unreachable mirror shouldn't keep the app from starting
+        match Self::start_mirror(&db.get_setting(settings::MIRROR)?) {
+            Ok(mirror) => db.mirror = mirror,
+            Err(e) => eprintln!("Mirror disabled: {}", e),
+        }
+
+        Ok(db)
+    }
+
+    // Stored settings; rows with unknown keys or malformed values are skipped
+    fn load_settings(conn: &Connection) -> Result<HashMap<String, serde_json::Value>> {
+        let mut stmt = conn.prepare("SELECT key, value FROM settings")?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut settings = HashMap::new();
+        for (key, raw) in rows {
+            let parsed = serde_json::from_str(&raw)
+                .map_err(anyhow::Error::from)
+                .and_then(|value| settings::validate(&key, &value).map(|_| value));
+            match parsed {
+                Ok(value) => {
+                    settings.insert(key, value);
+                }
+                Err(e) => eprintln!("Ignoring setting {}: {}", key, e),
+            }
+        }
+        Ok(settings)
+    }
+
+    /// Current value of a known setting, falling back to its default
+    pub fn get_setting(&self, key: &str) -> Result<serde_json::Value> {
+        match self.settings.get(key) {
+            Some(value) => Ok(value.clone()),
+            None => settings::default_value(key),
+        }
+    }
+
+    pub fn get_settings(&self) -> Result<HashMap<String, serde_json::Value>> {
+        settings::KEYS
+            .iter()
+            .map(|key| Ok((key.to_string(), self.get_setting(key)?)))
+            .collect()
+    }
+
+    pub fn set_setting(&mut self, key: &str, value: serde_json::Value) -> Result<()> {
+        settings::validate(key, &value)?;
+        // Start the new mirror before saving, so a bad target is rejected
+        let new_mirror = match key {
+            settings::MIRROR => Some(Self::start_mirror(&value)?),
+            _ => None,
+        };
+        self.conn()?.execute(
+            "INSERT INTO settings (key, value) VALUES (?1, ?2)
+             ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+            params![key, value.to_string()],
+        )?;
+
+        if key == settings::PERSISTENCE_ENABLED {
+            self.persistence_enabled = value.as_bool().unwrap_or(true);
+        }
+        if let Some(mirror) = new_mirror {
+            // Dropping the old handle flushes its queue
+            self.mirror = mirror;
+        }
+        self.settings.insert(key.to_string(), value);
+        Ok(())
+    }
+
+    fn start_mirror(value: &serde_json::Value) -> Result<Option<Mirror>> {
+        mirror::Target::from_setting(value)?.map(Mirror::start).transpose()
+    }
+
+    fn readable_input(&self) -> bool {
+        self.settings
+            .get(settings::READABLE_INPUT)
+            .and_then(serde_json::Value::as_bool)
+            .unwrap_or(false)
+    }
+
+    fn input_log_max_bytes(&self) -> Option<usize> {
+        self.settings
+            .get(settings::INPUT_LOG_MAX_BYTES)
+            .and_then(serde_json::Value::as_u64)
+            .map(|n| n as usize)
+    }
+
+    // The first `max` bytes (to a char boundary) plus a note of how much was cut
+    fn truncate_for_log(data: &str, max: usize) -> Cow<'_, str> {
+        if data.len() <= max {
+            return Cow::Borrowed(data);
+        }
+        let mut end = max;
+        while !data.is_char_boundary(end) {
+            end -= 1;
+        }
+        Cow::Owned(format!("{}[... +{} bytes]", &data[..end], data.len() - end))
+    }
+
+    // While disabled, sessions, events, commands and interactions are not recorded;
+    // reads of existing history are unaffected
+    pub fn persistence_enabled(&self) -> bool {
+        self.persistence_enabled
+    }
+
+    // Open the database, setting aside a corrupt file and starting fresh if needed.
+    // Only corruption is quarantined; other failures (permissions, a full disk, a lock
+    // held elsewhere) are returned, since a fresh file wouldn't fix them.
+    fn open_checked(db_path: &Path) -> Result<(Connection, Option<PathBuf>)> {
+        let is_corrupt = |e: &rusqlite::Error| {
+            matches!(
+                e.sqlite_error_code(),
+                Some(rusqlite::ErrorCode::DatabaseCorrupt | rusqlite::ErrorCode::NotADatabase)
+            )
+        };
+        let problem = match Connection::open(db_path) {
+            Ok(conn) => match Self::integrity_messages(&conn) {
+                Ok(messages) if messages == ["ok"] => return Ok((conn, None)),
+                Ok(messages) => messages.join("; "),
+                Err(e) if e.downcast_ref().is_some_and(is_corrupt) => e.to_string(),
+                Err(e) => return Err(e.context("Failed to check database integrity")),
+            },
+            Err(e) if is_corrupt(&e) => e.to_string(),
+            Err(e) => return Err(anyhow::Error::new(e).context("Failed to open database")),
+        };
+
+        // Keep the corrupt file (and its WAL/SHM siblings) for manual recovery
+        let stamp = Utc::now().format("%Y%m%d%H%M%S");
+        let quarantined = db_path.with_extension(format!("db.corrupt-{}", stamp));
+        std::fs::rename(db_path, &quarantined)?;
+        for suffix in ["-wal", "-shm"] {
+            let sibling = PathBuf::from(format!("{}{}", db_path.display(), suffix));
+            if sibling.exists() {
+                let target = PathBuf::from(format!("{}{}", quarantined.display(), suffix));
+                std::fs::rename(&sibling, target).ok();
+            }
+        }
+
+        eprintln!(
+            "⚠️ Database failed integrity check ({}); moved to {} and starting fresh",
+            problem,
+            quarantined.display()
+        );
+
+        let conn = Connection::open(db_path)?;
+        Ok((conn, Some(quarantined)))
+    }
+
+    fn integrity_messages(conn: &Connection) -> Result<Vec<String>> {
+        let mut stmt = conn.prepare("PRAGMA integrity_check")?;
+        let messages = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        Ok(messages)
+    }
+
+    pub fn check_integrity(&self) -> Result<IntegrityReport> {
+        let messages = Self::integrity_messages(self.conn()?)?;
+        Ok(IntegrityReport {
+            ok: messages == ["ok"],
+            messages,
+            quarantined_path: self
+                .quarantined_path
+                .as_ref()
+                .map(|p| p.to_string_lossy().to_string()),
+        })
+    }
+
+    pub fn schema_version(&self) -> Result<i64> {
+        Ok(self.conn()?.pragma_query_value(None, "user_version", |row| row.get(0))?)
+    }
+
+    pub fn sqlite_version(&self) -> Result<String> {
+        Ok(self.conn()?.query_row("SELECT sqlite_version()", [], |row| row.get(0))?)
+    }
+
+    // Full-text search needs FTS5 compiled into SQLite and the events index built
+    pub fn fts_available(&self) -> Result<bool> {
+        Ok(self.conn()?.query_row(
+            "SELECT sqlite_compileoption_used('ENABLE_FTS5')
+                 AND EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'events_fts')",
+            [],
+            |row| row.get(0),
+        )?)
+    }
+
+    // Orphaned rows per child table, including tables with none
+    pub fn check_referential_integrity(&self) -> Result<Vec<OrphanReport>> {
+        Self::find_orphans(self.conn()?)
+    }
+
+    // List orphans, or with delete, remove them all in one transaction.
+    // Returns what was found (and deleted).
+    pub fn repair_orphans(&self, delete: bool) -> Result<Vec<OrphanReport>> {
+        if !delete {
+            return Self::find_orphans(self.conn()?);
+        }
+        let tx = self.conn()?.unchecked_transaction()?;
+        let reports = Self::find_orphans(&tx)?;
+        for report in reports.iter().filter(|r| r.rows > 0) {
+            tx.execute(
+                &format!("DELETE FROM {} WHERE session_id NOT IN (SELECT id FROM sessions)", report.table),
+                [],
+            )?;
+        }
+        tx.commit()?;
+        Ok(reports)
+    }
+
+    fn find_orphans(conn: &Connection) -> Result<Vec<OrphanReport>> {
+        let mut reports = Vec::new();
+        for table in SESSION_CHILD_TABLES {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT session_id, COUNT(*) FROM {}
+                 WHERE session_id NOT IN (SELECT id FROM sessions)
+                 GROUP BY session_id ORDER BY session_id",
+                table
+            ))?;
+            let missing = stmt
+                .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, usize>(1)?)))?
+                .collect::<Result<Vec<_>, _>>()?;
+            reports.push(OrphanReport {
+                table: table.to_string(),
+                rows: missing.iter().map(|(_, count)| count).sum(),
+                missing_session_ids: missing.into_iter().map(|(id, _)| id).collect(),
+            });
+        }
+        Ok(reports)
+    }
+
+    // Add a column to an existing table if an older database predates it.
+    // Returns true when the column was just added.
+    fn ensure_column(conn: &Connection, table: &str, column: &str, decl: &str) -> Result<bool> {
+        let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+        let exists = stmt
+            .query_map([], |row| row.get::<_, String>(1))?
+            .collect::<Result<Vec<_>, _>>()?
+            .iter()
+            .any(|name| name == column);
+
+        if !exists {
+            conn.execute(
+                &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl),
+                [],
+            )?;
+        }
+        Ok(!exists)
     }
 
     fn get_db_path() -> Result<PathBuf> {
@@ -153,222 +930,2123 @@ impl Database {
         Ok(vibe_dir.join("sessions.db"))
     }
 
-    pub fn create_session(&self, cwd: &str, shell: &str) -> Result<Session> {
+    pub fn create_session(
+        &self,
+        cwd: &str,
+        shell: &str,
+        cols: u16,
+        rows: u16,
+        parent_session_id: Option<&str>,
+    ) -> Result<Session> {
         let session = Session {
             id: Uuid::new_v4().to_string(),
             started_at: Utc::now().to_rfc3339(),
             ended_at: None,
             cwd: cwd.to_string(),
             shell: shell.to_string(),
+            cols: Some(cols),
+            rows: Some(rows),
+            env_file: None,
+            is_template: false,
+            parent_session_id: parent_session_id.map(str::to_string),
+            favorite: false,
         };
 
-        self.conn.execute(
-            "INSERT INTO sessions (id, started_at, cwd, shell) VALUES (?1, ?2, ?3, ?4)",
-            params![&session.id, &session.started_at, &session.cwd, &session.shell],
+        if !self.persistence_enabled {
+            return Ok(session);
+        }
+
+        self.conn()?.execute(
+            "INSERT INTO sessions (id, started_at, cwd, shell, cols, rows, parent_session_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                &session.id,
+                &session.started_at,
+                &session.cwd,
+                &session.shell,
+                cols,
+                rows,
+                &session.parent_session_id
+            ],
         )?;
 
+        if let Some(ref mirror) = self.mirror {
+            mirror.session(&session);
+        }
         Ok(session)
     }
 
-    pub fn end_session(&self, session_id: &str) -> Result<()> {
-        let ended_at = Utc::now().to_rfc3339();
-        self.conn.execute(
-            "UPDATE sessions SET ended_at = ?1 WHERE id = ?2",
-            params![ended_at, session_id],
+    // A session started while recording was off has no row, so once recording is
+    // back on its events would break the foreign key; give it one from now on
+    pub fn adopt_session(&self, session_id: &str, shell: &str) -> Result<()> {
+        if !self.persistence_enabled {
+            return Ok(());
+        }
+        self.conn()?.execute(
+            "INSERT OR IGNORE INTO sessions (id, started_at, cwd, shell) VALUES (?1, ?2, '', ?3)",
+            params![session_id, Utc::now().to_rfc3339(), shell],
         )?;
         Ok(())
     }
 
-    pub fn add_event(&self, session_id: &str, kind: &str, data: &str) -> Result<()> {
-        let event = Event {
+    // Store an external recording as an ended session, its events timed from
+    // `started_at`. Import is explicit, so it isn't subject to persistence_enabled.
+    pub fn import_recording(
+        &self,
+        recording: &Recording,
+        started_at: chrono::DateTime<Utc>,
+        cwd: &str,
+        shell: &str,
+    ) -> Result<Session> {
+        // Offsets come from the file, so a time past what chrono can represent is an error
+        let at = |offset_ms: i64| -> Result<chrono::DateTime<Utc>> {
+            chrono::TimeDelta::try_milliseconds(offset_ms)
+                .filter(|offset| *offset >= chrono::TimeDelta::zero())
+                .and_then(|offset| started_at.checked_add_signed(offset))
+                .ok_or_else(|| anyhow::anyhow!("Event time {} ms is out of range", offset_ms))
+        };
+        let last_offset = recording.events.iter().map(|e| e.offset_ms).max().unwrap_or(0);
+        let session = Session {
             id: Uuid::new_v4().to_string(),
-            session_id: session_id.to_string(),
-            ts: Utc::now().to_rfc3339(),
-            kind: kind.to_string(),
-            data: data.to_string(),
+            started_at: started_at.to_rfc3339(),
+            ended_at: Some(at(last_offset)?.to_rfc3339()),
+            cwd: cwd.to_string(),
+            shell: shell.to_string(),
+            cols: Some(recording.cols),
+            rows: Some(recording.rows),
+            env_file: None,
+            is_template: false,
+            parent_session_id: None,
+            favorite: false,
         };
 
-        self.conn.execute(
-            "INSERT INTO events (id, session_id, ts, kind, data) VALUES (?1, ?2, ?3, ?4, ?5)",
-            params![&event.id, &event.session_id, &event.ts, &event.kind, &event.data],
+        let tx = self.conn()?.unchecked_transaction()?;
+        tx.execute(
+            "INSERT INTO sessions (id, started_at, ended_at, cwd, shell, cols, rows)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                &session.id,
+                &session.started_at,
+                &session.ended_at,
+                &session.cwd,
+                &session.shell,
+                recording.cols,
+                recording.rows
+            ],
         )?;
-
-        Ok(())
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO events (id, session_id, ts, kind, data, ts_ms, seq, display)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            )?;
+            for (seq, event) in recording.events.iter().enumerate() {
+                let ts = at(event.offset_ms)?;
+                let display = (event.kind == "user_in" && self.readable_input()).then(|| vt::describe_input(&event.data));
+                stmt.execute(params![
+                    Uuid::new_v4().to_string(),
+                    &session.id,
+                    ts.to_rfc3339(),
+                    event.kind,
+                    &event.data,
+                    ts.timestamp_millis(),
+                    seq as i64 + 1,
+                    display
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(session)
     }
 
-    pub fn get_session(&self, session_id: &str) -> Result<Option<Session>> {
+    pub fn end_session(&mut self, session_id: &str) -> Result<()> {
+        if !self.persistence_enabled {
+            return Ok(());
+        }
+        // Best effort: staged metadata stays queued for the next flush, and a session
+        // that never gets its ended_at would look live forever
+        if let Err(e) = self.flush_session_metadata() {
+            eprintln!("Failed to save session metadata: {}", e);
+        }
+        let ended_at = Utc::now().to_rfc3339();
+        self.conn()?.execute(
+            "UPDATE sessions SET ended_at = ?1 WHERE id = ?2",
+            params![ended_at, session_id],
+        )?;
+
+        if let Some(ref mirror) = self.mirror {
+            if let Some(session) = self.get_session(session_id)? {
+                mirror.session(&session);
+            }
+        }
+        Ok(())
+    }
+
+    /// Stage a new value for a metadata column; only the latest value per session and
+    /// column is kept until the next flush
+    pub fn queue_session_metadata(&mut self, session_id: &str, column: &'static str, value: String) {
+        debug_assert!(METADATA_COLUMNS.contains(&column));
+        if self.persistence_enabled {
+            if self.pending_metadata.is_empty() && !self.connection.is_open() {
+                self.signal_maintenance();
+            }
+            self.pending_metadata.insert((session_id.to_string(), column), value);
+        }
+    }
+
+    /// Whether metadata is staged for the next flush
+    pub fn has_pending_metadata(&self) -> bool {
+        !self.pending_metadata.is_empty()
+    }
+
+    /// Write all staged metadata in one transaction; returns how many values were written
+    pub fn flush_session_metadata(&mut self) -> Result<usize> {
+        if self.pending_metadata.is_empty() {
+            return Ok(0);
+        }
+        let pending = std::mem::take(&mut self.pending_metadata);
+
+        let write = || -> Result<()> {
+            let tx = self.conn()?.unchecked_transaction()?;
+            for ((session_id, column), value) in &pending {
+                if METADATA_COLUMNS.contains(column) {
+                    tx.prepare_cached(&format!("UPDATE sessions SET {} = ?1 WHERE id = ?2", column))?
+                        .execute(params![value, session_id])?;
+                }
+            }
+            tx.commit()?;
+            Ok(())
+        };
+        if let Err(e) = write() {
+            // Nothing was written; keep it all for the next flush
+            self.pending_metadata = pending;
+            return Err(e);
+        }
+        Ok(pending.len())
+    }
+
+    // Record the .env file and the variables it contributed, so templates can reuse them
+    pub fn set_session_env(&self, session_id: &str, env_file: &str, env: &[(String, String)]) -> Result<()> {
+        if !self.persistence_enabled {
+            return Ok(());
+        }
+        self.conn()?.execute(
+            "UPDATE sessions SET env_file = ?1, env_snapshot = ?2 WHERE id = ?3",
+            params![env_file, serde_json::to_string(env)?, session_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_session_env(&self, session_id: &str) -> Result<Vec<(String, String)>> {
+        let snapshot: Option<String> = self
+            .conn()?
+            .query_row(
+                "SELECT env_snapshot FROM sessions WHERE id = ?1",
+                params![session_id],
+                |row| row.get(0),
+            )
+            .optional()?
+            .flatten();
+
+        match snapshot {
+            Some(json) => Ok(serde_json::from_str(&json)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    pub fn set_session_template(&self, session_id: &str, is_template: bool) -> Result<()> {
+        let updated = self.conn()?.execute(
+            "UPDATE sessions SET is_template = ?1 WHERE id = ?2",
+            params![is_template, session_id],
+        )?;
+        if updated == 0 {
+            anyhow::bail!("Session not found");
+        }
+        Ok(())
+    }
+
+    pub fn set_favorite(&self, session_id: &str, favorite: bool) -> Result<()> {
+        let updated = self.conn()?.execute(
+            "UPDATE sessions SET favorite = ?1 WHERE id = ?2",
+            params![favorite, session_id],
+        )?;
+        if updated == 0 {
+            anyhow::bail!("Session not found");
+        }
+        // Favorites aren't pruned, and unfavoriting later starts a fresh grace period
+        if favorite {
+            self.clear_prune_notice(session_id)?;
+        }
+        Ok(())
+    }
+
+    pub fn get_favorite_sessions(&self) -> Result<Vec<Session>> {
+        let mut stmt = self.conn()?.prepare(&format!(
+            "SELECT {} FROM sessions WHERE favorite = 1 ORDER BY started_at DESC",
+            SESSION_COLUMNS
+        ))?;
+
+        let sessions = stmt
+            .query_map([], Self::session_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(sessions)
+    }
+
+    // Delete sessions last active more than `retention_days` ago, except favorites
+    // and `keep_session_id` (the live one). With a `grace` period a session is only
+    // noticed the first time it's due, and deleted by a pass at least `grace` later;
+    // favoriting or exporting it in between drops the notice.
+    pub fn prune_sessions(
+        &self,
+        retention_days: u64,
+        keep_session_id: Option<&str>,
+        grace: Option<chrono::Duration>,
+    ) -> Result<PruneOutcome> {
+        let cutoff = i64::try_from(retention_days)
+            .ok()
+            .and_then(chrono::TimeDelta::try_days)
+            .and_then(|retention| Utc::now().checked_sub_signed(retention))
+            .ok_or_else(|| anyhow::anyhow!("Retention of {} days is out of range", retention_days))?
+            .to_rfc3339();
+        let ids: Vec<String> = {
+            let mut stmt = self.conn()?.prepare(
+                "SELECT id FROM sessions
+                 WHERE favorite = 0
+                   AND COALESCE(ended_at, last_activity_at, started_at) < ?1
+                   AND (?2 IS NULL OR id != ?2)",
+            )?;
+            let ids = stmt
+                .query_map(params![cutoff, keep_session_id], |row| row.get(0))?
+                .collect::<Result<Vec<_>, _>>()?;
+            ids
+        };
+
+        let Some(grace) = grace else {
+            let deleted = self.delete_sessions(&ids)?;
+            return Ok(PruneOutcome { deleted, newly_pending: Vec::new() });
+        };
+
+        let now = Utc::now();
+        let delete_after = now
+            .checked_add_signed(grace)
+            .ok_or_else(|| anyhow::anyhow!("Grace period is out of range"))?
+            .to_rfc3339();
+        let noticed: HashMap<String, String> = {
+            let mut stmt = self.conn()?.prepare("SELECT session_id, noticed_at FROM prune_notices")?;
+            let noticed = stmt
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<Result<HashMap<_, _>, _>>()?;
+            noticed
+        };
+
+        let tx = self.conn()?.unchecked_transaction()?;
+        // A notice for a session that's no longer due (retention raised, or it's the
+        // live one again) would otherwise cut a later grace period short
+        for session_id in noticed.keys().filter(|id| !ids.contains(id)) {
+            tx.execute("DELETE FROM prune_notices WHERE session_id = ?1", params![session_id])?;
+        }
+        let mut due = Vec::new();
+        let mut newly_pending = Vec::new();
+        for id in ids {
+            match noticed.get(&id) {
+                Some(noticed_at) => {
+                    let elapsed = chrono::DateTime::parse_from_rfc3339(noticed_at)
+                        .map(|at| now.signed_duration_since(at))
+                        .unwrap_or(grace);
+                    if elapsed >= grace {
+                        due.push(id);
+                    }
+                }
+                None => {
+                    tx.execute(
+                        "INSERT INTO prune_notices (session_id, noticed_at) VALUES (?1, ?2)",
+                        params![id, now.to_rfc3339()],
+                    )?;
+                    newly_pending.push(PendingPrune {
+                        session_id: id,
+                        noticed_at: now.to_rfc3339(),
+                        delete_after: delete_after.clone(),
+                    });
+                }
+            }
+        }
+        tx.commit()?;
+
+        let deleted = self.delete_sessions(&due)?;
+        Ok(PruneOutcome { deleted, newly_pending })
+    }
+
+    /// Sessions waiting out their grace period, oldest notice first
+    pub fn get_pending_prunes(&self, grace: chrono::Duration) -> Result<Vec<PendingPrune>> {
+        let mut stmt = self.conn()?.prepare(
+            "SELECT p.session_id, p.noticed_at FROM prune_notices p
+             JOIN sessions s ON s.id = p.session_id
+             WHERE s.favorite = 0
+             ORDER BY p.noticed_at ASC",
+        )?;
+        let pending = stmt
+            .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+            .map(|row| {
+                let (session_id, noticed_at) = row?;
+                let delete_after = chrono::DateTime::parse_from_rfc3339(&noticed_at)
+                    .ok()
+                    .and_then(|at| at.checked_add_signed(grace))
+                    .map(|at| at.to_rfc3339())
+                    .unwrap_or_default();
+                Ok(PendingPrune { session_id, noticed_at, delete_after })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(pending)
+    }
+
+    /// Spare a session from a pending prune, e.g. after it was exported
+    pub fn clear_prune_notice(&self, session_id: &str) -> Result<()> {
+        self.conn()?.execute("DELETE FROM prune_notices WHERE session_id = ?1", params![session_id])?;
+        Ok(())
+    }
+
+    // Bytes in pages holding data; deleted rows free pages without shrinking the file
+    fn used_bytes(&self) -> Result<u64> {
+        let (page_count, freelist_count, page_size): (i64, i64, i64) = self.conn()?.query_row(
+            "SELECT * FROM pragma_page_count(), pragma_freelist_count(), pragma_page_size()",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?;
+        Ok(((page_count - freelist_count) * page_size) as u64)
+    }
+
+    fn file_bytes(&self) -> Result<u64> {
+        let (page_count, page_size): (i64, i64) = self.conn()?.query_row(
+            "SELECT * FROM pragma_page_count(), pragma_page_size()",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        Ok((page_count * page_size) as u64)
+    }
+
+    /// Event bytes written since the size limit was last enforced
+    pub fn written_since_size_check(&self) -> u64 {
+        self.written_since_size_check.get()
+    }
+
+    /// Event bytes written since the WAL was last checkpointed
+    pub fn written_since_checkpoint(&self) -> u64 {
+        self.written_since_checkpoint.get()
+    }
+
+    // Copy the WAL into the database file and truncate it to zero bytes
+    pub fn checkpoint(&self) -> Result<CheckpointReport> {
+        self.written_since_checkpoint.set(0);
+        let checkpoint = |mode: &str| -> Result<(i64, i64, i64)> {
+            Ok(self.conn()?.query_row(&format!("PRAGMA wal_checkpoint({})", mode), [], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })?)
+        };
+        // TRUNCATE reports the emptied WAL, so the pages are counted by a PASSIVE pass first
+        let (_, wal_pages, pages_moved) = checkpoint("PASSIVE")?;
+        let (busy, _, _) = checkpoint("TRUNCATE")?;
+        Ok(CheckpointReport {
+            at: Utc::now().to_rfc3339(),
+            busy: busy != 0,
+            // -1 when the database isn't in WAL mode
+            wal_pages: wal_pages.max(0) as u64,
+            pages_moved: pages_moved.max(0) as u64,
+        })
+    }
+
+    // The connection for a query, reopened if close_if_idle closed it
+    fn conn(&self) -> Result<&Connection> {
+        if self.connection.is_open() {
+            return self.connection.get();
+        }
+        let conn = self.connection.get()?;
+        self.signal_maintenance();
+        Ok(conn)
+    }
+
+    /// Condvar to notify when maintenance threads sleeping on the closed connection
+    /// have work again; it must be paired with the mutex this Database is kept in
+    pub fn set_maintenance_signal(&mut self, signal: Arc<Condvar>) {
+        self.maintenance_signal = Some(signal);
+    }
+
+    fn signal_maintenance(&self) {
+        if let Some(ref signal) = self.maintenance_signal {
+            signal.notify_all();
+        }
+    }
+
+    /// False while close_if_idle has the connection closed; any query reopens it
+    pub fn connection_open(&self) -> bool {
+        self.connection.is_open()
+    }
+
+    /// Time since an event was last added
+    pub fn idle_for(&self) -> Duration {
+        self.last_event_at.get().elapsed()
+    }
+
+    /// Once no event has been added for `idle`, write out staged metadata, fold the WAL
+    /// into the database file and close the connection. Returns whether it closed.
+    pub fn close_if_idle(&mut self, idle: Duration) -> Result<bool> {
+        if !self.connection.is_open() || self.idle_for() < idle {
+            return Ok(false);
+        }
+        self.flush_session_metadata()?;
+        self.checkpoint()?;
+        self.connection.close()?;
+        Ok(true)
+    }
+
+    pub fn last_eviction(&self) -> Option<EvictionReport> {
+        self.last_eviction.clone()
+    }
+
+    // Delete whole sessions, oldest first, until the data fits in max_db_bytes, then VACUUM.
+    // Favorites and `keep_session_id` (the live one) are never evicted. None when no
+    // limit is set or the database is within it.
+    // VACUUM rewrites the whole file, which can take seconds on a large database, and
+    // every caller waiting on the db lock waits for it. It runs here rather than on a
+    // separate connection on purpose: VACUUM blocks writers on any connection, and an
+    // event write that waits is better than one that fails busy and is dropped.
+    pub fn enforce_size_limit(&mut self, keep_session_id: Option<&str>) -> Result<Option<EvictionReport>> {
+        self.written_since_size_check.set(0);
+        let Some(max_bytes) = self.get_setting(settings::MAX_DB_BYTES)?.as_u64() else {
+            return Ok(None);
+        };
+        if self.used_bytes()? <= max_bytes {
+            return Ok(None);
+        }
+
+        let bytes_before = self.file_bytes()?;
+        let mut evicted = Vec::new();
+        let mut over_limit = false;
+        while self.used_bytes()? > max_bytes {
+            let oldest: Option<String> = self
+                .conn()?
+                .query_row(
+                    "SELECT id FROM sessions
+                     WHERE favorite = 0 AND (?1 IS NULL OR id != ?1)
+                     ORDER BY started_at ASC LIMIT 1",
+                    params![keep_session_id],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            let Some(id) = oldest else {
+                over_limit = true;
+                break;
+            };
+            self.delete_sessions(std::slice::from_ref(&id))?;
+            evicted.push(id);
+        }
+
+        if !evicted.is_empty() {
+            self.conn()?.execute_batch("VACUUM")?;
+            // VACUUM can renumber event rowids, which the search index is keyed on
+            self.rebuild_search_index()?;
+        }
+
+        let report = EvictionReport {
+            at: Utc::now().to_rfc3339(),
+            max_bytes,
+            bytes_before,
+            bytes_after: self.file_bytes()?,
+            evicted_session_ids: evicted,
+            over_limit,
+        };
+        self.last_eviction = Some(report.clone());
+        Ok(Some(report))
+    }
+
+    pub fn get_templates(&self) -> Result<Vec<Session>> {
+        let mut stmt = self.conn()?.prepare(&format!(
+            "SELECT {} FROM sessions WHERE is_template = 1 ORDER BY started_at DESC",
+            SESSION_COLUMNS
+        ))?;
+
+        let sessions = stmt
+            .query_map([], Self::session_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(sessions)
+    }
+
+    pub fn add_event(&self, session_id: &str, kind: &str, data: &str) -> Result<()> {
+        if !self.persistence_enabled {
+            return Ok(());
+        }
+        let now = Utc::now();
+        // Only the log is capped; the PTY already got the whole input
+        let data = match self.input_log_max_bytes() {
+            Some(max) if kind == "user_in" => Self::truncate_for_log(data, max),
+            _ => Cow::Borrowed(data),
+        };
+        let event = Event {
+            id: Uuid::new_v4().to_string(),
+            session_id: session_id.to_string(),
+            ts: now.to_rfc3339(),
+            kind: kind.to_string(),
+            data: data.to_string(),
+            ts_ms: Some(now.timestamp_millis()),
+            seq: None, // Assigned by the insert
+            // data stays exactly as typed for replay
+            display: (kind == "user_in" && self.readable_input()).then(|| vt::describe_input(&data)),
+        };
+
+        self.last_event_at.set(Instant::now());
+        // Hot path (every output chunk): reuse the prepared statement
+        let mut stmt = self.conn()?.prepare_cached(
+            "INSERT INTO events (id, session_id, ts, kind, data, ts_ms, seq, display)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6,
+                     (SELECT COALESCE(MAX(seq), 0) + 1 FROM events WHERE session_id = ?2), ?7)",
+        )?;
+        let inserted = stmt.execute(params![
+            &event.id,
+            &event.session_id,
+            &event.ts,
+            &event.kind,
+            &event.data,
+            event.ts_ms,
+            &event.display
+        ]);
+        if let Err(e) = inserted {
+            // Best effort: whatever broke the insert (e.g. a full disk) may block this too
+            self.record_dropped_write(session_id).ok();
+            return Err(e.into());
+        }
+
+        self.written_since_size_check
+            .set(self.written_since_size_check.get() + event.data.len() as u64);
+        self.written_since_checkpoint
+            .set(self.written_since_checkpoint.get() + event.data.len() as u64);
+
+        if let Some(ref mirror) = self.mirror {
+            mirror.event(&event);
+        }
+        Ok(())
+    }
+
+    // Mark a session's recording as degraded after a lost event write
+    fn record_dropped_write(&self, session_id: &str) -> Result<()> {
+        self.conn()?.execute(
+            "UPDATE sessions SET dropped_writes = dropped_writes + 1, last_dropped_at = ?1 WHERE id = ?2",
+            params![Utc::now().to_rfc3339(), session_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_session_launch(&self, session_id: &str, launch: &SessionLaunchRecord) -> Result<()> {
+        if !self.persistence_enabled {
+            return Ok(());
+        }
+        self.conn()?.execute(
+            "UPDATE sessions SET launch_argv = ?1 WHERE id = ?2",
+            params![serde_json::to_string(launch)?, session_id],
+        )?;
+        Ok(())
+    }
+
+    // None for sessions recorded before launches were tracked
+    pub fn get_session_launch(&self, session_id: &str) -> Result<Option<SessionLaunchRecord>> {
+        let launch: Option<String> = self
+            .conn()?
+            .query_row(
+                "SELECT launch_argv FROM sessions WHERE id = ?1",
+                params![session_id],
+                |row| row.get(0),
+            )
+            .optional()?
+            .ok_or_else(|| anyhow::anyhow!("Session not found"))?;
+
+        match launch {
+            Some(json) => Ok(Some(serde_json::from_str(&json)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_recording_status(&self, session_id: &str) -> Result<RecordingStatus> {
+        let (dropped_writes, last_dropped_at): (i64, Option<String>) = self
+            .conn()?
+            .query_row(
+                "SELECT dropped_writes, last_dropped_at FROM sessions WHERE id = ?1",
+                params![session_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?
+            .ok_or_else(|| anyhow::anyhow!("Session not found"))?;
+
+        Ok(RecordingStatus {
+            degraded: dropped_writes > 0,
+            dropped_writes,
+            last_dropped_at,
+        })
+    }
+
+    pub fn acknowledge_recording_degradation(&self, session_id: &str) -> Result<()> {
+        let updated = self.conn()?.execute(
+            "UPDATE sessions SET dropped_writes = 0, last_dropped_at = NULL WHERE id = ?1",
+            params![session_id],
+        )?;
+        if updated == 0 {
+            anyhow::bail!("Session not found");
+        }
+        Ok(())
+    }
+
+    pub fn get_session(&self, session_id: &str) -> Result<Option<Session>> {
         let mut stmt = self
-            .conn
-            .prepare("SELECT id, started_at, ended_at, cwd, shell FROM sessions WHERE id = ?1")?;
+            .conn()?
+            .prepare_cached(&format!("SELECT {} FROM sessions WHERE id = ?1", SESSION_COLUMNS))?;
+
+        let mut rows = stmt.query(params![session_id])?;
+
+        if let Some(row) = rows.next()? {
+            Ok(Some(Self::session_from_row(row)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn get_events(&self, session_id: &str) -> Result<Vec<Event>> {
+        let mut stmt = self.conn()?.prepare_cached(&format!(
+            "SELECT {} FROM events WHERE session_id = ?1 ORDER BY seq ASC",
+            EVENT_COLUMNS
+        ))?;
+
+        let events = stmt
+            .query_map(params![session_id], Self::event_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.expand_repeats(session_id, events)
+    }
+
+    // Undo collapse_repeats for a read: each merged copy comes back as a clone of the
+    // stored event, id and timestamp included
+    fn expand_repeats(&self, session_id: &str, events: Vec<Event>) -> Result<Vec<Event>> {
+        let repeats: HashMap<String, usize> = {
+            let mut stmt = self
+                .conn()?
+                .prepare_cached("SELECT id, repeat_count FROM events WHERE session_id = ?1 AND repeat_count > 1")?;
+            let repeats = stmt
+                .query_map(params![session_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<Result<HashMap<_, _>, _>>()?;
+            repeats
+        };
+        if repeats.is_empty() {
+            return Ok(events);
+        }
+
+        let mut expanded = Vec::with_capacity(events.len());
+        for event in events {
+            let copies = repeats.get(&event.id).copied().unwrap_or(1);
+            expanded.extend(std::iter::repeat_n(event, copies));
+        }
+        Ok(expanded)
+    }
+
+    // Merge each run of byte-identical consecutive pty_out events (e.g. a spinner
+    // redrawn per chunk) into its first event, counting the copies in repeat_count.
+    // Reads expand them again; only the copies' own timestamps are lost.
+    pub fn collapse_repeats(&self, session_id: &str) -> Result<CollapseReport> {
+        if self.get_session(session_id)?.is_none() {
+            anyhow::bail!("Session not found");
+        }
+        let rows: Vec<(String, String, String, i64)> = {
+            let mut stmt = self.conn()?.prepare(
+                "SELECT id, kind, data, repeat_count FROM events WHERE session_id = ?1 ORDER BY seq ASC",
+            )?;
+            let rows = stmt
+                .query_map(params![session_id], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))?
+                .collect::<Result<Vec<_>, _>>()?;
+            rows
+        };
+
+        let tx = self.conn()?.unchecked_transaction()?;
+        let mut report = CollapseReport::default();
+        // First event of the current run: id, data, stored count, count after merging
+        let mut run: Option<(String, String, i64, i64)> = None;
+        let finish_run = |run: Option<(String, String, i64, i64)>, report: &mut CollapseReport| -> Result<()> {
+            if let Some((id, _, stored, merged)) = run {
+                if merged != stored {
+                    tx.execute("UPDATE events SET repeat_count = ?1 WHERE id = ?2", params![merged, id])?;
+                    report.runs_collapsed += 1;
+                }
+            }
+            Ok(())
+        };
+        for (id, kind, data, count) in rows {
+            if let Some((_, ref run_data, _, ref mut merged)) = run {
+                if kind == "pty_out" && *run_data == data {
+                    *merged += count;
+                    tx.execute("DELETE FROM events WHERE id = ?1", params![id])?;
+                    report.events_removed += 1;
+                    report.bytes_saved += data.len() as u64;
+                    continue;
+                }
+            }
+            finish_run(run.take(), &mut report)?;
+            if kind == "pty_out" {
+                run = Some((id, data, count, count));
+            }
+        }
+        finish_run(run, &mut report)?;
+        Self::clear_cached_summaries(&tx, Some(session_id))?;
+        tx.commit()?;
+        Ok(report)
+    }
+
+    fn command_from_row(row: &Row) -> rusqlite::Result<Command> {
+        Ok(Command {
+            id: row.get(0)?,
+            session_id: row.get(1)?,
+            input: row.get(2)?,
+            exit_code: row.get(3)?,
+            started_at: row.get(4)?,
+            ended_at: row.get(5)?,
+            output_first_seq: row.get(6)?,
+            output_last_seq: row.get(7)?,
+        })
+    }
+
+    fn event_from_row(row: &Row) -> rusqlite::Result<Event> {
+        Ok(Event {
+            id: row.get(0)?,
+            session_id: row.get(1)?,
+            ts: row.get(2)?,
+            kind: row.get(3)?,
+            data: row.get(4)?,
+            ts_ms: row.get(5)?,
+            seq: row.get(6)?,
+            display: row.get(7)?,
+        })
+    }
+
+    pub fn get_events_of_kind(&self, session_id: &str, kind: &str) -> Result<Vec<Event>> {
+        let mut stmt = self.conn()?.prepare(&format!(
+            "SELECT {} FROM events WHERE session_id = ?1 AND kind = ?2 ORDER BY seq ASC",
+            EVENT_COLUMNS
+        ))?;
+
+        let events = stmt
+            .query_map(params![session_id, kind], Self::event_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.expand_repeats(session_id, events)
+    }
+
+    // Concatenated pty_out data from one event to another, inclusive, in seq order
+    pub fn get_output_slice(&self, session_id: &str, from_event_id: &str, to_event_id: &str) -> Result<Vec<u8>> {
+        let seq_of = |event_id: &str| -> Result<i64> {
+            self.conn()?
+                .query_row(
+                    "SELECT seq FROM events WHERE id = ?1 AND session_id = ?2",
+                    params![event_id, session_id],
+                    |row| row.get(0),
+                )
+                .optional()?
+                .ok_or_else(|| anyhow::anyhow!("Event {} not found in session", event_id))
+        };
+        let (from_seq, to_seq) = (seq_of(from_event_id)?, seq_of(to_event_id)?);
+        if from_seq > to_seq {
+            anyhow::bail!("Start event comes after end event");
+        }
+
+        let mut stmt = self.conn()?.prepare(
+            "SELECT data, repeat_count FROM events
+             WHERE session_id = ?1 AND kind = 'pty_out' AND seq BETWEEN ?2 AND ?3
+             ORDER BY seq ASC",
+        )?;
+        let mut rows = stmt.query(params![session_id, from_seq, to_seq])?;
+
+        let mut bytes = Vec::new();
+        while let Some(row) = rows.next()? {
+            let data: String = row.get(0)?;
+            let copies: usize = row.get(1)?;
+            for _ in 0..copies {
+                bytes.extend_from_slice(data.as_bytes());
+            }
+        }
+        Ok(bytes)
+    }
+
+    pub fn get_export_stats(&self, session_id: &str) -> Result<ExportStats> {
+        let mut stmt = self.conn()?.prepare(
+            "SELECT kind, SUM(repeat_count),
+                    COALESCE(SUM(LENGTH(CAST(data AS BLOB)) * repeat_count), 0),
+                    COALESCE(SUM((LENGTH(data) - LENGTH(REPLACE(data, char(27), ''))) * repeat_count), 0),
+                    COALESCE(SUM((LENGTH(data) - LENGTH(REPLACE(REPLACE(data, char(13), ''), char(10), ''))) * repeat_count), 0)
+             FROM events WHERE session_id = ?1
+             GROUP BY kind",
+        )?;
+        let by_kind = stmt
+            .query_map(params![session_id], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    EventKindStats {
+                        count: row.get::<_, i64>(1)? as u64,
+                        bytes: row.get::<_, i64>(2)? as u64,
+                        escapes: row.get::<_, i64>(3)? as u64,
+                        line_breaks: row.get::<_, i64>(4)? as u64,
+                    },
+                ))
+            })?
+            .collect::<Result<HashMap<_, _>, _>>()?;
+
+        let command_count: i64 = self.conn()?.query_row(
+            "SELECT COUNT(*) FROM commands WHERE session_id = ?1",
+            params![session_id],
+            |row| row.get(0),
+        )?;
+
+        Ok(ExportStats {
+            by_kind,
+            command_count: command_count as u64,
+        })
+    }
+
+    // A marker event with up to `before` events preceding and `after` following it, in seq order
+    pub fn get_around_marker(&self, event_id: &str, before: usize, after: usize) -> Result<Vec<Event>> {
+        let marker = self
+            .conn()?
+            .query_row(
+                &format!("SELECT {} FROM events WHERE id = ?1", EVENT_COLUMNS),
+                params![event_id],
+                Self::event_from_row,
+            )
+            .optional()?
+            .ok_or_else(|| anyhow::anyhow!("Event not found"))?;
+        if marker.kind != "marker" {
+            anyhow::bail!("Event is not a marker");
+        }
+
+        let mut prior_stmt = self.conn()?.prepare(&format!(
+            "SELECT {} FROM events WHERE session_id = ?1 AND seq < ?2 ORDER BY seq DESC LIMIT ?3",
+            EVENT_COLUMNS
+        ))?;
+        let mut prior = prior_stmt
+            .query_map(params![&marker.session_id, marker.seq, before as i64], Self::event_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        prior.reverse();
+
+        let mut next_stmt = self.conn()?.prepare(&format!(
+            "SELECT {} FROM events WHERE session_id = ?1 AND seq > ?2 ORDER BY seq ASC LIMIT ?3",
+            EVENT_COLUMNS
+        ))?;
+        let following = next_stmt
+            .query_map(params![&marker.session_id, marker.seq, after as i64], Self::event_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Collapsed copies count toward `before` and `after` like the events they were
+        let mut events = self.expand_repeats(&marker.session_id, prior)?;
+        events.drain(..events.len().saturating_sub(before));
+        let mut following = self.expand_repeats(&marker.session_id, following)?;
+        following.truncate(after);
+        events.push(marker);
+        events.extend(following);
+        Ok(events)
+    }
+
+    // Events in [start_ms, end_ms] (epoch millis, as used by the commands table).
+    // Compared as ts_ms: ts strings only order correctly when every one is canonical UTC.
+    pub fn get_events_between(&self, session_id: &str, start_ms: i64, end_ms: Option<i64>) -> Result<Vec<Event>> {
+        let mut stmt = self.conn()?.prepare(&format!(
+            "SELECT {} FROM events
+             WHERE session_id = ?1 AND ts_ms >= ?2 AND (?3 IS NULL OR ts_ms <= ?3)
+             ORDER BY ts_ms ASC, seq ASC",
+            EVENT_COLUMNS
+        ))?;
+
+        let events = stmt
+            .query_map(params![session_id, start_ms, end_ms], Self::event_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.expand_repeats(session_id, events)
+    }
+
+    // Events between two offsets (ms from session start), clamped to the session's span
+    pub fn get_events_in_window(&self, session_id: &str, start_ms: i64, end_ms: i64) -> Result<Vec<Event>> {
+        let session = self
+            .get_session(session_id)?
+            .ok_or_else(|| anyhow::anyhow!("Session not found"))?;
+
+        let started = chrono::DateTime::parse_from_rfc3339(&session.started_at)?.timestamp_millis();
+        let ended = match session.ended_at {
+            Some(ref ended_at) => chrono::DateTime::parse_from_rfc3339(ended_at)?.timestamp_millis(),
+            None => Utc::now().timestamp_millis(),
+        };
+        let span = (ended - started).max(0);
+
+        let start = start_ms.clamp(0, span);
+        let end = end_ms.clamp(start, span);
+
+        self.get_events_between(session_id, started + start, Some(started + end))
+    }
+
+    pub fn millis_to_ts(ms: i64) -> String {
+        chrono::DateTime::from_timestamp_millis(ms)
+            .unwrap_or_default()
+            .to_rfc3339()
+    }
+
+    pub fn get_sessions_with_commands(&self, limit: usize) -> Result<Vec<SessionSummary>> {
+        let mut stmt = self.conn()?.prepare(
+            "SELECT
+                s.id,
+                s.started_at,
+                s.ended_at,
+                s.cwd,
+                s.shell,
+                COUNT(c.id) as command_count
+             FROM sessions s
+             LEFT JOIN commands c ON s.id = c.session_id
+             GROUP BY s.id
+             ORDER BY s.started_at DESC
+             LIMIT ?1",
+        )?;
+
+        let sessions = stmt
+            .query_map(params![limit], |row| {
+                let session_id: String = row.get(0)?;
+                Ok(SessionSummary {
+                    id: session_id,
+                    started_at: row.get(1)?,
+                    ended_at: row.get(2)?,
+                    cwd: row.get(3)?,
+                    shell: row.get(4)?,
+                    command_count: row.get(5)?,
+                    commands: vec![], // Will be filled in next query
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(sessions)
+    }
+
+    pub fn get_recent_sessions(&self, limit: usize) -> Result<Vec<Session>> {
+        let mut stmt = self.conn()?.prepare(
+            &format!("SELECT {} FROM sessions ORDER BY started_at DESC LIMIT ?1", SESSION_COLUMNS),
+        )?;
+
+        let sessions = stmt
+            .query_map(params![limit], Self::session_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(sessions)
+    }
+
+    // Maps a row selected with SESSION_COLUMNS (optionally followed by extra columns)
+    fn session_from_row(row: &Row) -> rusqlite::Result<Session> {
+        Ok(Session {
+            id: row.get(0)?,
+            started_at: row.get(1)?,
+            ended_at: row.get(2)?,
+            cwd: row.get(3)?,
+            shell: row.get(4)?,
+            cols: row.get(5)?,
+            rows: row.get(6)?,
+            env_file: row.get(7)?,
+            is_template: row.get(8)?,
+            parent_session_id: row.get(9)?,
+            favorite: row.get(10)?,
+        })
+    }
+
+    // Lineage of a session: its ancestors, and it with all descendants nested below
+    pub fn get_session_tree(&self, root_id: &str) -> Result<SessionTree> {
+        let root = self
+            .get_session(root_id)?
+            .ok_or_else(|| anyhow::anyhow!("Session not found"))?;
+
+        let mut ancestors_stmt = self.conn()?.prepare(&format!(
+            "WITH RECURSIVE up(id, depth) AS (
+                SELECT parent_session_id, 1 FROM sessions WHERE id = ?1
+                UNION
+                SELECT s.parent_session_id, up.depth + 1 FROM sessions s JOIN up ON s.id = up.id
+             )
+             SELECT {} FROM sessions JOIN up ON sessions.id = up.id ORDER BY up.depth DESC",
+            Self::qualified_session_columns()
+        ))?;
+        let ancestors = ancestors_stmt
+            .query_map(params![root_id], Self::session_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut descendants_stmt = self.conn()?.prepare(&format!(
+            "WITH RECURSIVE down(id) AS (
+                SELECT id FROM sessions WHERE parent_session_id = ?1
+                UNION
+                SELECT s.id FROM sessions s JOIN down ON s.parent_session_id = down.id
+             )
+             SELECT {} FROM sessions WHERE id IN down ORDER BY started_at ASC",
+            SESSION_COLUMNS
+        ))?;
+        let descendants = descendants_stmt
+            .query_map(params![root_id], Self::session_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut by_parent: HashMap<String, Vec<Session>> = HashMap::new();
+        for session in descendants {
+            if let Some(parent) = session.parent_session_id.clone() {
+                by_parent.entry(parent).or_default().push(session);
+            }
+        }
+
+        Ok(SessionTree {
+            ancestors,
+            root: Self::session_node(root, &mut by_parent),
+        })
+    }
+
+    fn session_node(session: Session, by_parent: &mut HashMap<String, Vec<Session>>) -> SessionNode {
+        let children = by_parent
+            .remove(&session.id)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|child| Self::session_node(child, by_parent))
+            .collect();
+        SessionNode { session, children }
+    }
+
+    // SESSION_COLUMNS prefixed with the table name, for joins
+    fn qualified_session_columns() -> String {
+        SESSION_COLUMNS
+            .split(", ")
+            .map(|column| format!("sessions.{}", column))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    // Recent sessions with counts, duration and an output tail, without per-session queries
+    pub fn get_recent_sessions_detailed(&self, limit: usize) -> Result<Vec<SessionDetail>> {
+        self.sessions_detailed("favorite DESC, started_at DESC", limit, 0)
+    }
+
+    // One page of sessions ordered by an allowlisted key (see SESSION_SORT_KEYS),
+    // "asc" or "desc"; ties go newest first
+    pub fn get_sessions_sorted(
+        &self,
+        sort_by: &str,
+        direction: &str,
+        limit: usize,
+        offset: usize,
+    ) -> Result<Vec<SessionDetail>> {
+        let Some(&(_, expr)) = SESSION_SORT_KEYS.iter().find(|(key, _)| *key == sort_by) else {
+            anyhow::bail!("Unknown sort key: {}", sort_by);
+        };
+        let direction = match direction.to_ascii_lowercase().as_str() {
+            "asc" => "ASC",
+            "desc" => "DESC",
+            _ => anyhow::bail!("Direction must be asc or desc"),
+        };
+        self.sessions_detailed(&format!("{} {}, started_at DESC, id", expr, direction), limit, offset)
+    }
+
+    // `order_by` is interpolated, so it must come from code, never from the caller
+    fn sessions_detailed(&self, order_by: &str, limit: usize, offset: usize) -> Result<Vec<SessionDetail>> {
+        let mut stmt = self.conn()?.prepare(&format!(
+            "SELECT {},
+                (SELECT COUNT(*) FROM events e WHERE e.session_id = sessions.id) AS event_count,
+                (SELECT COUNT(*) FROM commands c WHERE c.session_id = sessions.id) AS command_count,
+                last_activity_at
+             FROM sessions
+             ORDER BY {} LIMIT ?1 OFFSET ?2",
+            SESSION_COLUMNS, order_by
+        ))?;
+
+        let mut details = stmt
+            .query_map(params![limit, offset], |row| {
+                let session = Self::session_from_row(row)?;
+                Ok(SessionDetail {
+                    duration_ms: Self::duration_ms(&session.started_at, session.ended_at.as_deref()),
+                    session,
+                    event_count: row.get(SESSION_COLUMN_COUNT)?,
+                    command_count: row.get(SESSION_COLUMN_COUNT + 1)?,
+                    last_activity_at: row.get(SESSION_COLUMN_COUNT + 2)?,
+                    preview: String::new(),
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if details.is_empty() {
+            return Ok(details);
+        }
+
+        // Last few output chunks of every listed session in one pass
+        let placeholders = vec!["?"; details.len()].join(", ");
+        let mut stmt = self.conn()?.prepare(&format!(
+            "SELECT session_id, data FROM (
+                SELECT session_id, data, ts,
+                    ROW_NUMBER() OVER (PARTITION BY session_id ORDER BY ts DESC) AS rn
+                FROM events
+                WHERE kind = 'pty_out' AND session_id IN ({})
+             )
+             WHERE rn <= {}
+             ORDER BY session_id, ts ASC",
+            placeholders, PREVIEW_EVENTS
+        ))?;
+
+        let mut tails: HashMap<String, String> = HashMap::new();
+        let ids = details.iter().map(|d| d.session.id.as_str());
+        let rows = stmt.query_map(rusqlite::params_from_iter(ids), |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+        for row in rows {
+            let (session_id, data) = row?;
+            tails.entry(session_id).or_default().push_str(&data);
+        }
+
+        for detail in &mut details {
+            if let Some(tail) = tails.get(&detail.session.id) {
+                detail.preview = Self::preview_text(tail);
+            }
+        }
+
+        Ok(details)
+    }
+
+    fn duration_ms(started_at: &str, ended_at: Option<&str>) -> Option<i64> {
+        let start = chrono::DateTime::parse_from_rfc3339(started_at).ok()?;
+        let end = chrono::DateTime::parse_from_rfc3339(ended_at?).ok()?;
+        Some((end - start).num_milliseconds())
+    }
+
+    // Last few non-empty lines of output, escapes stripped
+    fn preview_text(raw: &str) -> String {
+        let text = crate::vt::strip_ansi(raw);
+        let lines: Vec<&str> = text
+            .lines()
+            .map(|l| l.trim_end_matches('\r').trim_end())
+            .filter(|l| !l.is_empty())
+            .collect();
+        let tail = lines[lines.len().saturating_sub(PREVIEW_LINES)..].join("\n");
+
+        let chars: Vec<char> = tail.chars().collect();
+        chars[chars.len().saturating_sub(PREVIEW_CHARS)..].iter().collect()
+    }
+
+    // Command methods for shell integration
+    pub fn create_command(&self, session_id: &str, input: &str) -> Result<String> {
+        let id = Uuid::new_v4().to_string();
+        if !self.persistence_enabled {
+            return Ok(id);
+        }
+        let started_at = Utc::now().timestamp_millis();
+
+        self.conn()?
+            .prepare_cached("INSERT INTO commands (id, session_id, started_at, input) VALUES (?1, ?2, ?3, ?4)")?
+            .execute(params![&id, session_id, started_at, input])?;
+
+        Ok(id)
+    }
+
+    pub fn end_command(&self, session_id: &str, exit_code: i32) -> Result<()> {
+        if !self.persistence_enabled {
+            return Ok(());
+        }
+        let ended_at = Utc::now().timestamp_millis();
+
+        // Find the most recent unfinished command
+        let command_id: Option<String> = self
+            .conn()?
+            .prepare_cached(
+                "SELECT id FROM commands
+                 WHERE session_id = ?1 AND ended_at IS NULL
+                 ORDER BY started_at DESC LIMIT 1",
+            )?
+            .query_row(params![session_id], |row| row.get(0))
+            .optional()?;
+
+        // Update that command if found
+        if let Some(id) = command_id {
+            self.conn()?
+                .prepare_cached("UPDATE commands SET ended_at = ?1, exit_code = ?2 WHERE id = ?3")?
+                .execute(params![ended_at, exit_code, &id])?;
+            self.conn()?
+                .prepare_cached(&format!("UPDATE commands SET {} WHERE id = ?1", COMMAND_OUTPUT_RANGE))?
+                .execute(params![&id])?;
+        }
+
+        Ok(())
+    }
+
+    pub fn get_recent_commands(&self, session_id: &str, limit: usize) -> Result<Vec<(String, i32)>> {
+        let mut stmt = self.conn()?.prepare_cached(
+            "SELECT input, COALESCE(exit_code, -1) FROM commands
+             WHERE session_id = ?1
+             ORDER BY started_at DESC LIMIT ?2",
+        )?;
+
+        let commands = stmt
+            .query_map(params![session_id, limit], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(commands)
+    }
+
+    pub fn get_commands(&self, session_id: &str) -> Result<Vec<Command>> {
+        let mut stmt = self.conn()?.prepare_cached(&format!(
+            "SELECT {} FROM commands WHERE session_id = ?1 ORDER BY started_at ASC",
+            COMMAND_COLUMNS
+        ))?;
+
+        let commands = stmt
+            .query_map(params![session_id], Self::command_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(commands)
+    }
+
+    // Every session's commands, oldest first
+    pub fn get_all_commands(&self) -> Result<Vec<Command>> {
+        let mut stmt = self.conn()?.prepare(&format!(
+            "SELECT {} FROM commands ORDER BY started_at ASC",
+            COMMAND_COLUMNS
+        ))?;
+
+        let commands = stmt
+            .query_map([], Self::command_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(commands)
+    }
+
+    // Finished commands across all sessions, longest first
+    pub fn get_slowest_commands(&self, limit: usize) -> Result<Vec<TimedCommand>> {
+        let mut stmt = self.conn()?.prepare(&format!(
+            "SELECT {}, ended_at - started_at
+             FROM commands
+             WHERE ended_at IS NOT NULL
+             ORDER BY (ended_at - started_at) DESC
+             LIMIT ?1",
+            COMMAND_COLUMNS
+        ))?;
+
+        let commands = stmt
+            .query_map(params![limit], |row| {
+                Ok(TimedCommand {
+                    command: Self::command_from_row(row)?,
+                    duration_ms: row.get(COMMAND_COLUMN_COUNT)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(commands)
+    }
+
+    // Commands that exited non-zero, newest first, in one session or all
+    pub fn get_failed_commands(&self, session_id: Option<&str>, limit: usize) -> Result<Vec<Command>> {
+        let mut stmt = self.conn()?.prepare(&format!(
+            "SELECT {}
+             FROM commands
+             WHERE exit_code != 0 AND (?1 IS NULL OR session_id = ?1)
+             ORDER BY started_at DESC
+             LIMIT ?2",
+            COMMAND_COLUMNS
+        ))?;
+
+        let commands = stmt
+            .query_map(params![session_id, limit], Self::command_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(commands)
+    }
+
+    // The command running at a moment: started at or before it and not yet ended.
+    // If commands overlap, the latest started wins.
+    pub fn command_at_time(&self, session_id: &str, at: &TimePoint) -> Result<Option<Command>> {
+        let at_ms = match at {
+            TimePoint::Timestamp(ts) => chrono::DateTime::parse_from_rfc3339(ts)?.timestamp_millis(),
+            TimePoint::Offset(offset_ms) => {
+                let session = self
+                    .get_session(session_id)?
+                    .ok_or_else(|| anyhow::anyhow!("Session not found"))?;
+                chrono::DateTime::parse_from_rfc3339(&session.started_at)?.timestamp_millis() + offset_ms
+            }
+        };
+
+        let command = self
+            .conn()?
+            .query_row(
+                &format!(
+                    "SELECT {}
+                     FROM commands
+                     WHERE session_id = ?1 AND started_at <= ?2 AND (ended_at IS NULL OR ended_at >= ?2)
+                     ORDER BY started_at DESC LIMIT 1",
+                    COMMAND_COLUMNS
+                ),
+                params![session_id, at_ms],
+                Self::command_from_row,
+            )
+            .optional()?;
+
+        Ok(command)
+    }
+
+    pub fn get_command(&self, command_id: &str) -> Result<Option<Command>> {
+        let command = self
+            .conn()?
+            .query_row(
+                &format!("SELECT {} FROM commands WHERE id = ?1", COMMAND_COLUMNS),
+                params![command_id],
+                Self::command_from_row,
+            )
+            .optional()?;
 
-        let mut rows = stmt.query(params![session_id])?;
+        Ok(command)
+    }
 
-        if let Some(row) = rows.next()? {
-            Ok(Some(Session {
-                id: row.get(0)?,
-                started_at: row.get(1)?,
-                ended_at: row.get(2)?,
-                cwd: row.get(3)?,
-                shell: row.get(4)?,
-            }))
-        } else {
-            Ok(None)
+    // Time between each command's start and the next one's, bucketed for a bar chart
+    pub fn get_idle_histogram(&self, session_id: &str) -> Result<IdleHistogram> {
+        let mut stmt = self.conn()?.prepare(
+            "SELECT gap FROM (
+                 SELECT started_at - LAG(started_at) OVER (ORDER BY started_at) AS gap
+                 FROM commands
+                 WHERE session_id = ?1
+             )
+             WHERE gap IS NOT NULL",
+        )?;
+        let gaps = stmt
+            .query_map(params![session_id], |row| row.get::<_, i64>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut buckets: Vec<IdleBucket> = std::iter::once(0)
+            .chain(IDLE_BUCKET_BOUNDS_MS.iter().copied())
+            .zip(IDLE_BUCKET_BOUNDS_MS.iter().copied().map(Some).chain([None]))
+            .map(|(min_ms, max_ms)| IdleBucket { min_ms, max_ms, count: 0 })
+            .collect();
+        for gap in &gaps {
+            let slot = IDLE_BUCKET_BOUNDS_MS.partition_point(|bound| bound <= gap);
+            buckets[slot].count += 1;
         }
+
+        Ok(IdleHistogram {
+            buckets,
+            gaps: gaps.len(),
+        })
     }
 
-    pub fn get_events(&self, session_id: &str) -> Result<Vec<Event>> {
-        let mut stmt = self.conn.prepare(
-            "SELECT id, session_id, ts, kind, data FROM events
-             WHERE session_id = ?1 ORDER BY ts ASC",
+    // Commands and markers merged into one ordered list; ties keep commands first
+    pub fn get_session_outline(&self, session_id: &str) -> Result<Vec<OutlineEntry>> {
+        let session = self
+            .get_session(session_id)?
+            .ok_or_else(|| anyhow::anyhow!("Session not found"))?;
+        let started = chrono::DateTime::parse_from_rfc3339(&session.started_at)?.timestamp_millis();
+
+        let mut entries: Vec<OutlineEntry> = self
+            .get_commands(session_id)?
+            .into_iter()
+            .map(|c| OutlineEntry {
+                kind: "command".to_string(),
+                title: c.input.unwrap_or_default().trim().to_string(),
+                at_ms: c.started_at,
+                offset_ms: (c.started_at - started).max(0),
+                exit_code: c.exit_code,
+                duration_ms: c.ended_at.map(|ended| ended - c.started_at),
+                id: c.id,
+            })
+            .collect();
+
+        for marker in self.get_events_of_kind(session_id, "marker")? {
+            // Older rows have no ts_ms
+            let at_ms = match marker.ts_ms {
+                Some(ms) => ms,
+                None => chrono::DateTime::parse_from_rfc3339(&marker.ts)?.timestamp_millis(),
+            };
+            entries.push(OutlineEntry {
+                kind: "marker".to_string(),
+                id: marker.id,
+                title: marker.data.trim().to_string(),
+                at_ms,
+                offset_ms: (at_ms - started).max(0),
+                exit_code: None,
+                duration_ms: None,
+            });
+        }
+
+        // Stable, so markers stay in seq order among themselves
+        entries.sort_by_key(|e| e.at_ms);
+        Ok(entries)
+    }
+
+    // Aggregate usage over [start, end), both RFC3339
+    pub fn get_usage_metrics(&self, start: &str, end: &str) -> Result<UsageMetrics> {
+        let start_ms = chrono::DateTime::parse_from_rfc3339(start)?.timestamp_millis();
+        let end_ms = chrono::DateTime::parse_from_rfc3339(end)?.timestamp_millis();
+        let (start_ts, end_ts) = (Self::millis_to_ts(start_ms), Self::millis_to_ts(end_ms));
+
+        let (total_sessions, total_active_ms): (i64, i64) = self.conn()?.query_row(
+            "SELECT COUNT(*),
+                COALESCE(SUM(CAST((julianday(ended_at) - julianday(started_at)) * 86400000 AS INTEGER)), 0)
+             FROM sessions WHERE started_at >= ?1 AND started_at < ?2",
+            params![start_ts, end_ts],
+            |row| Ok((row.get(0)?, row.get(1)?)),
         )?;
 
-        let events = stmt
-            .query_map(params![session_id], |row| {
-                Ok(Event {
-                    id: row.get(0)?,
-                    session_id: row.get(1)?,
-                    ts: row.get(2)?,
-                    kind: row.get(3)?,
-                    data: row.get(4)?,
-                })
-            })?
-            .collect::<Result<Vec<_>, _>>()?;
+        let total_commands: i64 = self.conn()?.query_row(
+            "SELECT COUNT(*) FROM commands WHERE started_at >= ?1 AND started_at < ?2",
+            params![start_ms, end_ms],
+            |row| row.get(0),
+        )?;
 
-        Ok(events)
+        let total_output_bytes: i64 = self.conn()?.query_row(
+            "SELECT COALESCE(SUM(LENGTH(CAST(data AS BLOB)) * repeat_count), 0) FROM events
+             WHERE kind = 'pty_out' AND ts >= ?1 AND ts < ?2",
+            params![start_ts, end_ts],
+            |row| row.get(0),
+        )?;
+
+        let busiest_hour: Option<String> = self
+            .conn()?
+            .query_row(
+                "SELECT strftime('%H', started_at / 1000, 'unixepoch', 'localtime') AS hour
+                 FROM commands WHERE started_at >= ?1 AND started_at < ?2
+                 GROUP BY hour ORDER BY COUNT(*) DESC, hour ASC LIMIT 1",
+                params![start_ms, end_ms],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(UsageMetrics {
+            total_sessions,
+            total_commands,
+            total_output_bytes,
+            total_active_ms,
+            busiest_hour: busiest_hour.and_then(|h| h.parse().ok()),
+        })
     }
 
-    pub fn get_sessions_with_commands(&self, limit: usize) -> Result<Vec<SessionSummary>> {
-        let mut stmt = self.conn.prepare(
-            "SELECT
-                s.id,
-                s.started_at,
-                s.ended_at,
-                s.cwd,
-                s.shell,
-                COUNT(c.id) as command_count
-             FROM sessions s
-             LEFT JOIN commands c ON s.id = c.session_id
-             GROUP BY s.id
-             ORDER BY s.started_at DESC
-             LIMIT ?1",
+    // Tags
+    pub fn add_session_tag(&self, session_id: &str, tag: &str) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT OR IGNORE INTO session_tags (session_id, tag) VALUES (?1, ?2)",
+            params![session_id, tag],
         )?;
+        Ok(())
+    }
 
-        let sessions = stmt
-            .query_map(params![limit], |row| {
-                let session_id: String = row.get(0)?;
-                Ok(SessionSummary {
-                    id: session_id,
-                    started_at: row.get(1)?,
-                    ended_at: row.get(2)?,
-                    cwd: row.get(3)?,
-                    shell: row.get(4)?,
-                    command_count: row.get(5)?,
-                    commands: vec![], // Will be filled in next query
-                })
-            })?
-            .collect::<Result<Vec<_>, _>>()?;
+    pub fn remove_session_tag(&self, session_id: &str, tag: &str) -> Result<()> {
+        self.conn()?.execute(
+            "DELETE FROM session_tags WHERE session_id = ?1 AND tag = ?2",
+            params![session_id, tag],
+        )?;
+        Ok(())
+    }
 
-        Ok(sessions)
+    // Add the auto_tag_rules tags one session matches; returns those it didn't already have
+    pub fn apply_auto_tags(&self, session_id: &str) -> Result<Vec<String>> {
+        if !self.persistence_enabled {
+            return Ok(Vec::new());
+        }
+        let rules = autotag::rules_from_setting(&self.get_setting(settings::AUTO_TAG_RULES)?)?;
+        self.auto_tag(&rules, session_id)
     }
 
-    pub fn get_recent_sessions(&self, limit: usize) -> Result<Vec<Session>> {
-        let mut stmt = self.conn.prepare(
-            "SELECT id, started_at, ended_at, cwd, shell FROM sessions
-             ORDER BY started_at DESC LIMIT ?1",
+    // Check the RFC3339 timestamps of sessions (started_at, ended_at, last_activity_at),
+    // events (ts) and prune notices (noticed_at), in one session or all, e.g. after
+    // importing data from elsewhere
+    pub fn validate_timestamps(&self, session_id: Option<&str>) -> Result<TimestampReport> {
+        self.check_timestamps(session_id, false)
+    }
+
+    // Like validate_timestamps, also rewriting parseable timestamps to canonical UTC
+    // so text ordering and range queries agree with time. Unparseable ones are left.
+    // Events' ts_ms is recomputed from ts, and with it the commands' output ranges.
+    pub fn normalize_timestamps(&self, session_id: Option<&str>) -> Result<TimestampReport> {
+        self.check_timestamps(session_id, true)
+    }
+
+    fn check_timestamps(&self, session_id: Option<&str>, rewrite: bool) -> Result<TimestampReport> {
+        if let Some(id) = session_id {
+            if self.get_session(id)?.is_none() {
+                anyhow::bail!("Session not found");
+            }
+        }
+        let tx = self.conn()?.unchecked_transaction()?;
+        let mut report = TimestampReport::default();
+        // Table, its key column, column, key, canonical value
+        let mut fixes: Vec<(&str, &str, &str, String, String)> = Vec::new();
+        let mut ts_ms_fixes: Vec<(String, i64)> = Vec::new(); // Event id, ts_ms from its ts
+        let mut check = |table: &'static str, key: &'static str, column: &'static str, id: String, value: String| {
+            report.checked += 1;
+            match chrono::DateTime::parse_from_rfc3339(&value) {
+                Ok(ts) => {
+                    let ts = ts.with_timezone(&Utc);
+                    let canonical = ts.to_rfc3339();
+                    if canonical != value {
+                        report.non_canonical += 1;
+                        fixes.push((table, key, column, id, canonical));
+                    }
+                    Some(ts)
+                }
+                Err(_) => {
+                    report.unparseable.push(BadTimestamp {
+                        table: table.to_string(),
+                        id,
+                        column: column.to_string(),
+                        value,
+                    });
+                    None
+                }
+            }
+        };
+
+        {
+            let mut stmt = tx.prepare(
+                "SELECT id, started_at, ended_at, last_activity_at FROM sessions WHERE ?1 IS NULL OR id = ?1",
+            )?;
+            let mut rows = stmt.query(params![session_id])?;
+            while let Some(row) = rows.next()? {
+                let id: String = row.get(0)?;
+                for (idx, column) in [(2, "ended_at"), (3, LAST_ACTIVITY_AT)] {
+                    if let Some(value) = row.get::<_, Option<String>>(idx)? {
+                        check("sessions", "id", column, id.clone(), value);
+                    }
+                }
+                check("sessions", "id", "started_at", id, row.get(1)?);
+            }
+            let mut stmt =
+                tx.prepare("SELECT session_id, noticed_at FROM prune_notices WHERE ?1 IS NULL OR session_id = ?1")?;
+            let mut rows = stmt.query(params![session_id])?;
+            while let Some(row) = rows.next()? {
+                check("prune_notices", "session_id", "noticed_at", row.get(0)?, row.get(1)?);
+            }
+            let mut stmt = tx.prepare("SELECT id, ts, ts_ms FROM events WHERE ?1 IS NULL OR session_id = ?1")?;
+            let mut rows = stmt.query(params![session_id])?;
+            while let Some(row) = rows.next()? {
+                let id: String = row.get(0)?;
+                let ts_ms: Option<i64> = row.get(2)?;
+                if let Some(ts) = check("events", "id", "ts", id.clone(), row.get(1)?) {
+                    if ts_ms != Some(ts.timestamp_millis()) {
+                        ts_ms_fixes.push((id, ts.timestamp_millis()));
+                    }
+                }
+            }
+        }
+        report.stale_ts_ms = ts_ms_fixes.len();
+
+        if rewrite {
+            for (table, key, column, id, canonical) in fixes {
+                tx.execute(
+                    &format!("UPDATE {} SET {} = ?1 WHERE {} = ?2", table, column, key),
+                    params![canonical, id],
+                )?;
+                report.rewritten += 1;
+            }
+            if !ts_ms_fixes.is_empty() {
+                for (id, ts_ms) in &ts_ms_fixes {
+                    tx.execute("UPDATE events SET ts_ms = ?1 WHERE id = ?2", params![ts_ms, id])?;
+                }
+                // Output ranges were found by ts_ms
+                tx.execute(
+                    &format!(
+                        "UPDATE commands SET {} WHERE (?1 IS NULL OR session_id = ?1) AND ended_at IS NOT NULL",
+                        COMMAND_OUTPUT_RANGE
+                    ),
+                    params![session_id],
+                )?;
+            }
+            Self::clear_cached_summaries(&tx, session_id)?;
+            tx.commit()?;
+        }
+        Ok(report)
+    }
+
+    // Renumber a session's events 1..n in timestamp order (ties, and events without a
+    // ts_ms, which go last, keep their current order), e.g. after a merge or import
+    // left gaps. Returns how many seqs changed.
+    pub fn normalize_session(&self, session_id: &str) -> Result<usize> {
+        if self.get_session(session_id)?.is_none() {
+            anyhow::bail!("Session not found");
+        }
+        let tx = self.conn()?.unchecked_transaction()?;
+        let rows: Vec<(i64, Option<i64>)> = {
+            let mut stmt = tx.prepare(
+                "SELECT rowid, seq FROM events WHERE session_id = ?1 ORDER BY ts_ms IS NULL, ts_ms, seq, rowid",
+            )?;
+            let rows = stmt
+                .query_map(params![session_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<Result<Vec<_>, _>>()?;
+            rows
+        };
+
+        let mut renumbered = 0;
+        for (idx, (rowid, seq)) in rows.iter().enumerate() {
+            let new_seq = idx as i64 + 1;
+            if *seq == Some(new_seq) {
+                continue;
+            }
+            let changed = tx.execute(
+                "UPDATE events SET seq = ?1 WHERE rowid = ?2 AND session_id = ?3",
+                params![new_seq, rowid, session_id],
+            )?;
+            if changed != 1 {
+                anyhow::bail!("Event moved to another session while renumbering");
+            }
+            renumbered += 1;
+        }
+
+        // Read back what was written: each event still belongs to the requested session
+        // and holds its new seq, and the session has no events beyond these
+        let verified = {
+            let mut stmt = tx.prepare("SELECT session_id, seq FROM events WHERE rowid = ?1")?;
+            let mut verified = true;
+            for (idx, (rowid, _)) in rows.iter().enumerate() {
+                let (owner, seq): (String, Option<i64>) =
+                    stmt.query_row(params![rowid], |row| Ok((row.get(0)?, row.get(1)?)))?;
+                verified &= owner == session_id && seq == Some(idx as i64 + 1);
+            }
+            let count: usize =
+                tx.query_row("SELECT COUNT(*) FROM events WHERE session_id = ?1", params![session_id], |row| row.get(0))?;
+            verified && count == rows.len()
+        };
+        if !verified {
+            anyhow::bail!("Renumbered events failed verification; nothing was changed");
+        }
+        // Commands point at output by seq
+        tx.execute(
+            &format!("UPDATE commands SET {} WHERE session_id = ?1 AND ended_at IS NOT NULL", COMMAND_OUTPUT_RANGE),
+            params![session_id],
         )?;
+        Self::clear_cached_summaries(&tx, Some(session_id))?;
+        tx.commit()?;
+        Ok(renumbered)
+    }
 
-        let sessions = stmt
-            .query_map(params![limit], |row| {
-                Ok(Session {
-                    id: row.get(0)?,
-                    started_at: row.get(1)?,
-                    ended_at: row.get(2)?,
-                    cwd: row.get(3)?,
-                    shell: row.get(4)?,
-                })
-            })?
+    // Rebuild a session's commands from the OSC 133 markers in its stored output,
+    // replacing those recorded live. Returns how many were found.
+    pub fn reparse_commands(&self, session_id: &str) -> Result<usize> {
+        if self.get_session(session_id)?.is_none() {
+            anyhow::bail!("Session not found");
+        }
+        let events = self.get_events_of_kind(session_id, "pty_out")?;
+        let chunks: Vec<(i64, &str)> = events
+            .iter()
+            .map(|event| {
+                let ms = event.ts_ms.or_else(|| {
+                    chrono::DateTime::parse_from_rfc3339(&event.ts).ok().map(|ts| ts.timestamp_millis())
+                });
+                (ms.unwrap_or(0), event.data.as_str())
+            })
+            .collect();
+        let commands = osc::recorded_commands(&chunks);
+
+        let tx = self.conn()?.unchecked_transaction()?;
+        tx.execute("DELETE FROM commands WHERE session_id = ?1", params![session_id])?;
+        for command in &commands {
+            tx.execute(
+                "INSERT INTO commands (id, session_id, started_at, ended_at, exit_code, input)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    Uuid::new_v4().to_string(),
+                    session_id,
+                    command.started_at,
+                    command.ended_at,
+                    command.exit_code,
+                    command.input
+                ],
+            )?;
+        }
+        tx.execute(
+            &format!("UPDATE commands SET {} WHERE session_id = ?1 AND ended_at IS NOT NULL", COMMAND_OUTPUT_RANGE),
+            params![session_id],
+        )?;
+        tx.commit()?;
+        Ok(commands.len())
+    }
+
+    // Run the rules over every recorded session, for rules added after the fact
+    pub fn reapply_auto_tags(&self) -> Result<Vec<AutoTagged>> {
+        let rules = autotag::rules_from_setting(&self.get_setting(settings::AUTO_TAG_RULES)?)?;
+        let ids: Vec<String> = {
+            let mut stmt = self.conn()?.prepare("SELECT id FROM sessions ORDER BY started_at ASC")?;
+            let ids = stmt
+                .query_map([], |row| row.get(0))?
+                .collect::<Result<Vec<_>, _>>()?;
+            ids
+        };
+
+        let tx = self.conn()?.unchecked_transaction()?;
+        let mut tagged = Vec::new();
+        for session_id in ids {
+            let tags = self.auto_tag(&rules, &session_id)?;
+            if !tags.is_empty() {
+                tagged.push(AutoTagged { session_id, tags });
+            }
+        }
+        tx.commit()?;
+        Ok(tagged)
+    }
+
+    fn auto_tag(&self, rules: &[autotag::Rule], session_id: &str) -> Result<Vec<String>> {
+        if rules.is_empty() {
+            return Ok(Vec::new());
+        }
+        let Some(cwd) = self
+            .conn()?
+            .query_row("SELECT cwd FROM sessions WHERE id = ?1", params![session_id], |row| {
+                row.get::<_, Option<String>>(0)
+            })
+            .optional()?
+        else {
+            return Ok(Vec::new());
+        };
+        let commands: Vec<String> = self
+            .get_commands(session_id)?
+            .into_iter()
+            .filter_map(|c| c.input)
+            .collect();
+
+        let mut added = Vec::new();
+        for tag in autotag::matching_tags(rules, cwd.as_deref().unwrap_or(""), &commands) {
+            let inserted = self.conn()?.execute(
+                "INSERT OR IGNORE INTO session_tags (session_id, tag) VALUES (?1, ?2)",
+                params![session_id, &tag],
+            )?;
+            if inserted > 0 {
+                added.push(tag);
+            }
+        }
+        Ok(added)
+    }
+
+    pub fn get_session_tags(&self, session_id: &str) -> Result<Vec<String>> {
+        let mut stmt = self
+            .conn()?
+            .prepare("SELECT tag FROM session_tags WHERE session_id = ?1 ORDER BY tag")?;
+        let tags = stmt
+            .query_map(params![session_id], |row| row.get(0))?
             .collect::<Result<Vec<_>, _>>()?;
+        Ok(tags)
+    }
 
-        Ok(sessions)
+    // Delete every session matching the filter; refuses an empty filter, and empty
+    // values that would match more than intended
+    pub fn delete_sessions_where(&self, filter: &SessionFilter) -> Result<usize> {
+        let mut clauses = Vec::new();
+        let mut values: Vec<String> = Vec::new();
+
+        if let Some(ref tag) = filter.tag {
+            if tag.trim().is_empty() {
+                anyhow::bail!("Refusing to delete with an empty tag");
+            }
+            values.push(tag.clone());
+            clauses.push(format!(
+                "id IN (SELECT session_id FROM session_tags WHERE tag = ?{})",
+                values.len()
+            ));
+        }
+        if let Some(ref prefix) = filter.cwd_prefix {
+            // Whole path components: /home/me/proj matches /home/me/proj/src, not /home/me/project
+            let dir = prefix.trim_end_matches('/');
+            if dir.trim().is_empty() {
+                anyhow::bail!("Refusing to delete with cwd prefix {:?}", prefix);
+            }
+            values.push(dir.to_string());
+            clauses.push(format!(
+                "(cwd = ?{n} OR substr(cwd, 1, length(?{n}) + 1) = ?{n} || '/')",
+                n = values.len()
+            ));
+        }
+        if filter.failed_only == Some(true) {
+            clauses.push(
+                "id IN (SELECT session_id FROM commands WHERE exit_code IS NOT NULL AND exit_code != 0)"
+                    .to_string(),
+            );
+        }
+
+        if clauses.is_empty() {
+            anyhow::bail!("Refusing to delete with an empty filter");
+        }
+
+        let ids: Vec<String> = {
+            let mut stmt = self.conn()?.prepare(&format!(
+                "SELECT id FROM sessions WHERE {}",
+                clauses.join(" AND ")
+            ))?;
+            let ids = stmt
+                .query_map(rusqlite::params_from_iter(values.iter()), |row| row.get(0))?
+                .collect::<Result<Vec<_>, _>>()?;
+            ids
+        };
+
+        self.delete_sessions(&ids)
     }
 
-    // Command methods for shell integration
-    pub fn create_command(&self, session_id: &str, input: &str) -> Result<String> {
-        let id = Uuid::new_v4().to_string();
-        let started_at = Utc::now().timestamp_millis();
+    // Up to `max` pty_out payloads spread evenly across the session. Every `step`th
+    // payload is taken, counting collapsed copies, so a stored event may be picked
+    // more than once.
+    pub fn sample_output(&self, session_id: &str, max: usize) -> Result<Vec<String>> {
+        let mut stmt = self.conn()?.prepare(
+            "SELECT data, (upto + step - 1) / step - (upto - repeat_count + step - 1) / step FROM (
+                SELECT data, seq, repeat_count,
+                    SUM(repeat_count) OVER (ORDER BY seq) AS upto,
+                    MAX(1, SUM(repeat_count) OVER () / ?2) AS step
+                FROM events WHERE session_id = ?1 AND kind = 'pty_out'
+             )
+             ORDER BY seq",
+        )?;
+        let mut rows = stmt.query(params![session_id, max.max(1) as i64])?;
+
+        let mut samples = Vec::new();
+        while let Some(row) = rows.next()? {
+            let (data, picks): (String, usize) = (row.get(0)?, row.get(1)?);
+            samples.extend(std::iter::repeat_n(data, picks));
+            if samples.len() >= max.max(1) {
+                break;
+            }
+        }
+        samples.truncate(max.max(1));
+        Ok(samples)
+    }
+
+    // Sessions whose output mentions a file path, most recently mentioned first. Paths
+    // aren't indexed, so this scans output for the text; see paths::count_mentions.
+    pub fn find_sessions_mentioning_path(&self, path: &str) -> Result<Vec<PathSessionMatch>> {
+        let path = path.trim();
+        if path.is_empty() {
+            anyhow::bail!("Path is empty");
+        }
 
-        self.conn.execute(
-            "INSERT INTO commands (id, session_id, started_at, input) VALUES (?1, ?2, ?3, ?4)",
-            params![&id, session_id, started_at, input],
+        let mut stmt = self.conn()?.prepare(
+            "SELECT session_id, data, ts, repeat_count FROM events
+             WHERE kind = 'pty_out' AND instr(data, ?1) > 0",
         )?;
+        let mut rows = stmt.query(params![path])?;
 
-        Ok(id)
+        // session -> (mentions, last mention ts)
+        let mut found: HashMap<String, (usize, String)> = HashMap::new();
+        while let Some(row) = rows.next()? {
+            let (session_id, data, ts): (String, String, String) = (row.get(0)?, row.get(1)?, row.get(2)?);
+            let count = paths::count_mentions(&data, path) * row.get::<_, usize>(3)?;
+            if count == 0 {
+                continue;
+            }
+            let entry = found.entry(session_id).or_insert((0, String::new()));
+            entry.0 += count;
+            if ts > entry.1 {
+                entry.1 = ts;
+            }
+        }
+
+        let mut matches = Vec::new();
+        for (session_id, (mentions, last_mentioned_at)) in found {
+            if let Some(session) = self.get_session(&session_id)? {
+                matches.push(PathSessionMatch {
+                    session,
+                    mentions,
+                    last_mentioned_at,
+                });
+            }
+        }
+        matches.sort_by(|a, b| b.last_mentioned_at.cmp(&a.last_mentioned_at));
+        Ok(matches)
     }
 
-    pub fn end_command(&self, session_id: &str, exit_code: i32) -> Result<()> {
-        let ended_at = Utc::now().timestamp_millis();
+    // Events whose data matches an FTS5 query, newest first, collapsed copies included
+    pub fn search_events(&self, query: &str, limit: usize) -> Result<Vec<Event>> {
+        let mut stmt = self.conn()?.prepare(&format!(
+            "SELECT {}, repeat_count FROM events
+             WHERE rowid IN (SELECT rowid FROM events_fts WHERE events_fts MATCH ?1)
+             ORDER BY ts DESC LIMIT ?2",
+            EVENT_COLUMNS
+        ))?;
 
-        // Find the most recent unfinished command
-        let command_id: Option<String> = self
-            .conn
+        let mut events = Vec::new();
+        let mut rows = stmt.query(params![query, limit as i64])?;
+        while let Some(row) = rows.next()? {
+            let copies: usize = row.get(EVENT_COLUMN_COUNT)?;
+            events.extend(std::iter::repeat_n(Self::event_from_row(row)?, copies));
+        }
+        events.truncate(limit);
+        Ok(events)
+    }
+
+    // Repopulate the search index from events; returns the number of rows indexed
+    pub fn rebuild_search_index(&self) -> Result<usize> {
+        let tx = self.conn()?.unchecked_transaction()?;
+        tx.execute("INSERT INTO events_fts(events_fts) VALUES ('rebuild')", [])?;
+        let count: i64 = tx.query_row("SELECT COUNT(*) FROM events", [], |row| row.get(0))?;
+        tx.commit()?;
+        Ok(count as usize)
+    }
+
+    // SHA-256 (hex) of the session's output, and optionally its input, in seq order.
+    // The output-only hash of an ended session is cached on its row.
+    pub fn session_content_hash(&self, session_id: &str, include_input: bool) -> Result<String> {
+        let (ended_at, cached): (Option<String>, Option<String>) = self
+            .conn()?
             .query_row(
-                "SELECT id FROM commands
-                 WHERE session_id = ?1 AND ended_at IS NULL
-                 ORDER BY started_at DESC LIMIT 1",
+                "SELECT ended_at, content_hash FROM sessions WHERE id = ?1",
                 params![session_id],
-                |row| row.get(0),
+                |row| Ok((row.get(0)?, row.get(1)?)),
             )
-            .optional()?;
+            .optional()?
+            .ok_or_else(|| anyhow::anyhow!("Session not found"))?;
 
-        // Update that command if found
-        if let Some(id) = command_id {
-            self.conn.execute(
-                "UPDATE commands SET ended_at = ?1, exit_code = ?2 WHERE id = ?3",
-                params![ended_at, exit_code, &id],
+        if !include_input {
+            if let Some(hash) = cached {
+                return Ok(hash);
+            }
+        }
+
+        let mut stmt = self.conn()?.prepare(
+            "SELECT kind, data, repeat_count FROM events
+             WHERE session_id = ?1 AND (kind = 'pty_out' OR (?2 AND kind = 'user_in'))
+             ORDER BY seq ASC",
+        )?;
+        let mut rows = stmt.query(params![session_id, include_input])?;
+
+        // Output alone is hashed as one byte stream, so chunk boundaries don't matter;
+        // with input, each switch between directions is tagged
+        let mut hasher = Sha256::new();
+        let mut last_kind = String::new();
+        while let Some(row) = rows.next()? {
+            let kind: String = row.get(0)?;
+            let data: String = row.get(1)?;
+            let copies: usize = row.get(2)?;
+            if include_input && kind != last_kind {
+                hasher.update([0u8]);
+                hasher.update(kind.as_bytes());
+                hasher.update([0u8]);
+                last_kind = kind;
+            }
+            // Collapsed copies hash as the events they were
+            for _ in 0..copies {
+                hasher.update(data.as_bytes());
+            }
+        }
+        let hash = format!("{:x}", hasher.finalize());
+
+        if !include_input && ended_at.is_some() {
+            self.conn()?.execute(
+                "UPDATE sessions SET content_hash = ?1 WHERE id = ?2",
+                params![&hash, session_id],
             )?;
         }
+        Ok(hash)
+    }
 
+    // Drop what's cached on session rows from their events (session_content_hash,
+    // session_sparkline), for any method that rewrites stored events; None clears
+    // every session's. Caches are only filled once a session has ended, so live
+    // appends don't need this.
+    fn clear_cached_summaries(conn: &Connection, session_id: Option<&str>) -> Result<()> {
+        conn.execute(
+            "UPDATE sessions SET content_hash = NULL, sparkline = NULL WHERE ?1 IS NULL OR id = ?1",
+            params![session_id],
+        )?;
         Ok(())
     }
 
-    pub fn get_recent_commands(&self, session_id: &str, limit: usize) -> Result<Vec<(String, i32)>> {
-        let mut stmt = self.conn.prepare(
-            "SELECT input, COALESCE(exit_code, -1) FROM commands
-             WHERE session_id = ?1
-             ORDER BY started_at DESC LIMIT ?2",
-        )?;
+    // Output bytes in each of SPARKLINE_BUCKETS equal slices of the session, from its
+    // start to its end (or latest output if still live). Cached once the session ends.
+    pub fn session_sparkline(&self, session_id: &str) -> Result<Vec<u64>> {
+        let (started_at, ended_at, cached): (String, Option<String>, Option<String>) = self
+            .conn()?
+            .query_row(
+                "SELECT started_at, ended_at, sparkline FROM sessions WHERE id = ?1",
+                params![session_id],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()?
+            .ok_or_else(|| anyhow::anyhow!("Session not found"))?;
 
-        let commands = stmt
-            .query_map(params![session_id, limit], |row| {
-                Ok((row.get(0)?, row.get(1)?))
-            })?
+        if let Some(json) = cached {
+            return Ok(serde_json::from_str(&json)?);
+        }
+
+        let mut stmt = self.conn()?.prepare(
+            "SELECT COALESCE(ts_ms, CAST((julianday(ts) - 2440587.5) * 86400000 AS INTEGER)),
+                    length(CAST(data AS BLOB)) * repeat_count
+             FROM events
+             WHERE session_id = ?1 AND kind = 'pty_out'
+             ORDER BY seq ASC",
+        )?;
+        let samples = stmt
+            .query_map(params![session_id], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, u64>(1)?)))?
             .collect::<Result<Vec<_>, _>>()?;
 
-        Ok(commands)
+        let start = chrono::DateTime::parse_from_rfc3339(&started_at)?.timestamp_millis();
+        let end = match ended_at {
+            Some(ref ended_at) => chrono::DateTime::parse_from_rfc3339(ended_at)?.timestamp_millis(),
+            None => samples.iter().map(|(ts, _)| *ts).max().unwrap_or(start),
+        };
+        let span = (end - start).max(1);
+
+        let mut buckets = vec![0u64; SPARKLINE_BUCKETS];
+        for (ts, bytes) in samples {
+            let offset = (ts - start).clamp(0, span);
+            let slot = (offset * SPARKLINE_BUCKETS as i64 / span) as usize;
+            buckets[slot.min(SPARKLINE_BUCKETS - 1)] += bytes;
+        }
+
+        if ended_at.is_some() {
+            self.conn()?.execute(
+                "UPDATE sessions SET sparkline = ?1 WHERE id = ?2",
+                params![serde_json::to_string(&buckets)?, session_id],
+            )?;
+        }
+        Ok(buckets)
     }
 
-    pub fn get_commands(&self, session_id: &str) -> Result<Vec<Command>> {
-        let mut stmt = self.conn.prepare(
-            "SELECT id, session_id, input, exit_code, started_at, ended_at
-             FROM commands
-             WHERE session_id = ?1
-             ORDER BY started_at ASC",
-        )?;
+    // Ended sessions with identical output, skipping ones that recorded nothing
+    pub fn find_duplicate_sessions(&self) -> Result<Vec<DuplicateGroup>> {
+        let uncached = {
+            let mut stmt = self.conn()?.prepare(
+                "SELECT id FROM sessions WHERE ended_at IS NOT NULL AND content_hash IS NULL",
+            )?;
+            let ids = stmt
+                .query_map([], |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<_>, _>>()?;
+            ids
+        };
+        for id in uncached {
+            self.session_content_hash(&id, false)?;
+        }
 
-        let commands = stmt
-            .query_map(params![session_id], |row| {
-                Ok(Command {
-                    id: row.get(0)?,
-                    session_id: row.get(1)?,
-                    input: row.get(2)?,
-                    exit_code: row.get(3)?,
-                    started_at: row.get(4)?,
-                    ended_at: row.get(5)?,
-                })
-            })?
+        let mut stmt = self.conn()?.prepare(
+            "SELECT content_hash, id FROM sessions s
+             WHERE content_hash IS NOT NULL
+               AND EXISTS (SELECT 1 FROM events WHERE session_id = s.id AND kind = 'pty_out')
+               AND content_hash IN (
+                   SELECT content_hash FROM sessions
+                   WHERE content_hash IS NOT NULL
+                   GROUP BY content_hash HAVING COUNT(*) > 1
+               )
+             ORDER BY content_hash, started_at ASC",
+        )?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
             .collect::<Result<Vec<_>, _>>()?;
 
-        Ok(commands)
+        let mut groups: Vec<DuplicateGroup> = Vec::new();
+        for (hash, id) in rows {
+            match groups.last_mut() {
+                Some(group) if group.content_hash == hash => group.session_ids.push(id),
+                _ => groups.push(DuplicateGroup {
+                    content_hash: hash,
+                    session_ids: vec![id],
+                }),
+            }
+        }
+        // Empty-output sessions were dropped above, which can leave singletons
+        groups.retain(|group| group.session_ids.len() > 1);
+
+        Ok(groups)
+    }
+
+    // Remove sessions and everything that references them, atomically
+    pub fn delete_sessions(&self, ids: &[String]) -> Result<usize> {
+        let tx = self.conn()?.unchecked_transaction()?;
+        for id in ids {
+            for table in SESSION_CHILD_TABLES {
+                tx.execute(&format!("DELETE FROM {} WHERE session_id = ?1", table), params![id])?;
+            }
+            tx.execute("DELETE FROM sessions WHERE id = ?1", params![id])?;
+        }
+        tx.commit()?;
+        Ok(ids.len())
     }
 
     // Interaction tracking - capture behavioral patterns
@@ -382,9 +3060,12 @@ impl Database {
         metadata: Option<&str>,
     ) -> Result<String> {
         let id = Uuid::new_v4().to_string();
+        if !self.persistence_enabled {
+            return Ok(id);
+        }
         let timestamp = Utc::now().to_rfc3339();
 
-        self.conn.execute(
+        self.conn()?.execute(
             "INSERT INTO interactions (id, session_id, timestamp, interaction_type, context, target, outcome, metadata)
              VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
             params![&id, session_id, &timestamp, interaction_type, context, target, outcome, metadata],
@@ -395,7 +3076,7 @@ impl Database {
 
     // Get interaction patterns for analysis
     pub fn get_interaction_patterns(&self, limit: usize) -> Result<Vec<Interaction>> {
-        let mut stmt = self.conn.prepare(
+        let mut stmt = self.conn()?.prepare(
             "SELECT id, session_id, timestamp, interaction_type, context, target, outcome, metadata
              FROM interactions
              ORDER BY timestamp DESC
@@ -425,7 +3106,7 @@ impl Database {
         let cutoff = Utc::now() - chrono::Duration::hours(hours);
         let cutoff_str = cutoff.to_rfc3339();
 
-        let mut stmt = self.conn.prepare(
+        let mut stmt = self.conn()?.prepare(
             "SELECT interaction_type, COUNT(*) as count
              FROM interactions
              WHERE timestamp > ?1
@@ -448,7 +3129,7 @@ impl Database {
         let cutoff = Utc::now() - chrono::Duration::hours(hours);
         let cutoff_str = cutoff.to_rfc3339();
 
-        let mut stmt = self.conn.prepare(
+        let mut stmt = self.conn()?.prepare(
             "SELECT id, session_id, timestamp, interaction_type, context, target, outcome, metadata
              FROM interactions
              WHERE timestamp > ?1 AND (outcome = 'friction' OR outcome = 'abandoned' OR outcome = 'error')
@@ -473,3 +3154,202 @@ impl Database {
         Ok(friction)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A migrated database in a file of its own, deleted (with its WAL) when dropped
+    struct TestDb(Database, PathBuf);
+
+    impl Drop for TestDb {
+        fn drop(&mut self) {
+            for suffix in ["", "-wal", "-shm"] {
+                std::fs::remove_file(format!("{}{}", self.1.display(), suffix)).ok();
+            }
+        }
+    }
+
+    fn test_db() -> TestDb {
+        let path = std::env::temp_dir().join(format!("vibe-test-{}.db", Uuid::new_v4()));
+        TestDb(Database::open_at(path.clone()).unwrap(), path)
+    }
+
+    #[test]
+    fn test_seq_backfill() {
+        // A file from before events had seq
+        let path = std::env::temp_dir().join(format!("vibe-test-{}.db", Uuid::new_v4()));
+        let old = Connection::open(&path).unwrap();
+        old.execute_batch(
+            "CREATE TABLE sessions (id TEXT PRIMARY KEY, started_at TEXT NOT NULL, ended_at TEXT, cwd TEXT, shell TEXT);
+             CREATE TABLE events (id TEXT PRIMARY KEY, session_id TEXT NOT NULL, ts TEXT NOT NULL, kind TEXT NOT NULL, data TEXT NOT NULL);
+             INSERT INTO sessions VALUES ('a', '2024-01-01T00:00:00+00:00', NULL, '/', 'sh'),
+                                         ('b', '2024-01-01T00:00:00+00:00', NULL, '/', 'sh');
+             INSERT INTO events VALUES ('1', 'a', '2024-01-01T00:00:02+00:00', 'pty_out', 'x'),
+                                       ('2', 'b', '2024-01-01T00:00:01+00:00', 'pty_out', 'y'),
+                                       ('3', 'a', '2024-01-01T00:00:01+00:00', 'pty_out', 'z'),
+                                       ('4', 'a', '2024-01-01T00:00:01+00:00', 'pty_out', 'w');",
+        )
+        .unwrap();
+        drop(old);
+
+        let test_db = TestDb(Database::open_at(path.clone()).unwrap(), path);
+        let seqs = |session: &str| -> Vec<(String, Option<i64>)> {
+            test_db.0.get_events(session).unwrap().into_iter().map(|e| (e.id, e.seq)).collect()
+        };
+        let expected = [("3", 1), ("4", 2), ("1", 3)].map(|(id, seq)| (id.to_string(), Some(seq)));
+        assert_eq!(seqs("a"), expected);
+        assert_eq!(seqs("b"), vec![("2".to_string(), Some(1))]);
+    }
+
+    #[test]
+    fn test_collapse_repeats_round_trip() {
+        let TestDb(ref db, _) = test_db();
+        let id = db.create_session("/home/me/proj", "bash", 80, 24, None).unwrap().id;
+        for data in ["$ make\r\n", "\rspin src/a.rs", "\rspin src/a.rs", "\rspin src/a.rs", "done\r\n"] {
+            db.add_event(&id, "pty_out", data).unwrap();
+        }
+        db.add_event(&id, "marker", "built").unwrap();
+        for _ in 0..3 {
+            db.add_event(&id, "pty_out", "tick\r\n").unwrap();
+        }
+        let marker_id = db.get_events_of_kind(&id, "marker").unwrap()[0].id.clone();
+
+        // What each reader sees, ignoring event ids (copies share the stored one)
+        let read = || {
+            let kinds_and_data =
+                |events: Vec<Event>| events.into_iter().map(|e| (e.kind, e.data)).collect::<Vec<_>>();
+            let search = db.fts_available().unwrap().then(|| kinds_and_data(db.search_events("spin", 10).unwrap()));
+            (
+                kinds_and_data(db.get_events(&id).unwrap()),
+                kinds_and_data(db.get_around_marker(&marker_id, 3, 2).unwrap()),
+                db.sample_output(&id, 4).unwrap(),
+                db.find_sessions_mentioning_path("src/a.rs").unwrap()[0].mentions,
+                search,
+                db.session_content_hash(&id, false).unwrap(),
+                db.session_content_hash(&id, true).unwrap(),
+            )
+        };
+
+        let before = read();
+        let report = db.collapse_repeats(&id).unwrap();
+        assert_eq!((report.runs_collapsed, report.events_removed), (2, 4));
+        assert_eq!(read(), before);
+    }
+
+    #[test]
+    fn test_normalize_timestamps_rederives() {
+        let TestDb(ref db, _) = test_db();
+        let id = db.create_session("/home/me/proj", "bash", 80, 24, None).unwrap().id;
+        db.create_command(&id, "make").unwrap();
+        db.add_event(&id, "pty_out", "built\r\n").unwrap();
+        db.end_command(&id, 0).unwrap();
+        let event = db.get_events(&id).unwrap().remove(0);
+        let command = db.get_commands(&id).unwrap().remove(0);
+        assert!(command.output_first_seq.is_some());
+
+        // As an import might leave it: the same instants written with an offset, a
+        // ts_ms that disagrees, and output ranges found with it
+        let offset = chrono::FixedOffset::east_opt(2 * 3600).unwrap();
+        let shifted = |ts: &str| chrono::DateTime::parse_from_rfc3339(ts).unwrap().with_timezone(&offset).to_rfc3339();
+        let conn = db.conn().unwrap();
+        conn.execute(
+            "UPDATE events SET ts = ?1, ts_ms = 0 WHERE id = ?2",
+            params![shifted(&event.ts), event.id],
+        )
+        .unwrap();
+        conn.execute("UPDATE commands SET output_first_seq = NULL, output_last_seq = NULL", []).unwrap();
+        conn.execute(
+            "UPDATE sessions SET last_activity_at = ?1 WHERE id = ?2",
+            params![shifted(&event.ts), id],
+        )
+        .unwrap();
+
+        let report = db.validate_timestamps(Some(&id)).unwrap();
+        assert_eq!((report.non_canonical, report.stale_ts_ms, report.rewritten), (2, 1, 0));
+        let report = db.normalize_timestamps(Some(&id)).unwrap();
+        assert_eq!(report.rewritten, 2);
+
+        let renormalized = db.get_events(&id).unwrap().remove(0);
+        assert_eq!((renormalized.ts, renormalized.ts_ms), (event.ts.clone(), event.ts_ms));
+        let recomputed = db.get_commands(&id).unwrap().remove(0);
+        assert_eq!(recomputed.output_first_seq, command.output_first_seq);
+        let last_activity: String = conn
+            .query_row("SELECT last_activity_at FROM sessions WHERE id = ?1", params![id], |row| row.get(0))
+            .unwrap();
+        assert_eq!(last_activity, event.ts);
+        assert_eq!(db.validate_timestamps(Some(&id)).unwrap().stale_ts_ms, 0);
+    }
+
+    #[test]
+    fn test_events_between_uses_time() {
+        let TestDb(ref db, _) = test_db();
+        let id = db.create_session("/home/me/proj", "bash", 80, 24, None).unwrap().id;
+        for data in ["before", "during", "after"] {
+            db.add_event(&id, "pty_out", data).unwrap();
+        }
+        let conn = db.conn().unwrap();
+        for (data, ms) in [("before", 1_000), ("during", 2_000), ("after", 3_001)] {
+            conn.execute("UPDATE events SET ts_ms = ?1 WHERE data = ?2", params![ms, data]).unwrap();
+        }
+        // The same instants as text that sorts differently from time
+        conn.execute("UPDATE events SET ts = '1970-01-01T02:00:02+02:00' WHERE data = 'during'", []).unwrap();
+        conn.execute("UPDATE events SET ts = '1970-01-01T00:00:03.001+00:00' WHERE data = 'after'", []).unwrap();
+
+        let data = |events: Vec<Event>| events.into_iter().map(|e| e.data).collect::<Vec<_>>();
+        assert_eq!(data(db.get_events_between(&id, 1_500, Some(3_000)).unwrap()), vec!["during"]);
+        assert_eq!(data(db.get_events_between(&id, 2_000, None).unwrap()), vec!["during", "after"]);
+    }
+
+    #[test]
+    fn test_normalize_session_order() {
+        let TestDb(ref db, _) = test_db();
+        let id = db.create_session("/home/me/proj", "bash", 80, 24, None).unwrap().id;
+        let other = db.create_session("/home/me/proj", "bash", 80, 24, None).unwrap().id;
+        for data in ["a", "b", "c", "d"] {
+            db.add_event(&id, "pty_out", data).unwrap();
+        }
+        db.add_event(&other, "pty_out", "x").unwrap();
+        let conn = db.conn().unwrap();
+        // A gap, a timeless event, and an out-of-order one
+        conn.execute("UPDATE events SET seq = seq + 10 WHERE session_id = ?1", params![id]).unwrap();
+        conn.execute("UPDATE events SET ts_ms = NULL WHERE data = 'a'", []).unwrap();
+        conn.execute("UPDATE events SET ts_ms = ts_ms - 60000 WHERE data = 'c'", []).unwrap();
+
+        assert_eq!(db.normalize_session(&id).unwrap(), 4);
+        let order: Vec<(String, Option<i64>)> = db.get_events(&id).unwrap().into_iter().map(|e| (e.data, e.seq)).collect();
+        let expected = [("c", 1), ("b", 2), ("d", 3), ("a", 4)].map(|(d, s)| (d.to_string(), Some(s)));
+        assert_eq!(order, expected);
+        assert_eq!(db.get_events(&other).unwrap()[0].seq, Some(1));
+    }
+
+    #[test]
+    fn test_closed_connection_signals_maintenance() {
+        let signal = Arc::new(Condvar::new());
+        let mut test_db = test_db();
+        test_db.0.set_maintenance_signal(signal.clone());
+        let db = Arc::new(std::sync::Mutex::new(test_db));
+
+        // Each change happens on another thread, which gets the lock once the wait releases it
+        let mut guard = db.lock().unwrap();
+        let changes: [fn(&mut Database); 2] = [
+            |db| {
+                db.conn().unwrap();
+            },
+            |db| db.queue_session_metadata("s", LAST_ACTIVITY_AT, "now".to_string()),
+        ];
+        for change in changes {
+            assert!(guard.0.close_if_idle(Duration::ZERO).unwrap());
+            let changer = {
+                let db = db.clone();
+                std::thread::spawn(move || change(&mut db.lock().unwrap().0))
+            };
+            let (woken, wait) = signal.wait_timeout(guard, Duration::from_secs(5)).unwrap();
+            assert!(!wait.timed_out());
+            drop(woken);
+            changer.join().unwrap();
+            guard = db.lock().unwrap();
+        }
+        assert!(guard.0.has_pending_metadata());
+    }
+}
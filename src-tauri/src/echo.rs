@@ -0,0 +1,98 @@
+// Dropping the tty's local echo of typed input from logged output
+// With echo on, every typed character comes back as output, so a transcript has it
+// twice: once as user_in and again inside pty_out. The filter remembers recently
+// sent text and removes it from the start of the output that follows.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::vt::{self, Token};
+
+// Echo arrives within a few ms; anything later is treated as ordinary output
+pub const ECHO_WINDOW: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Default)]
+pub struct EchoFilter {
+    pending: VecDeque<char>, // Sent characters whose echo hasn't been seen yet
+    last_input: Option<Instant>,
+}
+
+impl EchoFilter {
+    /// Remember input just written to the PTY
+    pub fn input(&mut self, data: &str) {
+        self.expire();
+        for token in vt::tokenize(data) {
+            match token {
+                Token::Text(text) => self.pending.extend(text.chars()),
+                Token::Control('\r') => self.pending.push_back('\r'),
+                // Editing keys, arrows and signals redraw rather than echo; stop matching
+                _ => self.pending.clear(),
+            }
+        }
+        self.last_input = Some(Instant::now());
+    }
+
+    /// `output` without the echo of pending input. Matching stops at the first
+    /// character that differs; escape sequences between echoed characters are kept.
+    pub fn filter(&mut self, output: &str) -> String {
+        self.expire();
+        if self.pending.is_empty() {
+            return output.to_string();
+        }
+
+        let mut kept = String::with_capacity(output.len());
+        let mut after_cr = false;
+        for (token, span) in vt::tokenize_spans(output) {
+            match token {
+                Token::Text(text) if !self.pending.is_empty() => {
+                    for (idx, ch) in text.char_indices() {
+                        if self.pending.front() == Some(&ch) {
+                            self.pending.pop_front();
+                        } else {
+                            self.pending.clear();
+                            kept.push_str(&text[idx..]);
+                            break;
+                        }
+                    }
+                }
+                Token::Control('\r') if self.pending.front() == Some(&'\r') => {
+                    self.pending.pop_front();
+                    after_cr = true;
+                    continue;
+                }
+                Token::Control('\n') if after_cr => {} // Enter echoes as \r\n
+                _ => kept.push_str(&output[span]),
+            }
+            after_cr = false;
+        }
+        kept
+    }
+
+    fn expire(&mut self) {
+        if self.last_input.is_some_and(|at| at.elapsed() > ECHO_WINDOW) {
+            self.pending.clear();
+            self.last_input = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter() {
+        let mut filter = EchoFilter::default();
+        filter.input("ls");
+        assert_eq!(filter.filter("l"), "");
+        filter.input("\r");
+        assert_eq!(filter.filter("\x1b[32ms\x1b[0m\r\nfile.txt\r\n"), "\x1b[32m\x1b[0mfile.txt\r\n");
+        assert_eq!(filter.filter("$ "), "$ ");
+
+        // Output that isn't the echo is left alone
+        filter.input("y");
+        assert_eq!(filter.filter("Password: "), "Password: ");
+        filter.input("\x1b[A");
+        assert_eq!(filter.filter("ls"), "ls");
+    }
+}
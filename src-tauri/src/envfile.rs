@@ -0,0 +1,111 @@
+// .env file parsing for per-session shell environments
+// Supports comments, `export` prefixes, and single/double quoted values
+
+use anyhow::{Context, Result};
+use std::path::Path;
+
+/// Load KEY=VALUE pairs from a dotenv-style file
+pub fn load(path: &Path) -> Result<Vec<(String, String)>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read env file {}", path.display()))?;
+    Ok(parse(&contents))
+}
+
+/// Parse dotenv contents; malformed lines are skipped
+pub fn parse(contents: &str) -> Vec<(String, String)> {
+    let mut vars = Vec::new();
+
+    for (lineno, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+
+        let Some((key, raw_value)) = line.split_once('=') else {
+            eprintln!("env file line {}: expected KEY=VALUE", lineno + 1);
+            continue;
+        };
+        let key = key.trim();
+        if !is_valid_key(key) {
+            eprintln!("env file line {}: invalid variable name {:?}", lineno + 1, key);
+            continue;
+        }
+
+        match parse_value(raw_value.trim()) {
+            Some(value) => vars.push((key.to_string(), value)),
+            None => eprintln!("env file line {}: unterminated quote", lineno + 1),
+        }
+    }
+
+    vars
+}
+
+fn is_valid_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_value(raw: &str) -> Option<String> {
+    if let Some(rest) = raw.strip_prefix('\'') {
+        // Single quotes are literal
+        let end = rest.find('\'')?;
+        return Some(rest[..end].to_string());
+    }
+
+    if let Some(rest) = raw.strip_prefix('"') {
+        let mut value = String::new();
+        let mut chars = rest.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => return Some(value),
+                '\\' => match chars.next()? {
+                    'n' => value.push('\n'),
+                    't' => value.push('\t'),
+                    'r' => value.push('\r'),
+                    other => value.push(other),
+                },
+                _ => value.push(c),
+            }
+        }
+        return None;
+    }
+
+    // Unquoted: a " #" starts an inline comment
+    let value = match raw.find(" #") {
+        Some(idx) => &raw[..idx],
+        None => raw,
+    };
+    Some(value.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let vars = parse(
+            "# comment\n\
+             PLAIN=value # trailing\n\
+             export EXPORTED=1\n\
+             SINGLE='no $expansion # here'\n\
+             DOUBLE=\"line\\nbreak \\\"quoted\\\"\"\n\
+             BAD KEY=x\n\
+             UNTERMINATED=\"oops\n\
+             EMPTY=\n",
+        );
+
+        assert_eq!(
+            vars,
+            vec![
+                ("PLAIN".to_string(), "value".to_string()),
+                ("EXPORTED".to_string(), "1".to_string()),
+                ("SINGLE".to_string(), "no $expansion # here".to_string()),
+                ("DOUBLE".to_string(), "line\nbreak \"quoted\"".to_string()),
+                ("EMPTY".to_string(), String::new()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,156 @@
+// "What went wrong" in a session: failed commands with the output they printed,
+// plus error-looking lines printed outside any failed command
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+use crate::db::{Command, Event};
+use crate::vt;
+
+// Keeps the report small enough to show in a panel
+pub const MAX_SNIPPET_CHARS: usize = 1500;
+// Shown instead when a failed command printed nothing error-looking
+const TAIL_LINES: usize = 10;
+
+// Whole words only, so "0 errors" and "no failures" don't match
+const ERROR_LINE_PATTERN: &str = r"(?i)\b(error|fatal|failed|failure|panicked|exception|traceback)\b";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionError {
+    pub command: Option<String>, // None for error lines outside failed commands
+    pub exit_code: Option<i32>,
+    pub started_at: Option<i64>, // Unix ms
+    pub error_output_snippet: String,
+}
+
+fn error_regex() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| Regex::new(ERROR_LINE_PATTERN).expect("invalid built-in error pattern"))
+}
+
+/// One entry per failed command in start order, then one for error lines printed
+/// outside them, if any. Output is matched to a command by its time window.
+pub fn session_errors(commands: &[Command], events: &[Event]) -> Vec<SessionError> {
+    let failed: Vec<&Command> = commands
+        .iter()
+        .filter(|c| c.exit_code.is_some_and(|code| code != 0))
+        .collect();
+    let mut outputs = vec![String::new(); failed.len()];
+    let mut outside = String::new();
+
+    for event in events.iter().filter(|e| e.kind == "pty_out") {
+        let ms = event.ts_ms.or_else(|| {
+            chrono::DateTime::parse_from_rfc3339(&event.ts).ok().map(|ts| ts.timestamp_millis())
+        });
+        let owner = ms.and_then(|ms| {
+            failed
+                .iter()
+                .rposition(|c| ms >= c.started_at && c.ended_at.is_none_or(|end| ms <= end))
+        });
+        match owner {
+            Some(idx) => outputs[idx].push_str(&event.data),
+            None => outside.push_str(&event.data),
+        }
+    }
+
+    let mut errors: Vec<SessionError> = failed
+        .iter()
+        .zip(outputs)
+        .map(|(command, output)| {
+            let text = vt::strip_ansi(&output);
+            let lines = visible_lines(&text);
+            let mut picked = error_lines(&lines);
+            if picked.is_empty() {
+                let printed: Vec<&str> = lines.iter().filter(|l| !l.is_empty()).copied().collect();
+                picked = printed[printed.len().saturating_sub(TAIL_LINES)..].to_vec();
+            }
+            SessionError {
+                command: command.input.clone(),
+                exit_code: command.exit_code,
+                started_at: Some(command.started_at),
+                error_output_snippet: snippet(&picked),
+            }
+        })
+        .collect();
+
+    let text = vt::strip_ansi(&outside);
+    let stray = error_lines(&visible_lines(&text));
+    if !stray.is_empty() {
+        errors.push(SessionError {
+            command: None,
+            exit_code: None,
+            started_at: None,
+            error_output_snippet: snippet(&stray),
+        });
+    }
+    errors
+}
+
+// Lines as displayed: a carriage return (progress bars) overwrites what came before it
+fn visible_lines(text: &str) -> Vec<&str> {
+    text.split('\n')
+        .map(|line| line.trim_end_matches('\r'))
+        .map(|line| line.rsplit('\r').next().unwrap_or(line).trim_end())
+        .collect()
+}
+
+fn error_lines<'a>(lines: &[&'a str]) -> Vec<&'a str> {
+    lines.iter().filter(|l| error_regex().is_match(l)).copied().collect()
+}
+
+fn snippet(lines: &[&str]) -> String {
+    let mut text = lines.join("\n");
+    if let Some((idx, _)) = text.char_indices().nth(MAX_SNIPPET_CHARS) {
+        text.truncate(idx);
+        text.push('…');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::event;
+
+    fn command(input: &str, exit_code: i32, started_at: i64, ended_at: i64) -> Command {
+        Command {
+            id: input.to_string(),
+            session_id: "s".to_string(),
+            input: Some(input.to_string()),
+            exit_code: Some(exit_code),
+            started_at,
+            ended_at: Some(ended_at),
+            output_first_seq: None,
+            output_last_seq: None,
+        }
+    }
+
+    fn output_at(ms: i64, data: &str) -> Event {
+        Event { ts_ms: Some(ms), ..event(&ms.to_string(), data) }
+    }
+
+    #[test]
+    fn test_session_errors() {
+        let commands = [
+            command("cargo build", 101, 1000, 2000),
+            command("ls", 0, 3000, 3100),
+            command("false", 1, 4000, 4100),
+        ];
+        let events = [
+            output_at(1500, "   Compiling app\r\n\x1b[31merror[E0425]\x1b[0m: cannot find value `x`\r\n"),
+            output_at(1600, "build finished with 0 errors? no: could not compile\r\n"),
+            output_at(3050, "Error: config missing\r\n"),
+            output_at(4050, "50%\r100%\r\n"),
+        ];
+        let errors = session_errors(&commands, &events);
+
+        assert_eq!(errors.len(), 3);
+        assert_eq!(errors[0].command.as_deref(), Some("cargo build"));
+        assert_eq!(errors[0].error_output_snippet, "error[E0425]: cannot find value `x`");
+        assert_eq!(errors[1].exit_code, Some(1));
+        assert_eq!(errors[1].error_output_snippet, "100%");
+        assert_eq!(errors[2].command, None);
+        assert_eq!(errors[2].error_output_snippet, "Error: config missing");
+    }
+}
@@ -0,0 +1,683 @@
+// Text and HTML export formats for sessions and commands
+
+use crate::db::{Command, Event, EventKindStats, ExportStats, Session};
+use crate::errors::SessionError;
+use crate::vt::{self, Color, Style, Token};
+
+pub const DEFAULT_CLEAR_SEPARATOR: &str = "--- screen cleared ---";
+
+/// Resolve the caller's separator choice: None = default, "" = no separator
+pub fn clear_separator(requested: Option<&str>) -> Option<&str> {
+    match requested {
+        None => Some(DEFAULT_CLEAR_SEPARATOR),
+        Some("") => None,
+        Some(text) => Some(text),
+    }
+}
+
+// Rough per-item framing costs for size estimates
+const CAST_HEADER_BYTES: u64 = 200;
+const CAST_LINE_BYTES: u64 = 24; // [12.345678, "o", "..."]\n
+const JSON_HEADER_BYTES: u64 = 600; // Envelope plus pretty-printed session object
+const JSON_EVENT_BYTES: u64 = 260; // Keys, ids and timestamps of one pretty-printed event
+const JSON_COMMAND_BYTES: u64 = 220;
+const HTML_WRAPPER_BYTES: u64 = 300;
+const HTML_SPAN_BYTES: u64 = 26; // <span class="fgN">...</span>
+const AVG_ESCAPE_BYTES: u64 = 5; // Typical SGR sequence, e.g. ESC[32m
+
+/// Approximate size in bytes of exporting a session as "json", "cast" or "html"
+pub fn estimate_size(stats: &ExportStats, format: &str) -> Result<u64, String> {
+    let kind = |k: &str| stats.by_kind.get(k).cloned().unwrap_or_default();
+    let escaped = |s: &EventKindStats| s.bytes + s.escapes * 5 + s.line_breaks;
+
+    match format {
+        "cast" => {
+            let (output, resize) = (kind("pty_out"), kind("resize"));
+            Ok(CAST_HEADER_BYTES
+                + (output.count + resize.count) * CAST_LINE_BYTES
+                + escaped(&output)
+                + resize.bytes)
+        }
+        "json" => {
+            let events: u64 = stats.by_kind.values().map(|s| s.count * JSON_EVENT_BYTES + escaped(s)).sum();
+            Ok(JSON_HEADER_BYTES + events + stats.command_count * JSON_COMMAND_BYTES)
+        }
+        "html" => {
+            // Escape sequences are dropped; each set/reset pair becomes roughly one span
+            let output = kind("pty_out");
+            let text = output.bytes.saturating_sub(output.escapes * AVG_ESCAPE_BYTES);
+            Ok(HTML_WRAPPER_BYTES + html_css().len() as u64 + text + output.escapes / 2 * HTML_SPAN_BYTES)
+        }
+        other => Err(format!("Unknown export format: {}", other)),
+    }
+}
+
+/// How text exports end lines
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEndings {
+    Lf,
+    Crlf,
+    Keep, // As recorded: bare \r, \r\n and \n mixed, carriage returns and line erases not applied
+}
+
+impl LineEndings {
+    /// Resolve the caller's choice: None = "lf"
+    pub fn parse(requested: Option<&str>) -> Result<LineEndings, String> {
+        match requested.unwrap_or("lf") {
+            "lf" => Ok(LineEndings::Lf),
+            "crlf" => Ok(LineEndings::Crlf),
+            "keep" => Ok(LineEndings::Keep),
+            other => Err(format!("Unsupported line endings: {}", other)),
+        }
+    }
+
+    fn newline(self) -> &'static str {
+        match self {
+            LineEndings::Crlf => "\r\n",
+            LineEndings::Lf | LineEndings::Keep => "\n",
+        }
+    }
+}
+
+// One line of output as a terminal leaves it: a carriage return moves back to
+// column 0 and what follows overwrites, so "50%\r100%" ends up "100%", and an erase
+// in line (CSI K) blanks what a shorter redraw wouldn't cover. Works on chars, so
+// multi-byte characters are never split.
+struct LineBuffer<T> {
+    cells: Vec<(char, T)>,
+    col: usize,
+    erased: bool, // Blanks from an erase may trail the line; take() trims them
+}
+
+impl<T: Copy> LineBuffer<T> {
+    fn new() -> Self {
+        LineBuffer { cells: Vec::new(), col: 0, erased: false }
+    }
+
+    fn put(&mut self, text: &str, attr: T) {
+        for ch in text.chars() {
+            match self.cells.get_mut(self.col) {
+                Some(cell) => *cell = (ch, attr),
+                None => self.cells.push((ch, attr)),
+            }
+            self.col += 1;
+        }
+    }
+
+    fn rewind(&mut self) {
+        self.col = 0;
+    }
+
+    // CSI K: 0 erases from the cursor to the end of the line, 1 from the start
+    // through the cursor, 2 the whole line. The cursor doesn't move.
+    fn erase(&mut self, params: &str, attr: T) {
+        let blank_through = match params {
+            "" | "0" => {
+                self.cells.truncate(self.col);
+                return;
+            }
+            "1" => self.col + 1,
+            "2" => self.cells.len(),
+            _ => return,
+        };
+        for cell in self.cells.iter_mut().take(blank_through) {
+            *cell = (' ', attr);
+        }
+        self.erased = true;
+    }
+
+    fn take(&mut self) -> Vec<(char, T)> {
+        let mut cells = std::mem::take(&mut self.cells);
+        if std::mem::take(&mut self.erased) {
+            while cells.last().is_some_and(|(ch, _)| *ch == ' ') {
+                cells.pop();
+            }
+        }
+        self.col = 0;
+        cells
+    }
+}
+
+/// Apply carriage-return overwrites and erase-in-line sequences, and end every line
+/// with `endings`. Keep returns the text unchanged apart from dropping the erases.
+pub fn normalize_line_endings(text: &str, endings: LineEndings) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut line = LineBuffer::new();
+    for (token, span) in vt::tokenize_spans(text) {
+        match token {
+            Token::Csi { params, final_byte: 'K' } => {
+                if endings != LineEndings::Keep {
+                    line.erase(params, ());
+                }
+            }
+            _ if endings == LineEndings::Keep => out.push_str(&text[span]),
+            Token::Control('\n') => {
+                out.extend(line.take().into_iter().map(|(ch, _)| ch));
+                out.push_str(endings.newline());
+            }
+            Token::Control('\r') => line.rewind(),
+            _ => line.put(&text[span], ()),
+        }
+    }
+    out.extend(line.take().into_iter().map(|(ch, _)| ch));
+    out
+}
+
+/// Human-readable duration for headers, e.g. "850ms", "12.3s", "4m 05s"
+pub fn format_duration(ms: i64) -> String {
+    if ms < 1000 {
+        format!("{}ms", ms)
+    } else if ms < 60_000 {
+        format!("{:.1}s", ms as f64 / 1000.0)
+    } else {
+        format!("{}m {:02}s", ms / 60_000, (ms % 60_000) / 1000)
+    }
+}
+
+/// A command's details as shown in export headers
+pub struct CommandSummary {
+    pub input: String,
+    pub exit_code: String, // "running" until it ends
+    pub duration: String, // "-" until it ends
+}
+
+pub fn command_summary(command: &Command) -> CommandSummary {
+    CommandSummary {
+        input: command.input.clone().unwrap_or_default(),
+        exit_code: command
+            .exit_code
+            .map(|c| c.to_string())
+            .unwrap_or_else(|| "running".to_string()),
+        duration: command
+            .ended_at
+            .map(|end| format_duration(end - command.started_at))
+            .unwrap_or_else(|| "-".to_string()),
+    }
+}
+
+/// Short "key: value" header describing a single command
+pub fn command_header(command: &Command) -> Vec<(&'static str, String)> {
+    let summary = command_summary(command);
+    vec![
+        ("Command", summary.input),
+        ("Exit code", summary.exit_code),
+        ("Duration", summary.duration),
+    ]
+}
+
+/// What a session is called in exports. Sessions have no stored name, so this is the
+/// one the session list shows: the last two components of its directory.
+pub fn session_name(session: &Session) -> String {
+    let components: Vec<&str> = session.cwd.split('/').collect();
+    components[components.len().saturating_sub(2)..].join("/")
+}
+
+/// Text transcript of one command: header, then its output with escapes stripped
+pub fn command_text(command: &Command, events: &[Event], separator: Option<&str>, endings: LineEndings) -> String {
+    let mut out = String::new();
+    for (key, value) in command_header(command) {
+        out.push_str(&format!("# {}: {}\n", key, value));
+    }
+    out.push('\n');
+
+    for event in events.iter().filter(|e| e.kind == "pty_out") {
+        push_plain(&mut out, &event.data, separator);
+    }
+    normalize_line_endings(&out, endings)
+}
+
+// Append output with escapes stripped, marking screen clears with a separator line.
+// Erases in line are kept for normalize_line_endings to apply.
+fn push_plain(out: &mut String, data: &str, separator: Option<&str>) {
+    for token in vt::tokenize(data) {
+        match token {
+            Token::Text(text) => out.push_str(text),
+            Token::Control(c @ ('\n' | '\r' | '\t')) => out.push(c),
+            Token::Csi { params, final_byte: 'K' } => out.push_str(&format!("\x1b[{}K", params)),
+            ref t if vt::is_clear(t) => {
+                if let Some(sep) = separator {
+                    push_separator(out, sep);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// Separator on its own line; back-to-back clears (e.g. ED 2 + ED 3) yield one
+fn push_separator(out: &mut String, separator: &str) {
+    let line = format!("{}\n", separator);
+    if out.ends_with(&line) {
+        return;
+    }
+    if !out.is_empty() && !out.ends_with('\n') {
+        out.push('\n');
+    }
+    out.push_str(&line);
+}
+
+/// The session's commands, in order, as a script for its shell. With
+/// `comment_failures`, commands that exited non-zero are kept but commented out.
+pub fn commands_script(session: &Session, commands: &[Command], comment_failures: bool) -> String {
+    let mut out = format!(
+        "#!{}\n# Commands from vibe session {}\n# Started {} in {}\n\ncd {}\n\n",
+        session.shell,
+        session.id,
+        session.started_at,
+        session.cwd,
+        shell_quote(&session.cwd)
+    );
+
+    for command in commands {
+        let input = command.input.as_deref().unwrap_or("").trim_end();
+        if input.is_empty() {
+            continue;
+        }
+        match command.exit_code {
+            Some(code) if code != 0 && comment_failures => {
+                out.push_str(&format!("# exited {}:\n", code));
+                for line in input.lines() {
+                    out.push_str(&format!("# {}\n", line));
+                }
+            }
+            _ => {
+                out.push_str(input);
+                out.push('\n');
+            }
+        }
+    }
+    out
+}
+
+/// Markdown report: session details, a table of commands, and with `failures` (from
+/// errors::session_errors) a fenced block of output for each failed command
+pub fn session_markdown(
+    session: &Session,
+    tags: &[String],
+    commands: &[Command],
+    failures: Option<&[SessionError]>,
+    endings: LineEndings,
+) -> String {
+    let duration = session.ended_at.as_deref().and_then(|ended_at| {
+        let start = chrono::DateTime::parse_from_rfc3339(&session.started_at).ok()?;
+        let end = chrono::DateTime::parse_from_rfc3339(ended_at).ok()?;
+        Some(format_duration((end - start).num_milliseconds()))
+    });
+    let mut details = vec![
+        ("Name", session_name(session)),
+        ("Started", session.started_at.clone()),
+        ("Duration", duration.unwrap_or_else(|| "still running".to_string())),
+        ("Directory", markdown_code(&session.cwd)),
+        ("Shell", markdown_code(&session.shell)),
+    ];
+    if !tags.is_empty() {
+        details.push(("Tags", tags.join(", ")));
+    }
+
+    let short_id = session.id.get(..8).unwrap_or(&session.id);
+    let mut out = format!("# Session {}\n\n| | |\n|---|---|\n", short_id);
+    for (key, value) in details {
+        out.push_str(&format!("| {} | {} |\n", key, markdown_cell(&value)));
+    }
+
+    out.push_str(&format!("\n## Commands ({})\n\n", commands.len()));
+    if commands.is_empty() {
+        out.push_str("No commands were recorded.\n");
+    } else {
+        out.push_str("| # | Command | Exit code | Duration |\n|---:|---|---:|---:|\n");
+        for (idx, command) in commands.iter().enumerate() {
+            let summary = command_summary(command);
+            out.push_str(&format!(
+                "| {} | {} | {} | {} |\n",
+                idx + 1,
+                markdown_cell(&markdown_code(summary.input.trim_end())),
+                summary.exit_code,
+                summary.duration
+            ));
+        }
+    }
+
+    let mut failed = failures.unwrap_or_default().iter().filter(|f| f.command.is_some()).peekable();
+    if failed.peek().is_some() {
+        out.push_str("\n## Failed commands\n");
+        for failure in failed {
+            let number = commands
+                .iter()
+                .position(|c| Some(c.started_at) == failure.started_at && c.input == failure.command)
+                .map(|idx| format!("{}. ", idx + 1))
+                .unwrap_or_default();
+            out.push_str(&format!(
+                "\n### {}{} exited {}\n\n",
+                number,
+                markdown_code(failure.command.as_deref().unwrap_or("").trim_end()),
+                failure.exit_code.map(|c| c.to_string()).unwrap_or_default()
+            ));
+            if failure.error_output_snippet.is_empty() {
+                out.push_str("No output.\n");
+            } else {
+                out.push_str(&markdown_fence(&failure.error_output_snippet));
+            }
+        }
+    }
+    normalize_line_endings(&out, endings)
+}
+
+// Inline code that survives backticks in the text; line breaks become spaces
+fn markdown_code(text: &str) -> String {
+    let text = text.replace(['\r', '\n'], " ");
+    if text.is_empty() {
+        return String::new();
+    }
+    let longest_run = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let ticks = "`".repeat(longest_run + 1);
+    let pad = if text.starts_with('`') || text.ends_with('`') { " " } else { "" };
+    format!("{ticks}{pad}{text}{pad}{ticks}")
+}
+
+// Table cells end at an unescaped pipe, even inside code spans
+fn markdown_cell(text: &str) -> String {
+    text.replace('|', "\\|")
+}
+
+// Fenced block longer than any backtick run inside it
+fn markdown_fence(text: &str) -> String {
+    let longest_run = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest_run.max(2) + 1);
+    format!("{fence}text\n{}\n{fence}\n", text.trim_end_matches('\n'))
+}
+
+/// Commands as shell history, for appending to ~/.zsh_history or ~/.bash_history.
+/// "zsh" is extended history (`: <start>:<seconds>;<command>`), metafied as zsh stores
+/// it. "bash" puts a `#<start>` line before each command, which bash reads back as its
+/// timestamp; backslash-newline continuations are joined, since bash would read each
+/// line as an entry of its own.
+pub fn shell_history(commands: &[Command], format: &str) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    for command in commands {
+        let input = command.input.as_deref().unwrap_or("").trim();
+        if input.is_empty() {
+            continue;
+        }
+        let started = command.started_at / 1000;
+        match format {
+            "zsh" => {
+                let elapsed = command.ended_at.map_or(0, |ended| (ended - command.started_at).max(0) / 1000);
+                // zsh continues an entry onto the next line after a trailing backslash
+                let entry = format!(": {}:{};{}\n", started, elapsed, input.replace('\n', "\\\n"));
+                zsh_metafy(&entry, &mut out);
+            }
+            "bash" => {
+                out.extend_from_slice(format!("#{}\n", started).as_bytes());
+                for line in join_continuations(input).lines() {
+                    // A line that looks like a timestamp would be read as one; the space
+                    // leaves a comment a comment
+                    if line.strip_prefix('#').is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_digit())) {
+                        out.push(b' ');
+                    }
+                    out.extend_from_slice(line.as_bytes());
+                    out.push(b'\n');
+                }
+            }
+            _ => return Err(format!("Unknown history format: {} (expected zsh or bash)", format)),
+        }
+    }
+    Ok(out)
+}
+
+// zsh's Meta byte. Its history file escapes NUL and the bytes it uses internally
+// (Meta through Marker, 0x83..=0xa2, which turn up inside UTF-8 characters) as Meta
+// followed by the byte xor 32.
+const ZSH_META: u8 = 0x83;
+const ZSH_MARKER: u8 = 0xa2;
+
+fn zsh_metafy(text: &str, out: &mut Vec<u8>) {
+    for &byte in text.as_bytes() {
+        if byte == 0 || (ZSH_META..=ZSH_MARKER).contains(&byte) {
+            out.extend_from_slice(&[ZSH_META, byte ^ 32]);
+        } else {
+            out.push(byte);
+        }
+    }
+}
+
+// Remove backslash-newline line continuations, as the shell does when reading them.
+// A backslash that is itself escaped (an even run) doesn't continue the line.
+fn join_continuations(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut lines = input.split('\n').peekable();
+    while let Some(line) = lines.next() {
+        let trailing = line.len() - line.trim_end_matches('\\').len();
+        if trailing % 2 == 1 && lines.peek().is_some() {
+            out.push_str(&line[..line.len() - 1]);
+        } else {
+            out.push_str(line);
+            if lines.peek().is_some() {
+                out.push('\n');
+            }
+        }
+    }
+    out
+}
+
+// Describe the shell that recorded them rather than the one they're sourced into
+const UNREPLAYED_ENV: &[&str] = &["PWD", "OLDPWD", "SHLVL", "_", "ZDOTDIR", "VIBE_NONCE", "VIBE_SESSION_ID"];
+
+/// A recorded env as `export KEY='value'` lines, ready to source into a shell
+pub fn env_exports(env: &[(String, String)]) -> String {
+    let mut out = String::new();
+    for (key, value) in env {
+        if UNREPLAYED_ENV.contains(&key.as_str()) {
+            continue;
+        }
+        out.push_str(&format!("export {}={}\n", key, shell_quote(value)));
+    }
+    out
+}
+
+// Single-quoted for sh, bash, zsh and fish
+pub fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+const HTML_CSS: &str = "\
+body { background: #1e1e1e; color: #e5e5e5; margin: 0; padding: 1.5em; }
+h1 { font: 600 14px system-ui, sans-serif; color: #9da5b4; margin: 0 0 1em; }
+pre { font: 13px/1.35 ui-monospace, Menlo, Consolas, monospace; margin: 0; white-space: pre-wrap; }
+.b { font-weight: bold; } .d { opacity: 0.6; } .i { font-style: italic; }
+.u { text-decoration: underline; } .s { text-decoration: line-through; }
+.u.s { text-decoration: underline line-through; }
+.input { background: #2d3a4a; }
+.clear { color: #6b7280; }
+";
+
+// Base stylesheet plus the 16-color ANSI palette as .fgN/.bgN classes
+fn html_css() -> String {
+    let mut css = HTML_CSS.to_string();
+    for i in 0..16u8 {
+        let hex = Color::Indexed(i).to_hex();
+        css.push_str(&format!(".fg{i} {{ color: {hex}; }} .bg{i} {{ background: {hex}; }}\n"));
+    }
+    css
+}
+
+/// Standalone HTML transcript with SGR colors/styles rendered as spans
+pub fn session_html(
+    session: &Session,
+    events: &[Event],
+    include_input: bool,
+    separator: Option<&str>,
+    endings: LineEndings,
+) -> String {
+    let mut body = String::new();
+    let mut style = Style::default();
+    // Styled cells of the line being drawn; markup goes out once it's complete
+    let mut line = LineBuffer::new();
+
+    for event in events {
+        match event.kind.as_str() {
+            "pty_out" => {
+                for token in vt::tokenize(&event.data) {
+                    match token {
+                        Token::Text(text) => line.put(text, style),
+                        Token::Control('\n') => {
+                            push_line(&mut body, &mut line);
+                            body.push('\n');
+                        }
+                        Token::Control('\r') if endings != LineEndings::Keep => line.rewind(),
+                        Token::Csi { params, final_byte: 'K' } if endings != LineEndings::Keep => {
+                            line.erase(params, style)
+                        }
+                        Token::Control('\t') => line.put("\t", style),
+                        Token::Csi { params, final_byte: 'm' } => style.apply_sgr(params),
+                        ref t if vt::is_clear(t) => {
+                            if let Some(sep) = separator {
+                                push_line(&mut body, &mut line);
+                                let sep = format!("<span class=\"clear\">{}</span>", escape_html(sep));
+                                push_separator(&mut body, &sep);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            "user_in" if include_input => {
+                let text = match event.display {
+                    Some(ref display) => display.clone(),
+                    None => vt::strip_ansi(&event.data).replace('\r', ""),
+                };
+                if !text.is_empty() {
+                    // Output after it can't overwrite what came before it
+                    push_line(&mut body, &mut line);
+                    body.push_str(&format!("<span class=\"input\">{}</span>", escape_html(&text)));
+                }
+            }
+            _ => {}
+        }
+    }
+    push_line(&mut body, &mut line);
+
+    let html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n\
+         <style>\n{css}</style>\n</head>\n<body>\n<h1>{title}</h1>\n<pre>{body}</pre>\n</body>\n</html>\n",
+        title = escape_html(&format!("{} — {}", session.cwd, session.started_at)),
+        css = html_css(),
+        body = body,
+    );
+    // No carriage returns are left, so this only picks the newline
+    normalize_line_endings(&html, endings)
+}
+
+// Emit a finished line's cells as spans, one per run of the same style
+fn push_line(out: &mut String, line: &mut LineBuffer<Style>) {
+    let cells = line.take();
+    let mut start = 0;
+    while start < cells.len() {
+        let style = cells[start].1;
+        let end = cells[start..].iter().position(|(_, s)| *s != style).map_or(cells.len(), |n| start + n);
+        let text: String = cells[start..end].iter().map(|(ch, _)| ch).collect();
+        push_span(out, &style, &text);
+        start = end;
+    }
+}
+
+fn push_span(out: &mut String, style: &Style, text: &str) {
+    if *style == Style::default() {
+        out.push_str(&escape_html(text));
+        return;
+    }
+
+    let (mut fg, mut bg) = (style.fg, style.bg);
+    if style.inverse {
+        // Swap, substituting the page colors for defaults
+        let (page_fg, page_bg) = (Color::Rgb(0xe5, 0xe5, 0xe5), Color::Rgb(0x1e, 0x1e, 0x1e));
+        (fg, bg) = (Some(bg.unwrap_or(page_bg)), Some(fg.unwrap_or(page_fg)));
+    }
+
+    let mut classes: Vec<String> = [
+        (style.bold, "b"),
+        (style.dim, "d"),
+        (style.italic, "i"),
+        (style.underline, "u"),
+        (style.strikethrough, "s"),
+    ]
+    .iter()
+    .filter(|(on, _)| *on)
+    .map(|(_, class)| class.to_string())
+    .collect();
+
+    // Palette colors use classes; 256-color and truecolor go inline
+    let mut css = Vec::new();
+    for (color, prefix, property) in [(fg, "fg", "color"), (bg, "bg", "background")] {
+        match color {
+            Some(Color::Indexed(i)) if i < 16 => classes.push(format!("{}{}", prefix, i)),
+            Some(c) => css.push(format!("{}:{}", property, c.to_hex())),
+            None => {}
+        }
+    }
+
+    out.push_str("<span");
+    if !classes.is_empty() {
+        out.push_str(&format!(" class=\"{}\"", classes.join(" ")));
+    }
+    if !css.is_empty() {
+        out.push_str(&format!(" style=\"{}\"", css.join(";")));
+    }
+    out.push('>');
+    out.push_str(&escape_html(text));
+    out.push_str("</span>");
+}
+
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shell_history() {
+        let command = |input: &str| Command {
+            id: "c".to_string(),
+            session_id: "s".to_string(),
+            input: Some(input.to_string()),
+            exit_code: Some(0),
+            started_at: 1_700_000_000_000,
+            ended_at: Some(1_700_000_002_500),
+            output_first_seq: None,
+            output_last_seq: None,
+        };
+        let commands = [command("echo café — ok"), command("make \\\n  all\n#1 first")];
+
+        let zsh = shell_history(&commands, "zsh").unwrap();
+        // "—" is E2 80 94; 0x80 is below Meta, 0x94 is escaped
+        let expected: Vec<u8> = [
+            &b": 1700000000:2;echo caf\xc3\xa9 \xe2\x80\x83\xb4 ok\n"[..],
+            &b": 1700000000:2;make \\\\\n  all\\\n#1 first\n"[..],
+        ]
+        .concat();
+        assert_eq!(zsh, expected);
+
+        let bash = shell_history(&commands, "bash").unwrap();
+        assert_eq!(
+            String::from_utf8(bash).unwrap(),
+            "#1700000000\necho café — ok\n#1700000000\nmake   all\n #1 first\n"
+        );
+    }
+
+    #[test]
+    fn test_line_erases() {
+        // A progress line redrawn shorter: without the erase "100%" would leave "10%%"
+        let text = "Downloading 100%\r\x1b[KDone\nbar\r\x1b[2Kbaz\nkeep\x1b[1Kx\n";
+        assert_eq!(normalize_line_endings(text, LineEndings::Lf), "Done\nbaz\n    x\n");
+        assert_eq!(normalize_line_endings("é100%\r\x1b[0K€9%", LineEndings::Crlf), "€9%");
+        assert_eq!(normalize_line_endings(text, LineEndings::Keep), "Downloading 100%\rDone\nbar\rbaz\nkeepx\n");
+
+        let mut plain = String::new();
+        push_plain(&mut plain, "\x1b[32m 99%\r\x1b[K\x1b[0m5%\n", None);
+        assert_eq!(normalize_line_endings(&plain, LineEndings::Lf), "5%\n");
+    }
+}
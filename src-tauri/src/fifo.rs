@@ -0,0 +1,106 @@
+// Named pipes that let external tools type into a live session (Unix only)
+// e.g. `echo 'make test' > /run/user/1000/vibe/<session>.input`
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Directory for session FIFOs: $XDG_RUNTIME_DIR/vibe, else ~/.vibecodings/fifo
+pub fn runtime_dir() -> Result<PathBuf> {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => Ok(PathBuf::from(dir).join("vibe")),
+        None => {
+            let home = std::env::var("HOME").context("HOME not set")?;
+            Ok(PathBuf::from(home).join(".vibecodings/fifo"))
+        }
+    }
+}
+
+/// A FIFO plus the thread forwarding what's written to it. Dropping it stops the
+/// thread and removes the FIFO.
+pub struct InputFifo {
+    pub path: PathBuf,
+    stop: Arc<AtomicBool>,
+}
+
+impl InputFifo {
+    /// Create the FIFO (owner-only) and forward each chunk written to it to
+    /// `on_data`, until it returns false or the InputFifo is dropped
+    #[cfg(unix)]
+    pub fn create<F>(session_id: &str, path: &Path, mut on_data: F) -> Result<InputFifo>
+    where
+        F: FnMut(Vec<u8>) -> bool + Send + 'static,
+    {
+        use std::ffi::CString;
+        use std::io::Read;
+        use std::os::unix::ffi::OsStrExt;
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).context("Failed to create FIFO directory")?;
+        }
+        let c_path = CString::new(path.as_os_str().as_bytes()).context("Invalid FIFO path")?;
+        if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("Failed to create FIFO {}", path.display()));
+        }
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = Arc::clone(&stop);
+        let thread_path = path.to_path_buf();
+        crate::threads::spawn("input fifo", Some(session_id.to_string()), move || {
+            let mut buf = [0u8; 4096];
+            // Each open blocks until a writer appears and reads to EOF when the last one closes
+            while !thread_stop.load(Ordering::Relaxed) {
+                let Ok(mut fifo) = std::fs::File::open(&thread_path) else {
+                    break;
+                };
+                loop {
+                    match fifo.read(&mut buf) {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => {
+                            if thread_stop.load(Ordering::Relaxed) || !on_data(buf[..n].to_vec()) {
+                                return;
+                            }
+                        }
+                    }
+                }
+            }
+        });
+
+        Ok(InputFifo {
+            path: path.to_path_buf(),
+            stop,
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn create<F>(_session_id: &str, _path: &Path, _on_data: F) -> Result<InputFifo>
+    where
+        F: FnMut(Vec<u8>) -> bool + Send + 'static,
+    {
+        anyhow::bail!("Input FIFOs are only supported on Unix")
+    }
+}
+
+impl Drop for InputFifo {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+
+        // Wake a thread blocked opening the FIFO: a non-blocking writer open succeeds
+        // only while a reader waits, and closing it right away gives that reader EOF.
+        // Not joined: the thread may be waiting on the session lock the caller holds.
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            let _ = std::fs::OpenOptions::new()
+                .write(true)
+                .custom_flags(libc::O_NONBLOCK)
+                .open(&self.path);
+        }
+
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            eprintln!("Failed to remove FIFO {}: {}", self.path.display(), e);
+        }
+    }
+}
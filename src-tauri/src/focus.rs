@@ -0,0 +1,155 @@
+// Program-scoped spans ("subsessions") inside a shell session, for focus mode: from
+// when a program takes the terminal's foreground until it hands it back to the shell.
+// Where the foreground can't be seen, switching to the alternate screen and back
+// bounds the span instead. Logged as markers; get_subsessions pairs them up.
+
+use serde::{Deserialize, Serialize};
+
+use crate::db::Event;
+use crate::screen;
+use crate::vt;
+
+pub const SUBSESSION_MARKER_PREFIX: &str = "program: ";
+// Name for a span seen only through the alternate screen
+const ALT_SCREEN_PROGRAM: &str = "full-screen app";
+
+/// Who holds the terminal's foreground, as far as the platform tells
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Foreground {
+    Unknown,
+    Shell,
+    Program(i32), // Process group id
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Subsession {
+    pub program: String,
+    pub started_at: String,
+    pub ended_at: Option<String>, // None if the session ended (or focus mode was turned off) first
+    pub duration_ms: Option<i64>,
+}
+
+struct Span {
+    program: String,
+    pgid: Option<i32>, // None when only the alternate screen revealed it
+}
+
+#[derive(Default)]
+pub struct FocusTracker {
+    current: Option<Span>,
+}
+
+impl FocusTracker {
+    /// Markers for spans that ended or began, given the foreground after a chunk of
+    /// output. `name_of` looks up a process group leader's name for a new span.
+    pub fn observe<F>(&mut self, foreground: Foreground, output: &str, name_of: F) -> Vec<String>
+    where
+        F: Fn(i32) -> Option<String>,
+    {
+        let mut markers = Vec::new();
+        match foreground {
+            Foreground::Program(pgid) if self.current.as_ref().and_then(|s| s.pgid) != Some(pgid) => {
+                markers.extend(self.finish());
+                let program = name_of(pgid).unwrap_or_else(|| format!("pid {}", pgid));
+                markers.push(format!("{}start {}", SUBSESSION_MARKER_PREFIX, program));
+                self.current = Some(Span { program, pgid: Some(pgid) });
+            }
+            Foreground::Shell if self.current.as_ref().is_some_and(|s| s.pgid.is_some()) => {
+                markers.extend(self.finish());
+            }
+            _ => {}
+        }
+
+        for token in vt::tokenize(output) {
+            match screen::alt_screen_switch(&token) {
+                Some(true) if self.current.is_none() => {
+                    markers.push(format!("{}start {}", SUBSESSION_MARKER_PREFIX, ALT_SCREEN_PROGRAM));
+                    self.current = Some(Span { program: ALT_SCREEN_PROGRAM.to_string(), pgid: None });
+                }
+                Some(false) if self.current.as_ref().is_some_and(|s| s.pgid.is_none()) => {
+                    markers.extend(self.finish());
+                }
+                _ => {}
+            }
+        }
+        markers
+    }
+
+    /// End marker for the open span, if any, e.g. when the session ends
+    pub fn finish(&mut self) -> Option<String> {
+        self.current
+            .take()
+            .map(|span| format!("{}exit {}", SUBSESSION_MARKER_PREFIX, span.program))
+    }
+}
+
+/// Spans from a session's markers, in start order
+pub fn subsessions(markers: &[Event]) -> Vec<Subsession> {
+    let mut spans: Vec<Subsession> = Vec::new();
+    let mut open: Option<(usize, Option<i64>)> = None; // Index in spans, start ms
+    for marker in markers {
+        let Some(change) = marker.data.strip_prefix(SUBSESSION_MARKER_PREFIX) else {
+            continue;
+        };
+        if let Some(program) = change.strip_prefix("start ") {
+            open = Some((spans.len(), marker.ts_ms));
+            spans.push(Subsession {
+                program: program.to_string(),
+                started_at: marker.ts.clone(),
+                ended_at: None,
+                duration_ms: None,
+            });
+        } else if change.starts_with("exit ") {
+            if let Some((idx, start_ms)) = open.take() {
+                spans[idx].ended_at = Some(marker.ts.clone());
+                spans[idx].duration_ms = start_ms.zip(marker.ts_ms).map(|(start, end)| end - start);
+            }
+        }
+    }
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::event;
+
+    fn marker(ms: i64, data: &str) -> Event {
+        Event {
+            ts: format!("t{}", ms),
+            kind: "marker".to_string(),
+            ts_ms: Some(ms),
+            ..event(&ms.to_string(), data)
+        }
+    }
+
+    #[test]
+    fn test_focus_tracker() {
+        let name_of = |pgid: i32| (pgid == 7).then(|| "vim".to_string());
+        let mut tracker = FocusTracker::default();
+        assert!(tracker.observe(Foreground::Shell, "$ vim\r\n", name_of).is_empty());
+        assert_eq!(tracker.observe(Foreground::Program(7), "\x1b[?1049h~", name_of), vec!["program: start vim"]);
+        assert!(tracker.observe(Foreground::Program(7), "\x1b[?1049l", name_of).is_empty());
+        assert_eq!(
+            tracker.observe(Foreground::Program(9), "", name_of),
+            vec!["program: exit vim", "program: start pid 9"]
+        );
+        assert_eq!(tracker.observe(Foreground::Shell, "$ ", name_of), vec!["program: exit pid 9"]);
+
+        // Without foreground information the alternate screen bounds the span
+        assert_eq!(tracker.observe(Foreground::Unknown, "\x1b[?1049h", name_of), vec!["program: start full-screen app"]);
+        assert_eq!(tracker.observe(Foreground::Unknown, "\x1b[?1049l$ ", name_of), vec!["program: exit full-screen app"]);
+        assert_eq!(tracker.finish(), None);
+
+        let markers = [
+            marker(1000, "program: start vim"),
+            marker(1500, "mode: bracketed paste on"),
+            marker(4000, "program: exit vim"),
+            marker(5000, "program: start top"),
+        ];
+        let spans = subsessions(&markers);
+        assert_eq!(spans.len(), 2);
+        assert_eq!((spans[0].program.as_str(), spans[0].duration_ms), ("vim", Some(3000)));
+        assert_eq!((spans[1].program.as_str(), spans[1].ended_at.as_deref()), ("top", None));
+    }
+}
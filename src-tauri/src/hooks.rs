@@ -0,0 +1,53 @@
+// User-configured hook commands run on session lifecycle events
+// Hooks run through the platform shell; their output is summarized for the event log
+
+use anyhow::{Context, Result};
+use std::process::Command;
+
+// Keep hook output markers readable in the transcript
+const MAX_OUTPUT_CHARS: usize = 2000;
+
+/// Run the on_session_end hook: `<command> <session_id> <exit_code>`, with the same
+/// values in VIBE_SESSION_ID / VIBE_EXIT_CODE. Returns a marker line describing the run.
+pub fn run_session_end(command: &str, session_id: &str, exit_code: Option<i32>) -> Result<String> {
+    let exit_code = exit_code.map(|c| c.to_string()).unwrap_or_default();
+
+    let mut cmd = if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C").arg(format!("{} {} {}", command, session_id, exit_code));
+        cmd
+    } else {
+        // "$@" carries the arguments, so the command string needs no quoting
+        let mut cmd = Command::new("/bin/sh");
+        cmd.arg("-c")
+            .arg(format!("{} \"$@\"", command))
+            .arg("vibe-hook")
+            .arg(session_id)
+            .arg(&exit_code);
+        cmd
+    };
+
+    let output = cmd
+        .env("VIBE_SESSION_ID", session_id)
+        .env("VIBE_EXIT_CODE", &exit_code)
+        .output()
+        .with_context(|| format!("Failed to run on_session_end hook {:?}", command))?;
+
+    let mut text = String::from_utf8_lossy(&output.stdout).to_string();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    let text = text.trim();
+    let text = match text.char_indices().nth(MAX_OUTPUT_CHARS) {
+        Some((idx, _)) => format!("{}…", &text[..idx]),
+        None => text.to_string(),
+    };
+
+    let status = match output.status.code() {
+        Some(code) => format!("exited with {}", code),
+        None => "was terminated by a signal".to_string(),
+    };
+    Ok(if text.is_empty() {
+        format!("on_session_end hook {}", status)
+    } else {
+        format!("on_session_end hook {}: {}", status, text)
+    })
+}
@@ -0,0 +1,24 @@
+// Shell rc snippets for manual shell integration
+// Users who can't let vibe manage ZDOTDIR paste these into their own dotfiles;
+// they read VIBE_NONCE from the environment vibe gives every session's shell
+
+use std::path::Path;
+
+const ZSH_SNIPPET: &str = include_str!("../../shell-integration/snippet.zsh");
+const BASH_SNIPPET: &str = include_str!("../../shell-integration/snippet.bash");
+const FISH_SNIPPET: &str = include_str!("../../shell-integration/snippet.fish");
+
+/// rc snippet for a shell name or path ("zsh", "/bin/bash"), if supported
+pub fn snippet(shell: &str) -> Option<&'static str> {
+    let name = Path::new(shell.trim())
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+
+    match name {
+        "zsh" => Some(ZSH_SNIPPET),
+        "bash" => Some(BASH_SNIPPET),
+        "fish" => Some(FISH_SNIPPET),
+        _ => None,
+    }
+}
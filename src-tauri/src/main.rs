@@ -1,211 +1,2372 @@
 // Prevents additional console window on Windows in release
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod abbrev;
+mod agg;
+mod autotag;
+mod cast;
+mod context;
 mod db;
+mod echo;
+mod envfile;
+mod errors;
+mod export;
+mod fifo;
+mod focus;
+mod hooks;
+mod integration;
+mod mirror;
 mod osc;
+mod paths;
 mod pty;
+mod screen;
+mod secrets;
+mod settings;
+mod signing;
+#[cfg(test)]
+mod test_util;
+mod threads;
+mod urls;
+mod vt;
+mod ws;
 mod zdotdir;
 
 use db::Database;
 use osc::OscEvent;
 use pty::PtySession;
-use std::sync::Mutex;
-use tauri::State;
+use base64::{engine::general_purpose, Engine as _};
+use bytes::Bytes;
+use crossbeam_channel::{RecvTimeoutError, Sender};
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+use tauri::{Manager, State};
 
 struct AppState {
     db: Mutex<Database>,
     pty: Mutex<Option<PtySession>>,
     current_command_id: Mutex<Option<String>>,
+    allow_clipboard_write: Mutex<bool>, // Whether the frontend may honor OSC 52
+    recording_notice_pending: Mutex<bool>, // Show "recording is off" once in the terminal
+    replays: Mutex<HashMap<String, SteppedReplay>>, // Stepped replays by replay id
+    diff_capture: Mutex<Option<screen::DiffCapture>>, // Live session's alt-screen logging, if diff_alt_screen is on
+    input_modes: Mutex<Option<vt::ModeTracker>>, // Live session's input modes while log_mode_changes is on
+    focus: Mutex<Option<focus::FocusTracker>>, // Live session's foreground program, if focus_mode is on
+    runtime_limit: Mutex<Option<Sender<()>>>, // Dropped to stop the live session's runtime limit timer
+    // Paired with `db`; wakes database maintenance threads when their settings change, work
+    // piles up, or the idle-closed connection is needed again (Database notifies it then)
+    db_maintenance: Arc<Condvar>,
+}
+
+// A session's recorded commands being fed one at a time into a fresh shell
+struct SteppedReplay {
+    session_id: String, // The new live session the commands go to
+    source_session_id: String,
+    commands: Vec<String>,
+    next: usize,
+}
+
+#[derive(serde::Serialize)]
+struct SteppedReplayStatus {
+    replay_id: String,
+    session_id: String,
+    source_session_id: String,
+    sent: usize,
+    total: usize,
+    next_command: Option<String>, // None once every command has been sent
+}
+
+impl SteppedReplay {
+    fn status(&self, replay_id: &str) -> SteppedReplayStatus {
+        SteppedReplayStatus {
+            replay_id: replay_id.to_string(),
+            session_id: self.session_id.clone(),
+            source_session_id: self.source_session_id.clone(),
+            sent: self.next,
+            total: self.commands.len(),
+            next_command: self.commands.get(self.next).cloned(),
+        }
+    }
+}
+
+const RECORDING_OFF_NOTICE: &[u8] =
+    b"\r\n\x1b[33m[vibe] Recording is off: nothing from this session is being saved\x1b[0m\r\n";
+
+// Why a session couldn't start, structured so the frontend can tell running out of
+// PTYs apart from other failures
+#[derive(serde::Serialize)]
+struct StartSessionError {
+    kind: &'static str, // "exhausted" (out of PTYs or file descriptors) or "other"
+    errno: Option<i32>, // From opening the PTY, when known
+    attempts: Option<u32>, // PTY opens tried, when opening one failed
+    message: String,
+}
+
+impl From<String> for StartSessionError {
+    fn from(message: String) -> Self {
+        StartSessionError { kind: "other", errno: None, attempts: None, message }
+    }
+}
+
+// `max_runtime_secs` ends the session that long after it starts, however busy it is
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+fn start_session(
+    app: tauri::AppHandle,
+    state: State<AppState>,
+    cols: u16,
+    rows: u16,
+    shell: Option<String>,
+    env_file: Option<String>,
+    tee_to: Option<String>,
+    prompt: Option<String>,
+    max_runtime_secs: Option<u64>,
+    pixel_width: Option<u16>,
+    pixel_height: Option<u16>,
+) -> Result<String, StartSessionError> {
+    let cwd = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("/"));
+
+    // Optional .env (relative paths resolve against the session cwd)
+    let env_path = env_file.map(|f| cwd.join(f));
+    let env = match env_path {
+        Some(ref path) => envfile::load(path).map_err(|e| format!("{:#}", e))?,
+        None => Vec::new(),
+    };
+
+    // Optional live log of raw output, resolved the same way
+    let tee_file = match tee_to {
+        Some(ref f) => Some(pty::open_tee(&cwd.join(f)).map_err(|e| format!("{:#}", e))?),
+        None => None,
+    };
+
+    let launch = SessionLaunch {
+        cwd,
+        shell,
+        env,
+        env_file: env_path.map(|p| p.to_string_lossy().to_string()),
+        cols,
+        rows,
+        pixel_width: pixel_width.unwrap_or(0),
+        pixel_height: pixel_height.unwrap_or(0),
+        tee_file,
+        parent_session_id: None,
+        prompt: prompt.filter(|p| !p.is_empty()),
+    };
+    let session_id = launch_session(&state, launch)?;
+    if let Some(secs) = max_runtime_secs.filter(|s| *s > 0) {
+        spawn_runtime_limit(app, session_id.clone(), Duration::from_secs(secs));
+    }
+    Ok(session_id)
+}
+
+// Kill and end the session once `limit` has passed, if it's still the live one. The
+// timer stops early when the session ends or is replaced.
+fn spawn_runtime_limit(app: tauri::AppHandle, session_id: String, limit: Duration) {
+    let (stop_tx, stop_rx) = crossbeam_channel::bounded::<()>(0);
+    *app.state::<AppState>().runtime_limit.lock().unwrap() = Some(stop_tx);
+    threads::spawn("runtime limit", Some(session_id.clone()), move || {
+        // Nothing is ever sent: the sender being dropped is the signal
+        if stop_rx.recv_timeout(limit) != Err(RecvTimeoutError::Timeout) {
+            return;
+        }
+        let state = app.state::<AppState>();
+        let mut pty = state.pty.lock().unwrap();
+        let session = match pty.take() {
+            Some(session) if session.session_id == session_id => session,
+            other => {
+                *pty = other; // Ended already; leave whatever is live now alone
+                return;
+            }
+        };
+        drop(pty); // Dropping the session joins its threads, which mustn't hold up others
+        if let Err(e) = session.kill() {
+            eprintln!("{:#}", e);
+        }
+        {
+            let db = state.db.lock().unwrap();
+            let note = format!("Session reached its maximum runtime of {}s and was terminated", limit.as_secs());
+            db.add_event(&session_id, "marker", &note).ok();
+        }
+        if let Err(e) = finish_session(&app, &state, &session.session_id) {
+            eprintln!("{}", e);
+        }
+    });
+}
+
+// Everything needed to record and spawn a new session
+struct SessionLaunch {
+    cwd: PathBuf,
+    shell: Option<String>,
+    env: Vec<(String, String)>,
+    env_file: Option<String>,
+    cols: u16,
+    rows: u16,
+    pixel_width: u16, // Canvas size for TIOCGWINSZ; 0 when not known
+    pixel_height: u16,
+    tee_file: Option<File>,
+    parent_session_id: Option<String>,
+    prompt: Option<String>, // Synthetic prompt (e.g. "$ ") in place of the user's, for clean recordings
+}
+
+// Shared spawn path: create the session row, start its shell, make it the live session
+fn launch_session(state: &AppState, launch: SessionLaunch) -> Result<String, StartSessionError> {
+    // Create session in database
+    let db = state.db.lock().unwrap();
+
+    // A recorded shell may have been uninstalled since; fall back rather than fail
+    let configured_shell = db.get_setting(settings::DEFAULT_SHELL).ok();
+    let requested_shell = launch
+        .shell
+        .as_deref()
+        .or_else(|| configured_shell.as_ref().and_then(|v| v.as_str()));
+    let (shell, substitution) = pty::resolve_shell(requested_shell);
+    let requested_shell = substitution.as_ref().and(requested_shell.map(str::to_string));
+
+    let session = db
+        .create_session(
+            &launch.cwd.to_string_lossy(),
+            &shell,
+            launch.cols,
+            launch.rows,
+            launch.parent_session_id.as_deref(),
+        )
+        .map_err(|e| format!("Failed to create session: {}", e))?;
+
+    let session_id = session.id.clone();
+
+    if let Some(note) = substitution {
+        db.add_event(&session_id, "marker", &note).ok();
+    }
+    if let Some(ref env_file) = launch.env_file {
+        db.set_session_env(&session_id, env_file, &launch.env)
+            .map_err(|e| format!("Failed to record env file: {}", e))?;
+    }
+
+    // The prompt goes to the shell only; the recorded env snapshot stays as loaded.
+    // zsh applies VIBE_PROMPT from the integration rc after the user's config. bash
+    // resets PS1 in PROMPT_COMMAND, since rc files usually set PS1 (an rc that
+    // replaces PROMPT_COMMAND still wins); plain sh just takes PS1.
+    let mut spawn_env = launch.env.clone();
+    if let Some(ref prompt) = launch.prompt {
+        spawn_env.push(("VIBE_PROMPT".to_string(), prompt.clone()));
+        spawn_env.push(("PS1".to_string(), prompt.clone()));
+        spawn_env.push(("PROMPT_COMMAND".to_string(), "PS1=\"$VIBE_PROMPT\"".to_string()));
+    }
+
+    // Create PTY
+    let tee = launch.tee_file.is_some();
+    let pty_session = PtySession::new(
+        session_id.clone(),
+        &shell,
+        &launch.cwd,
+        &spawn_env,
+        pty::PtySize {
+            cols: launch.cols,
+            rows: launch.rows,
+            pixel_width: launch.pixel_width,
+            pixel_height: launch.pixel_height,
+        },
+        launch.tee_file,
+        read_tuning(&db),
+    )
+    .map_err(|e| {
+        let message = format!("Failed to create PTY: {}", e);
+        match e.downcast_ref::<pty::PtyOpenError>() {
+            Some(open) => StartSessionError {
+                kind: if open.exhausted { "exhausted" } else { "other" },
+                errno: open.errno,
+                attempts: Some(open.attempts),
+                message,
+            },
+            None => message.into(),
+        }
+    })?;
+    pty_session.set_idle_gap_threshold(idle_gap_threshold(&db));
+    pty_session.set_echo_suppression(suppress_echo(&db));
+    pty_session.set_abbreviations(abbreviations(&db));
+    let diff_alt_screen = db.get_setting(settings::DIFF_ALT_SCREEN).ok().and_then(|v| v.as_bool()) == Some(true);
+    *state.diff_capture.lock().unwrap() =
+        diff_alt_screen.then(|| screen::DiffCapture::new(launch.cols, launch.rows));
+    *state.input_modes.lock().unwrap() = log_mode_changes(&db).then(vt::ModeTracker::default);
+    *state.focus.lock().unwrap() = focus_mode(&db).then(focus::FocusTracker::default);
+
+    let record = db::SessionLaunchRecord {
+        argv: pty_session.argv.clone(),
+        cwd: launch.cwd.to_string_lossy().to_string(),
+        cols: launch.cols,
+        rows: launch.rows,
+        requested_shell,
+        env_file: launch.env_file,
+        tee,
+        prompt: launch.prompt,
+    };
+    db.set_session_launch(&session_id, &record)
+        .map_err(|e| format!("Failed to record launch: {}", e))?;
+    drop(db); // Locks are taken PTY first, then DB
+
+    // Any session it replaces is dropped (joining its threads) after the lock is released
+    let _replaced = state.pty.lock().unwrap().replace(pty_session);
+    state.runtime_limit.lock().unwrap().take();
+
+    Ok(session_id)
+}
+
+// Silence after which resumed output gets an "idle gap" marker; None when disabled
+fn idle_gap_threshold(db: &Database) -> Option<Duration> {
+    db.get_setting(settings::IDLE_MARKER_MINUTES)
+        .ok()
+        .and_then(|v| v.as_u64())
+        .and_then(|minutes| minutes.checked_mul(60))
+        .map(Duration::from_secs)
+}
+
+fn suppress_echo(db: &Database) -> bool {
+    db.get_setting(settings::SUPPRESS_ECHO).ok().and_then(|v| v.as_bool()) == Some(true)
+}
+
+fn abbreviations(db: &Database) -> HashMap<String, String> {
+    db.get_setting(settings::ABBREVIATIONS)
+        .ok()
+        .and_then(|v| abbrev::from_setting(&v).ok())
+        .unwrap_or_default()
+}
+
+fn focus_mode(db: &Database) -> bool {
+    db.get_setting(settings::FOCUS_MODE).ok().and_then(|v| v.as_bool()) == Some(true)
+}
+
+fn log_mode_changes(db: &Database) -> bool {
+    db.get_setting(settings::LOG_MODE_CHANGES).ok().and_then(|v| v.as_bool()) == Some(true)
+}
+
+// Reader tuning from settings; values were range-checked when saved
+fn read_tuning(db: &Database) -> pty::ReadTuning {
+    let number = |key: &str| db.get_setting(key).ok().and_then(|v| v.as_u64());
+    let defaults = pty::ReadTuning::default();
+    let read_bytes = number(settings::READ_BUFFER_BYTES).map_or(defaults.read_bytes, |n| n as usize);
+    pty::ReadTuning {
+        read_bytes,
+        coalesce: number(settings::OUTPUT_COALESCE_MS).map_or(defaults.coalesce, Duration::from_millis),
+        // A batch always fits at least one read
+        batch_bytes: number(settings::OUTPUT_BATCH_BYTES)
+            .map_or(defaults.batch_bytes, |n| n as usize)
+            .max(read_bytes),
+    }
+}
+
+// Open the session's working directory in the platform file manager. Only a
+// recorded session's cwd can be opened, so the webview's shell scope stays limited
+// to URLs.
+#[tauri::command]
+fn reveal_session_cwd(state: State<AppState>, session_id: String) -> Result<(), String> {
+    let cwd = {
+        let db = state.db.lock().unwrap();
+        db.get_session(&session_id)
+            .map_err(|e| format!("Failed to get session: {}", e))?
+            .ok_or("Session not found")?
+            .cwd
+    };
+
+    let dir = std::path::Path::new(&cwd);
+    if !dir.is_absolute() || !dir.is_dir() {
+        return Err(format!("Directory no longer exists: {}", cwd));
+    }
+    let dir = dir
+        .canonicalize()
+        .map_err(|e| format!("Failed to resolve {}: {}", cwd, e))?;
+
+    let opener = if cfg!(target_os = "macos") {
+        "open"
+    } else if cfg!(windows) {
+        "explorer"
+    } else {
+        "xdg-open"
+    };
+    let mut child = std::process::Command::new(opener)
+        .arg(&dir)
+        .spawn()
+        .map_err(|e| format!("Failed to open directory: {}", e))?;
+    // Reaped off the command thread: xdg-open can linger, and explorer's exit code
+    // doesn't mean failure
+    threads::spawn("file manager", None, move || child.wait().ok());
+    Ok(())
+}
+
+#[tauri::command]
+fn get_session_tree(state: State<AppState>, root_id: String) -> Result<db::SessionTree, String> {
+    let db = state.db.lock().unwrap();
+    db.get_session_tree(&root_id)
+        .map_err(|e| format!("Failed to get session tree: {}", e))
+}
+
+#[tauri::command]
+fn set_session_template(state: State<AppState>, session_id: String, is_template: bool) -> Result<(), String> {
+    let db = state.db.lock().unwrap();
+    db.set_session_template(&session_id, is_template)
+        .map_err(|e| format!("Failed to update session: {}", e))
+}
+
+#[tauri::command]
+fn set_favorite(state: State<AppState>, session_id: String, favorite: bool) -> Result<(), String> {
+    let db = state.db.lock().unwrap();
+    db.set_favorite(&session_id, favorite)
+        .map_err(|e| format!("Failed to update session: {}", e))
+}
+
+#[tauri::command]
+fn get_favorite_sessions(state: State<AppState>) -> Result<Vec<db::Session>, String> {
+    let db = state.db.lock().unwrap();
+    db.get_favorite_sessions()
+        .map_err(|e| format!("Failed to get favorites: {}", e))
+}
+
+// Delete sessions older than `retention_days`, or the retention_days setting; favorites are kept.
+// With prune_grace_hours set, sessions newly found due are only listed, in a
+// "prune-pending" event, and deleted by a prune call once the grace period is over.
+#[tauri::command]
+fn prune_sessions(app: tauri::AppHandle, state: State<AppState>, retention_days: Option<u64>) -> Result<usize, String> {
+    let live_id = state.pty.lock().unwrap().as_ref().map(|s| s.session_id.clone());
+    let db = state.db.lock().unwrap();
+    let days = match retention_days {
+        Some(days) => days,
+        None => db
+            .get_setting(settings::RETENTION_DAYS)
+            .ok()
+            .and_then(|v| v.as_u64())
+            .ok_or("No retention period given or configured")?,
+    };
+    let outcome = db
+        .prune_sessions(days, live_id.as_deref(), prune_grace(&db))
+        .map_err(|e| format!("Failed to prune sessions: {}", e))?;
+    if !outcome.newly_pending.is_empty() {
+        app.emit_all("prune-pending", &outcome.newly_pending).ok();
+    }
+    Ok(outcome.deleted)
+}
+
+// Sessions a prune pass found due that are still in their grace period
+#[tauri::command]
+fn get_pending_prunes(state: State<AppState>) -> Result<Vec<db::PendingPrune>, String> {
+    let db = state.db.lock().unwrap();
+    let Some(grace) = prune_grace(&db) else {
+        return Ok(Vec::new());
+    };
+    db.get_pending_prunes(grace)
+        .map_err(|e| format!("Failed to get pending prunes: {}", e))
+}
+
+fn prune_grace(db: &Database) -> Option<chrono::Duration> {
+    db.get_setting(settings::PRUNE_GRACE_HOURS)
+        .ok()
+        .and_then(|v| v.as_u64())
+        // Clamped rather than dropped: a grace period that can't be read isn't "none"
+        .and_then(|hours| chrono::TimeDelta::try_hours(hours.min(settings::MAX_PRUNE_GRACE_HOURS) as i64))
+}
+
+#[tauri::command]
+fn get_templates(state: State<AppState>) -> Result<Vec<db::Session>, String> {
+    let db = state.db.lock().unwrap();
+    db.get_templates()
+        .map_err(|e| format!("Failed to get templates: {}", e))
+}
+
+// Start a session with a template's shell, cwd and env, replaying its commands as init
+#[tauri::command]
+fn create_from_template(state: State<AppState>, session_id: String) -> Result<String, String> {
+    let (template, env, init_commands) = {
+        let db = state.db.lock().unwrap();
+        let template = db
+            .get_session(&session_id)
+            .map_err(|e| format!("Failed to get session: {}", e))?
+            .ok_or("Session not found")?;
+        if !template.is_template {
+            return Err("Session is not a template".to_string());
+        }
+        let env = db
+            .get_session_env(&session_id)
+            .map_err(|e| format!("Failed to get session env: {}", e))?;
+        let commands = db
+            .get_commands(&session_id)
+            .map_err(|e| format!("Failed to get commands: {}", e))?;
+        let init: Vec<String> = commands.into_iter().filter_map(|c| c.input).collect();
+        (template, env, init)
+    };
+
+    let launch = SessionLaunch {
+        cwd: PathBuf::from(&template.cwd),
+        shell: Some(template.shell),
+        env,
+        env_file: template.env_file,
+        cols: template.cols.unwrap_or(80),
+        rows: template.rows.unwrap_or(24),
+        pixel_width: 0,
+        pixel_height: 0,
+        tee_file: None,
+        parent_session_id: Some(session_id),
+        prompt: None,
+    };
+    let new_id = launch_session(&state, launch).map_err(|e| e.message)?;
+
+    // The shell reads these once it's up; the PTY buffers them until then
+    let pty = state.pty.lock().unwrap();
+    let db = state.db.lock().unwrap();
+    if let Some(ref session) = *pty {
+        for input in init_commands {
+            let data = format!("{}\n", input);
+            session
+                .write_input(data.clone())
+                .map_err(|e| format!("Failed to write input: {}", e))?;
+            db.add_event(&new_id, "user_in", &data)
+                .map_err(|e| format!("Failed to log input: {}", e))?;
+        }
+    }
+
+    Ok(new_id)
+}
+
+#[tauri::command]
+fn send_input(state: State<AppState>, data: Vec<u8>) -> Result<(), String> {
+    let pty = state.pty.lock().unwrap();
+    if let Some(ref session) = *pty {
+        // The terminal answering measure_latency's query; not meant for the shell
+        if session.complete_latency_probe(&data) {
+            return Ok(());
+        }
+
+        // The Vec is handed to the writer thread as-is, unless it completed an abbreviation
+        let written = session
+            .write_typed_input(data)
+            .map_err(|e| format!("Failed to write input: {}", e))?;
+        let data_str = String::from_utf8_lossy(&written).to_string();
+
+        // Log input to database
+        let mut db = state.db.lock().unwrap();
+        db.add_event(&session.session_id, "user_in", &data_str)
+            .map_err(|e| format!("Failed to log input: {}", e))?;
+        db.queue_session_metadata(&session.session_id, db::LAST_ACTIVITY_AT, chrono::Utc::now().to_rfc3339());
+    }
+    Ok(())
+}
+
+#[derive(serde::Serialize)]
+struct BackendInfo {
+    version: String, // Crate version
+    schema_version: i64,
+    sqlite_version: String,
+    features: Vec<String>, // Capabilities available in this build and environment
+}
+
+#[tauri::command]
+fn get_backend_info(state: State<AppState>) -> Result<BackendInfo, String> {
+    let db = state.db.lock().unwrap();
+    let fail = |e: anyhow::Error| format!("Failed to get backend info: {}", e);
+
+    let mut features = Vec::new();
+    if db.fts_available().map_err(fail)? {
+        features.push("fts");
+    }
+    if zdotdir::integration_installed() {
+        features.push("shell_integration");
+    }
+    if !db.get_setting(settings::MIRROR).map_err(fail)?.is_null() {
+        features.push("mirror");
+    }
+    if agg::find_agg().is_some() {
+        features.push("agg");
+    }
+
+    Ok(BackendInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        schema_version: db.schema_version().map_err(fail)?,
+        sqlite_version: db.sqlite_version().map_err(fail)?,
+        features: features.into_iter().map(str::to_string).collect(),
+    })
+}
+
+#[derive(serde::Serialize)]
+struct LiveSession {
+    session_id: String,
+    pid: Option<u32>,
+    alive: bool,
+    cols: u16,
+    rows: u16,
+    last_activity: String, // RFC3339
+}
+
+// The in-memory PTY, as opposed to the recorded history in get_recent_sessions. The app
+// runs a single PTY (state.pty), so the list is that session or empty; a list so the
+// UI can reconcile its tabs against it the same way either way.
+#[tauri::command]
+fn get_live_sessions(state: State<AppState>) -> Result<Vec<LiveSession>, String> {
+    let pty = state.pty.lock().unwrap();
+    let Some(ref session) = *pty else {
+        return Ok(Vec::new());
+    };
+
+    let (cols, rows) = session
+        .size()
+        .map_err(|e| format!("Failed to get size: {}", e))?;
+    let last_activity = chrono::DateTime::from_timestamp_millis(session.last_activity_ms())
+        .map(|ts| ts.to_rfc3339())
+        .unwrap_or_default();
+
+    Ok(vec![LiveSession {
+        session_id: session.session_id.clone(),
+        pid: session.pid(),
+        alive: session.is_alive(),
+        cols,
+        rows,
+        last_activity,
+    }])
+}
+
+#[derive(serde::Serialize)]
+struct BroadcastResult {
+    session_id: String,
+    error: Option<String>, // None when the input was written and logged
+}
+
+#[derive(serde::Serialize)]
+struct RuntimeStatus {
+    live_sessions: usize, // 0 or 1: the app runs a single PTY
+    open_pty_fds: Option<usize>, // Process-wide; None where the platform doesn't list open fds
+    scrollback_bytes: u64, // The live session's, held in memory for reattach
+    output_bytes_per_sec: f64, // The live session's, since the previous call
+}
+
+// Resource use of the live session, if any, for a status bar. Cheap enough to poll;
+// touches neither the DB nor the disk, except /proc or /dev/fd for the fd count.
+#[tauri::command]
+fn get_runtime_status(state: State<AppState>) -> RuntimeStatus {
+    let pty = state.pty.lock().unwrap();
+    RuntimeStatus {
+        live_sessions: usize::from(pty.is_some()),
+        open_pty_fds: pty::open_pty_fds(),
+        scrollback_bytes: pty.as_ref().map_or(0, |s| s.scrollback_bytes() as u64),
+        output_bytes_per_sec: pty.as_ref().map_or(0.0, |s| s.sample_output_rate()),
+    }
+}
+
+// Write input to the live session if `session_ids` lists it, and log it there. The
+// app runs a single PTY (state.pty), so there is nothing to fan out to: every other
+// id is reported as not live, and the live one is written once however often it's listed.
+#[tauri::command]
+fn broadcast_input(
+    state: State<AppState>,
+    session_ids: Vec<String>,
+    data: Vec<u8>,
+) -> Result<Vec<BroadcastResult>, String> {
+    let pty = state.pty.lock().unwrap();
+    let live = pty.as_ref().filter(|session| session_ids.contains(&session.session_id));
+    let outcome = live.map(|session| {
+        let data_str = String::from_utf8_lossy(&data).to_string();
+        session
+            .write_input(data)
+            .map_err(|e| format!("Failed to write input: {}", e))?;
+        let db = state.db.lock().unwrap();
+        db.add_event(&session.session_id, "user_in", &data_str)
+            .map_err(|e| format!("Failed to log input: {}", e))
+    });
+
+    let results = session_ids
+        .into_iter()
+        .map(|session_id| {
+            let error = match (live, &outcome) {
+                (Some(session), Some(outcome)) if session.session_id == session_id => outcome.clone().err(),
+                _ => Some("Session is not live".to_string()),
+            };
+            BroadcastResult { session_id, error }
+        })
+        .collect();
+
+    Ok(results)
+}
+
+#[derive(serde::Serialize)]
+struct PlannedInput {
+    delay_ms: u64, // Wait before sending, after the previous input
+    data: String,
+}
+
+// Re-drive a live session with another session's recorded input, spaced by the
+// original timing divided by `speed`. Returns the plan; dry runs send nothing.
+#[tauri::command]
+fn replay_inputs_into(
+    app: tauri::AppHandle,
+    state: State<AppState>,
+    target_session_id: String,
+    source_session_id: String,
+    speed: f64,
+    dry_run: Option<bool>,
+) -> Result<Vec<PlannedInput>, String> {
+    if !(speed.is_finite() && speed > 0.0) {
+        return Err("Speed must be a positive number".to_string());
+    }
+
+    let inputs = {
+        let db = state.db.lock().unwrap();
+        db.get_events_of_kind(&source_session_id, "user_in")
+            .map_err(|e| format!("Failed to get events: {}", e))?
+    };
+
+    let mut previous_ms: Option<i64> = None;
+    let plan: Vec<PlannedInput> = inputs
+        .into_iter()
+        .map(|event| {
+            let gap = match (previous_ms, event.ts_ms) {
+                (Some(prev), Some(ts)) => (ts - prev).max(0),
+                _ => 0,
+            };
+            previous_ms = event.ts_ms.or(previous_ms);
+            PlannedInput {
+                delay_ms: (gap as f64 / speed).round() as u64,
+                data: event.data,
+            }
+        })
+        .collect();
+
+    if dry_run.unwrap_or(false) {
+        return Ok(plan);
+    }
+
+    match *state.pty.lock().unwrap() {
+        Some(ref session) if session.session_id == target_session_id => {}
+        _ => return Err("Session is not live".to_string()),
+    }
+
+    let to_send: Vec<(u64, String)> = plan.iter().map(|p| (p.delay_ms, p.data.clone())).collect();
+    threads::spawn("input replay", Some(target_session_id.clone()), move || {
+        for (delay_ms, data) in to_send {
+            std::thread::sleep(Duration::from_millis(delay_ms));
+
+            // Stop as soon as the target is no longer the live session
+            let state = app.state::<AppState>();
+            let pty = state.pty.lock().unwrap();
+            let session = match *pty {
+                Some(ref session) if session.session_id == target_session_id => session,
+                _ => break,
+            };
+            if session.write_input(data.clone()).is_err() {
+                break;
+            }
+            let db = state.db.lock().unwrap();
+            db.add_event(&target_session_id, "user_in", &data).ok();
+        }
+    });
+
+    Ok(plan)
+}
+
+// Start a fresh shell like the source session's and hold its recorded commands
+// until step_replay sends them, one per call
+#[tauri::command]
+fn start_stepped_replay(state: State<AppState>, session_id: String) -> Result<SteppedReplayStatus, String> {
+    let (source, env, commands) = {
+        let db = state.db.lock().unwrap();
+        let source = db
+            .get_session(&session_id)
+            .map_err(|e| format!("Failed to get session: {}", e))?
+            .ok_or("Session not found")?;
+        let env = db
+            .get_session_env(&session_id)
+            .map_err(|e| format!("Failed to get session env: {}", e))?;
+        let commands: Vec<String> = db
+            .get_commands(&session_id)
+            .map_err(|e| format!("Failed to get commands: {}", e))?
+            .into_iter()
+            .filter_map(|c| c.input)
+            .filter(|input| !input.trim().is_empty())
+            .collect();
+        (source, env, commands)
+    };
+    if commands.is_empty() {
+        return Err("No recorded commands in this session".to_string());
+    }
+
+    let launch = SessionLaunch {
+        cwd: PathBuf::from(&source.cwd),
+        shell: Some(source.shell),
+        env,
+        env_file: source.env_file,
+        cols: source.cols.unwrap_or(80),
+        rows: source.rows.unwrap_or(24),
+        pixel_width: 0,
+        pixel_height: 0,
+        tee_file: None,
+        parent_session_id: Some(session_id.clone()),
+        prompt: None,
+    };
+    let new_id = launch_session(&state, launch).map_err(|e| e.message)?;
+
+    let replay_id = uuid::Uuid::new_v4().to_string();
+    let replay = SteppedReplay {
+        session_id: new_id,
+        source_session_id: session_id,
+        commands,
+        next: 0,
+    };
+    let status = replay.status(&replay_id);
+
+    // Replays into sessions that are no longer live can't step again
+    let mut replays = state.replays.lock().unwrap();
+    replays.clear();
+    replays.insert(replay_id, replay);
+    Ok(status)
+}
+
+// Send a stepped replay's next command; its output arrives through read_output as usual
+#[tauri::command]
+fn step_replay(state: State<AppState>, replay_id: String) -> Result<SteppedReplayStatus, String> {
+    let mut replays = state.replays.lock().unwrap();
+    let replay = replays.get_mut(&replay_id).ok_or("Replay not found")?;
+
+    let pty = state.pty.lock().unwrap();
+    let session = match *pty {
+        Some(ref session) if session.session_id == replay.session_id => session,
+        _ => {
+            replays.remove(&replay_id);
+            return Err("Replay session is no longer live".to_string());
+        }
+    };
+
+    let input = replay.commands.get(replay.next).ok_or("Replay has no commands left")?;
+    let data = format!("{}\n", input);
+    session
+        .write_input(data.clone())
+        .map_err(|e| format!("Failed to write input: {}", e))?;
+    replay.next += 1;
+
+    let db = state.db.lock().unwrap();
+    db.add_event(&replay.session_id, "user_in", &data)
+        .map_err(|e| format!("Failed to log input: {}", e))?;
+
+    let status = replay.status(&replay_id);
+    if status.next_command.is_none() {
+        replays.remove(&replay_id);
+    }
+    Ok(status)
+}
+
+#[tauri::command]
+fn rerun_last_command(state: State<AppState>, session_id: String) -> Result<String, String> {
+    let pty = state.pty.lock().unwrap();
+    let session = match *pty {
+        Some(ref session) if session.session_id == session_id => session,
+        _ => return Err("Session is not live".to_string()),
+    };
+
+    let db = state.db.lock().unwrap();
+    let (input, _) = db
+        .get_recent_commands(&session_id, 1)
+        .map_err(|e| format!("Failed to get commands: {}", e))?
+        .into_iter()
+        .next()
+        .ok_or("No recorded command in this session")?;
+
+    let data = format!("{}\n", input);
+    session
+        .write_input(data.clone())
+        .map_err(|e| format!("Failed to write input: {}", e))?;
+
+    db.add_event(&session_id, "user_in", &data)
+        .map_err(|e| format!("Failed to log input: {}", e))?;
+
+    Ok(input)
+}
+
+#[tauri::command]
+fn read_output(state: State<AppState>) -> Result<Option<Vec<u8>>, String> {
+    let pty = state.pty.lock().unwrap();
+    if let Some(ref session) = *pty {
+        // Latency probes go straight to the terminal and stay out of the log
+        if let Some(query) = session.take_latency_query() {
+            return Ok(Some(query.to_vec()));
+        }
+
+        let mut notice_pending = state.recording_notice_pending.lock().unwrap();
+        if *notice_pending {
+            *notice_pending = false;
+            return Ok(Some(RECORDING_OFF_NOTICE.to_vec()));
+        }
+        drop(notice_pending);
+
+        if let Some(data) = session.read_output() {
+            // Log output to database
+            let mut db = state.db.lock().unwrap();
+            if let Some(warning) = session.take_tee_error() {
+                db.add_event(&session.session_id, "marker", &warning).ok();
+            }
+            if let Some(gap) = session.take_idle_gap() {
+                let label = format!("idle gap ({})", export::format_duration(gap.as_millis() as i64));
+                db.add_event(&session.session_id, "marker", &label).ok();
+            }
+            let mut data_str = session.strip_echo(String::from_utf8_lossy(&data).to_string());
+            if let Some(ref mut modes) = *state.input_modes.lock().unwrap() {
+                for change in modes.feed(&data_str) {
+                    db.add_event(&session.session_id, "marker", &change).ok();
+                }
+            }
+            if let Some(ref mut tracker) = *state.focus.lock().unwrap() {
+                for marker in tracker.observe(session.foreground(), &data_str, pty::process_name) {
+                    db.add_event(&session.session_id, "marker", &marker).ok();
+                }
+            }
+            // Full-screen apps log as screen diffs when diff_alt_screen is on
+            if let Some(ref mut capture) = *state.diff_capture.lock().unwrap() {
+                data_str = capture.log_text(&data_str);
+            }
+            if !data_str.is_empty() {
+                db.add_event(&session.session_id, "pty_out", &data_str)
+                    .ok(); // Don't fail on log errors
+                if db.written_since_checkpoint() >= CHECKPOINT_AFTER_BYTES {
+                    state.db_maintenance.notify_all();
+                }
+            }
+            db.queue_session_metadata(&session.session_id, db::LAST_ACTIVITY_AT, chrono::Utc::now().to_rfc3339());
+
+            // Converted only here, where the command's return type needs an owned Vec
+            return Ok(Some(data.to_vec()));
+        }
+    }
+    Ok(None)
+}
+
+// Read-only view of a live session's output for a second window; there is no input path
+#[tauri::command]
+fn attach_observer(state: State<AppState>, session_id: String) -> Result<String, String> {
+    match *state.pty.lock().unwrap() {
+        Some(ref session) if session.session_id == session_id => Ok(session.attach_observer()),
+        _ => Err("Session is not live".to_string()),
+    }
+}
+
+// Poll like read_output; observers don't log or consume the primary view's output
+#[tauri::command]
+fn read_observer_output(state: State<AppState>, observer_id: String) -> Result<Option<Vec<u8>>, String> {
+    match *state.pty.lock().unwrap() {
+        Some(ref session) => session
+            .read_observer(&observer_id)
+            .map(|data| data.map(|d| d.to_vec()))
+            .map_err(|e| e.to_string()),
+        None => Err("Observer not found".to_string()),
+    }
+}
+
+#[tauri::command]
+fn detach_observer(state: State<AppState>, observer_id: String) -> Result<(), String> {
+    if let Some(ref session) = *state.pty.lock().unwrap() {
+        session.detach_observer(&observer_id);
+    }
+    Ok(())
+}
+
+// Turn expansion of the abbreviations setting on or off for the live session only;
+// it starts on for every session
+#[tauri::command]
+fn set_abbreviations_enabled(state: State<AppState>, session_id: String, enabled: bool) -> Result<(), String> {
+    match *state.pty.lock().unwrap() {
+        Some(ref session) if session.session_id == session_id => {
+            session.set_abbreviations_enabled(enabled);
+            Ok(())
+        }
+        _ => Err("Session is not live".to_string()),
+    }
+}
+
+#[tauri::command]
+fn set_tee(state: State<AppState>, session_id: String, path: Option<String>) -> Result<(), String> {
+    let pty = state.pty.lock().unwrap();
+    let session = match *pty {
+        Some(ref session) if session.session_id == session_id => session,
+        _ => return Err("Session is not live".to_string()),
+    };
+
+    let file = match path {
+        Some(ref f) => {
+            let cwd = std::env::current_dir().unwrap_or_else(|_| std::path::PathBuf::from("/"));
+            Some(pty::open_tee(&cwd.join(f)).map_err(|e| format!("{:#}", e))?)
+        }
+        None => None,
+    };
+    session.set_tee(file);
+    Ok(())
+}
+
+// Named pipe whose contents are typed into the session, for scripting from outside the
+// app; removed when the session ends. Returns the existing pipe if there is one.
+#[tauri::command]
+fn create_input_fifo(app: tauri::AppHandle, state: State<AppState>, session_id: String) -> Result<String, String> {
+    let pty = state.pty.lock().unwrap();
+    let session = match *pty {
+        Some(ref session) if session.session_id == session_id => session,
+        _ => return Err("Session is not live".to_string()),
+    };
+    if let Some(path) = session.input_fifo_path() {
+        return Ok(path.to_string_lossy().to_string());
+    }
+
+    let path = fifo::runtime_dir()
+        .map_err(|e| format!("Failed to locate runtime dir: {}", e))?
+        .join(format!("{}.input", session_id));
+
+    let target_session_id = session_id.clone();
+    let input_fifo = fifo::InputFifo::create(&session_id, &path, move |data| {
+        write_logged_input(&app.state::<AppState>(), &target_session_id, data)
+    })
+    .map_err(|e| format!("{:#}", e))?;
+
+    session.set_input_fifo(input_fifo);
+    Ok(path.to_string_lossy().to_string())
+}
+
+// Same write-then-log path as send_input, for input from outside the window;
+// false once the session is no longer live
+fn write_logged_input(state: &AppState, session_id: &str, data: Vec<u8>) -> bool {
+    let pty = state.pty.lock().unwrap();
+    let session = match *pty {
+        Some(ref session) if session.session_id == session_id => session,
+        _ => return false,
+    };
+    let data_str = String::from_utf8_lossy(&data).to_string();
+    if session.write_input(data).is_err() {
+        return false;
+    }
+    let db = state.db.lock().unwrap();
+    db.add_event(session_id, "user_in", &data_str).ok();
+    true
+}
+
+// A session server's view of the live session: observers for output, and
+// write_logged_input for input
+struct LiveSessionLink {
+    app: tauri::AppHandle,
+    session_id: String,
+}
+
+impl ws::SessionLink for LiveSessionLink {
+    fn attach(&self) -> Option<String> {
+        match *self.app.state::<AppState>().pty.lock().unwrap() {
+            Some(ref session) if session.session_id == self.session_id => Some(session.attach_observer()),
+            _ => None,
+        }
+    }
+
+    fn read(&self, stream_id: &str) -> anyhow::Result<Option<Bytes>> {
+        match *self.app.state::<AppState>().pty.lock().unwrap() {
+            Some(ref session) if session.session_id == self.session_id => session.read_observer(stream_id),
+            _ => anyhow::bail!("Session is not live"),
+        }
+    }
+
+    fn write(&self, data: Vec<u8>) -> bool {
+        write_logged_input(&self.app.state::<AppState>(), &self.session_id, data)
+    }
+
+    fn detach(&self, stream_id: &str) {
+        if let Some(ref session) = *self.app.state::<AppState>().pty.lock().unwrap() {
+            session.detach_observer(stream_id);
+        }
+    }
+}
+
+// Stream a live session over a local WebSocket; returns the URL, token included.
+// Binds to localhost unless `lan` is set; see ws.rs for who may connect. LAN traffic
+// isn't encrypted, token included.
+#[tauri::command]
+fn start_session_server(
+    app: tauri::AppHandle,
+    state: State<AppState>,
+    session_id: String,
+    port: Option<u16>,
+    lan: Option<bool>,
+) -> Result<String, String> {
+    let pty = state.pty.lock().unwrap();
+    let session = match *pty {
+        Some(ref session) if session.session_id == session_id => session,
+        _ => return Err("Session is not live".to_string()),
+    };
+    if let Some(url) = session.session_server_url() {
+        return Ok(url);
+    }
+
+    let link = LiveSessionLink {
+        app: app.clone(),
+        session_id: session_id.clone(),
+    };
+    let server = ws::SessionServer::start(&session_id, port.unwrap_or(0), lan.unwrap_or(false), link)
+        .map_err(|e| format!("Failed to start session server: {:#}", e))?;
+    let url = server.url.clone();
+    session.set_session_server(Some(server));
+    Ok(url)
+}
+
+// Disconnects every client; false if no server was running
+#[tauri::command]
+fn stop_session_server(state: State<AppState>, session_id: String) -> Result<bool, String> {
+    match *state.pty.lock().unwrap() {
+        Some(ref session) if session.session_id == session_id => Ok(session.set_session_server(None)),
+        _ => Err("Session is not live".to_string()),
+    }
+}
+
+const LATENCY_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Milliseconds for the frontend to answer a cursor position request: from queueing it
+// until the reply arrives, through read_output polling, the terminal emulator and
+// send_input. The shell and the PTY aren't involved, so this is the frontend's share of
+// input lag; whatever lag remains beyond it is the PTY's or the shell's.
+// Async so waiting on the terminal doesn't block the read_output/send_input calls it relies on
+#[tauri::command(async)]
+fn measure_latency(state: State<'_, AppState>, session_id: String) -> Result<f64, String> {
+    let done_rx = {
+        let pty = state.pty.lock().unwrap();
+        match *pty {
+            Some(ref session) if session.session_id == session_id => session
+                .start_latency_probe()
+                .map_err(|e| format!("Failed to measure latency: {}", e))?,
+            _ => return Err("Session is not live".to_string()),
+        }
+    };
+
+    match done_rx.recv_timeout(LATENCY_TIMEOUT) {
+        Ok(round_trip) => Ok(round_trip.as_secs_f64() * 1000.0),
+        Err(RecvTimeoutError::Timeout) => {
+            // The measured session may have been replaced while this waited
+            match *state.pty.lock().unwrap() {
+                Some(ref session) if session.session_id == session_id => session.cancel_latency_probe(),
+                _ => {}
+            }
+            Err(format!(
+                "No response from terminal within {}ms",
+                LATENCY_TIMEOUT.as_millis()
+            ))
+        }
+        Err(RecvTimeoutError::Disconnected) => Err("Session ended during measurement".to_string()),
+    }
+}
+
+#[tauri::command]
+fn get_scrollback(state: State<AppState>, session_id: String) -> Result<Vec<u8>, String> {
+    let pty = state.pty.lock().unwrap();
+    match *pty {
+        Some(ref session) if session.session_id == session_id => Ok(session.scrollback()),
+        _ => Err("Session is not live".to_string()),
+    }
+}
+
+#[tauri::command]
+fn process_osc_events(state: State<AppState>) -> Result<(), String> {
+    let pty = state.pty.lock().unwrap();
+    if let Some(ref session) = *pty {
+        let events = session.read_osc_events();
+        let session_id = session.session_id.clone();
+        drop(pty); // Release lock before database operations
+
+        for event in events {
+            match event {
+                OscEvent::CommandText(cmd_text) => {
+                    // Command starting - create new command record
+                    let db = state.db.lock().unwrap();
+                    match db.create_command(&session_id, &cmd_text) {
+                        Ok(cmd_id) => {
+                            println!("🎵 Command started: {}", cmd_text);
+                            let mut current = state.current_command_id.lock().unwrap();
+                            *current = Some(cmd_id);
+                        }
+                        Err(e) => eprintln!("Failed to create command: {}", e),
+                    }
+                }
+                OscEvent::CommandEnd(exit_code) => {
+                    // Command ended - update with exit code
+                    let db = state.db.lock().unwrap();
+                    if let Err(e) = db.end_command(&session_id, exit_code) {
+                        eprintln!("Failed to end command: {}", e);
+                    } else {
+                        let emoji = if exit_code == 0 { "✅" } else { "❌" };
+                        println!("{} Command completed with exit code: {}", emoji, exit_code);
+                    }
+                    let mut current = state.current_command_id.lock().unwrap();
+                    *current = None;
+                }
+                OscEvent::ClipboardSet { selection, bytes } => {
+                    // Logged for visibility; honoring it is up to the frontend's policy
+                    let db = state.db.lock().unwrap();
+                    let note = match selection.as_str() {
+                        "" | "c" => format!("clipboard set ({} bytes)", bytes),
+                        _ => format!("clipboard set ({} bytes, selection {})", bytes, selection),
+                    };
+                    db.add_event(&session_id, "clipboard", &note).ok();
+                }
+                _ => {} // Ignore other OSC events for now
+            }
+        }
+    }
+    Ok(())
+}
+
+// The session's captured env (from its env file) as lines to source into a live shell
+#[tauri::command]
+fn get_session_env_exports(state: State<AppState>, session_id: String) -> Result<String, String> {
+    let db = state.db.lock().unwrap();
+    let env = db
+        .get_session_env(&session_id)
+        .map_err(|e| format!("Failed to get session env: {}", e))?;
+    Ok(export::env_exports(&env))
+}
+
+#[tauri::command]
+fn get_session_launch(state: State<AppState>, session_id: String) -> Result<Option<db::SessionLaunchRecord>, String> {
+    let db = state.db.lock().unwrap();
+    db.get_session_launch(&session_id)
+        .map_err(|e| format!("Failed to get session launch: {}", e))
+}
+
+#[tauri::command]
+fn get_recording_status(state: State<AppState>, session_id: String) -> Result<db::RecordingStatus, String> {
+    let db = state.db.lock().unwrap();
+    db.get_recording_status(&session_id)
+        .map_err(|e| format!("Failed to get recording status: {}", e))
+}
+
+#[tauri::command]
+fn acknowledge_recording_degradation(state: State<AppState>, session_id: String) -> Result<(), String> {
+    let db = state.db.lock().unwrap();
+    db.acknowledge_recording_degradation(&session_id)
+        .map_err(|e| format!("Failed to update session: {}", e))
+}
+
+#[tauri::command]
+fn get_persistence_enabled(state: State<AppState>) -> bool {
+    state.db.lock().unwrap().persistence_enabled()
+}
+
+#[tauri::command]
+fn set_persistence_enabled(state: State<AppState>, enabled: bool) -> Result<(), String> {
+    set_setting(state, settings::PERSISTENCE_ENABLED.to_string(), serde_json::Value::Bool(enabled))
+}
+
+#[tauri::command]
+fn get_setting(state: State<AppState>, key: String) -> Result<serde_json::Value, String> {
+    let db = state.db.lock().unwrap();
+    db.get_setting(&key).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_settings(state: State<AppState>) -> Result<HashMap<String, serde_json::Value>, String> {
+    let db = state.db.lock().unwrap();
+    db.get_settings()
+        .map_err(|e| format!("Failed to get settings: {}", e))
+}
+
+#[tauri::command]
+fn set_setting(state: State<AppState>, key: String, value: serde_json::Value) -> Result<(), String> {
+    let live = state
+        .pty
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|s| (s.session_id.clone(), s.argv.first().cloned().unwrap_or_default()));
+    let mut db = state.db.lock().unwrap();
+    let was_recording = db.persistence_enabled();
+    db.set_setting(&key, value).map_err(|e| e.to_string())?;
+
+    // Tell the user once each time recording goes off
+    if was_recording && !db.persistence_enabled() {
+        *state.recording_notice_pending.lock().unwrap() = true;
+    }
+    if !was_recording && db.persistence_enabled() {
+        if let Some((ref session_id, ref shell)) = live {
+            db.adopt_session(session_id, shell)
+                .map_err(|e| format!("Failed to resume recording: {}", e))?;
+        }
+    }
+
+    if key == settings::IDLE_MARKER_MINUTES {
+        let threshold = idle_gap_threshold(&db);
+        drop(db); // read_output takes the PTY lock before the DB lock
+        if let Some(ref session) = *state.pty.lock().unwrap() {
+            session.set_idle_gap_threshold(threshold);
+        }
+    } else if key == settings::SUPPRESS_ECHO {
+        let enabled = suppress_echo(&db);
+        drop(db);
+        if let Some(ref session) = *state.pty.lock().unwrap() {
+            session.set_echo_suppression(enabled);
+        }
+    } else if key == settings::ABBREVIATIONS {
+        let abbreviations = abbreviations(&db);
+        drop(db);
+        if let Some(ref session) = *state.pty.lock().unwrap() {
+            session.set_abbreviations(abbreviations);
+        }
+    } else if key == settings::FOCUS_MODE {
+        let mut tracker = state.focus.lock().unwrap();
+        if !focus_mode(&db) {
+            // Close the span in progress so it doesn't read as running until the end
+            let marker = tracker.take().and_then(|mut t| t.finish());
+            if let (Some(marker), Some((session_id, _))) = (marker, live) {
+                db.add_event(&session_id, "marker", &marker).ok();
+            }
+        } else if tracker.is_none() {
+            *tracker = Some(focus::FocusTracker::default());
+        }
+    } else if key == settings::LOG_MODE_CHANGES {
+        // Turned on mid-session, modes the session had already set are only logged
+        // once they change again
+        let mut modes = state.input_modes.lock().unwrap();
+        if !log_mode_changes(&db) {
+            *modes = None;
+        } else if modes.is_none() {
+            *modes = Some(vt::ModeTracker::default());
+        }
+    } else if key == settings::DB_IDLE_CLOSE_SECS || key == settings::CHECKPOINT_INTERVAL_SECS {
+        state.db_maintenance.notify_all();
+    }
+    Ok(())
+}
+
+// Markers logged when output switched an input mode such as bracketed paste, for
+// seeing why keys or pastes arrived differently at different points. Only sessions
+// recorded with log_mode_changes on have them.
+#[tauri::command]
+fn get_mode_changes(state: State<AppState>, session_id: String) -> Result<Vec<db::Event>, String> {
+    let db = state.db.lock().unwrap();
+    let markers = db
+        .get_events_of_kind(&session_id, "marker")
+        .map_err(|e| format!("Failed to get mode changes: {}", e))?;
+    Ok(markers
+        .into_iter()
+        .filter(|event| event.data.starts_with(vt::MODE_MARKER_PREFIX))
+        .collect())
+}
+
+// Program-scoped spans recorded in focus mode, with their time windows
+#[tauri::command]
+fn get_subsessions(state: State<AppState>, session_id: String) -> Result<Vec<focus::Subsession>, String> {
+    let db = state.db.lock().unwrap();
+    let markers = db
+        .get_events_of_kind(&session_id, "marker")
+        .map_err(|e| format!("Failed to get subsessions: {}", e))?;
+    Ok(focus::subsessions(&markers))
+}
+
+#[tauri::command]
+fn get_clipboard_events(state: State<AppState>, session_id: String) -> Result<Vec<db::Event>, String> {
+    let db = state.db.lock().unwrap();
+    db.get_events_of_kind(&session_id, "clipboard")
+        .map_err(|e| format!("Failed to get clipboard events: {}", e))
+}
+
+#[tauri::command]
+fn get_clipboard_policy(state: State<AppState>) -> bool {
+    *state.allow_clipboard_write.lock().unwrap()
+}
+
+#[tauri::command]
+fn set_clipboard_policy(state: State<AppState>, allow: bool) {
+    *state.allow_clipboard_write.lock().unwrap() = allow;
+}
+
+#[tauri::command]
+fn resize_pty(
+    state: State<AppState>,
+    cols: u16,
+    rows: u16,
+    pixel_width: Option<u16>,
+    pixel_height: Option<u16>,
+) -> Result<(), String> {
+    let mut pty = state.pty.lock().unwrap();
+    if let Some(ref mut session) = *pty {
+        let size = pty::PtySize {
+            cols,
+            rows,
+            pixel_width: pixel_width.unwrap_or(0),
+            pixel_height: pixel_height.unwrap_or(0),
+        };
+        session
+            .resize(size)
+            .map_err(|e| format!("Failed to resize: {}", e))?;
+        if let Some(ref mut capture) = *state.diff_capture.lock().unwrap() {
+            capture.resize(cols, rows);
+        }
+
+        // Log resize so replays/casts can re-wrap at the right moments
+        let db = state.db.lock().unwrap();
+        db.add_event(&session.session_id, "resize", &format!("{}x{}", cols, rows))
+            .ok(); // Don't fail on log errors
+    }
+    Ok(())
+}
+
+#[tauri::command]
+fn end_session(app: tauri::AppHandle, state: State<AppState>) -> Result<(), String> {
+    // Taken out under the lock and dropped after it, since dropping joins its threads
+    let session = state.pty.lock().unwrap().take();
+    if let Some(session) = session {
+        finish_session(&app, &state, &session.session_id)?;
+    }
+    Ok(())
+}
+
+// Record the end of a session just taken out of state.pty, then run the end hook
+fn finish_session(app: &tauri::AppHandle, state: &AppState, session_id: &str) -> Result<(), String> {
+    state.runtime_limit.lock().unwrap().take();
+    let mut db = state.db.lock().unwrap();
+    if let Some(marker) = state.focus.lock().unwrap().take().and_then(|mut t| t.finish()) {
+        db.add_event(session_id, "marker", &marker).ok();
+    }
+    db.end_session(session_id)
+        .map_err(|e| format!("Failed to end session: {}", e))?;
+    // A bad rule shouldn't keep the session from ending
+    if let Err(e) = db.apply_auto_tags(session_id) {
+        eprintln!("Auto-tagging failed: {}", e);
+    }
+
+    let hook = db.get_setting(settings::ON_SESSION_END).ok();
+    if let Some(command) = hook.as_ref().and_then(|v| v.as_str()) {
+        let exit_code = db
+            .get_recent_commands(session_id, 1)
+            .ok()
+            .and_then(|recent| recent.first().map(|(_, code)| *code));
+        spawn_session_end_hook(app.clone(), command.to_string(), session_id.to_string(), exit_code);
+    }
+    Ok(())
+}
+
+// Run the hook off the command thread; its outcome is logged as a marker and
+// failures never affect teardown
+fn spawn_session_end_hook(app: tauri::AppHandle, command: String, session_id: String, exit_code: Option<i32>) {
+    threads::spawn("session end hook", Some(session_id.clone()), move || {
+        let note = hooks::run_session_end(&command, &session_id, exit_code).unwrap_or_else(|e| format!("{:#}", e));
+        let state = app.state::<AppState>();
+        let db = state.db.lock().unwrap();
+        db.add_event(&session_id, "marker", &note).ok();
+    });
+}
+
+#[tauri::command]
+fn get_recent_sessions(state: State<AppState>, limit: usize) -> Result<Vec<db::Session>, String> {
+    let db = state.db.lock().unwrap();
+    db.get_recent_sessions(limit)
+        .map_err(|e| format!("Failed to get sessions: {}", e))
+}
+
+#[tauri::command]
+fn get_recent_sessions_detailed(state: State<AppState>, limit: usize) -> Result<Vec<db::SessionDetail>, String> {
+    let db = state.db.lock().unwrap();
+    db.get_recent_sessions_detailed(limit)
+        .map_err(|e| format!("Failed to get sessions: {}", e))
+}
+
+// `sort_by` is one of started_at, duration, event_count, command_count, last_activity_at
+#[tauri::command]
+fn get_sessions_sorted(
+    state: State<AppState>,
+    sort_by: String,
+    direction: String,
+    limit: usize,
+    offset: usize,
+) -> Result<Vec<db::SessionDetail>, String> {
+    let db = state.db.lock().unwrap();
+    db.get_sessions_sorted(&sort_by, &direction, limit, offset)
+        .map_err(|e| format!("Failed to get sessions: {}", e))
+}
+
+#[tauri::command]
+fn get_session_events(state: State<AppState>, session_id: String) -> Result<Vec<db::Event>, String> {
+    let db = state.db.lock().unwrap();
+    db.get_events(&session_id)
+        .map_err(|e| format!("Failed to get events: {}", e))
+}
+
+#[tauri::command]
+fn get_events_in_window(
+    state: State<AppState>,
+    session_id: String,
+    start_ms: i64,
+    end_ms: i64,
+) -> Result<Vec<db::Event>, String> {
+    let db = state.db.lock().unwrap();
+    db.get_events_in_window(&session_id, start_ms, end_ms)
+        .map_err(|e| format!("Failed to get events: {}", e))
+}
+
+// Raw output between two events (inclusive), base64-encoded
+#[tauri::command]
+fn get_output_slice(
+    state: State<AppState>,
+    session_id: String,
+    from_event_id: String,
+    to_event_id: String,
+) -> Result<String, String> {
+    let db = state.db.lock().unwrap();
+    let bytes = db
+        .get_output_slice(&session_id, &from_event_id, &to_event_id)
+        .map_err(|e| format!("Failed to get output slice: {}", e))?;
+
+    Ok(general_purpose::STANDARD.encode(bytes))
+}
+
+// The same output as styled text runs, for renderers and highlighting that
+// don't need the full screen replay
+#[tauri::command]
+fn get_styled_runs(
+    state: State<AppState>,
+    session_id: String,
+    from_event_id: String,
+    to_event_id: String,
+) -> Result<Vec<vt::StyledRun>, String> {
+    let db = state.db.lock().unwrap();
+    let bytes = db
+        .get_output_slice(&session_id, &from_event_id, &to_event_id)
+        .map_err(|e| format!("Failed to get output slice: {}", e))?;
+
+    Ok(vt::styled_runs(&String::from_utf8_lossy(&bytes)))
+}
+
+#[tauri::command]
+fn get_around_marker(
+    state: State<AppState>,
+    event_id: String,
+    before: usize,
+    after: usize,
+) -> Result<Vec<db::Event>, String> {
+    let db = state.db.lock().unwrap();
+    db.get_around_marker(&event_id, before, after)
+        .map_err(|e| format!("Failed to get events around marker: {}", e))
+}
+
+#[tauri::command]
+fn get_sessions_with_commands(state: State<AppState>, limit: usize) -> Result<Vec<db::SessionSummary>, String> {
+    let db = state.db.lock().unwrap();
+    db.get_sessions_with_commands(limit)
+        .map_err(|e| format!("Failed to get sessions: {}", e))
+}
+
+#[tauri::command]
+fn get_commands(state: State<AppState>, session_id: String) -> Result<Vec<db::Command>, String> {
+    let db = state.db.lock().unwrap();
+    db.get_commands(&session_id)
+        .map_err(|e| format!("Failed to get commands: {}", e))
+}
+
+// Re-derive a session's commands from the OSC 133 markers in its stored output, e.g.
+// for sessions recorded before shell integration. Replaces its existing commands.
+#[tauri::command]
+fn reparse_markers(state: State<AppState>, session_id: String) -> Result<usize, String> {
+    let db = state.db.lock().unwrap();
+    db.reparse_commands(&session_id)
+        .map_err(|e| format!("Failed to reparse markers: {}", e))
+}
+
+// Parse every stored session and event timestamp, in one session or all, and list
+// the ones that don't parse
+#[tauri::command]
+fn validate_timestamps(state: State<AppState>, session_id: Option<String>) -> Result<db::TimestampReport, String> {
+    let db = state.db.lock().unwrap();
+    db.validate_timestamps(session_id.as_deref())
+        .map_err(|e| format!("Failed to validate timestamps: {}", e))
+}
+
+// Rewrite parseable timestamps that aren't canonical UTC RFC3339, e.g. from imports
+#[tauri::command]
+fn normalize_timestamps(state: State<AppState>, session_id: Option<String>) -> Result<db::TimestampReport, String> {
+    let db = state.db.lock().unwrap();
+    db.normalize_timestamps(session_id.as_deref())
+        .map_err(|e| format!("Failed to normalize timestamps: {}", e))
+}
+
+// Maintenance after structural edits: renumber a session's events contiguously in
+// timestamp order so pagination and replay agree. Returns how many were renumbered.
+#[tauri::command]
+fn normalize_session(state: State<AppState>, session_id: String) -> Result<usize, String> {
+    let db = state.db.lock().unwrap();
+    db.normalize_session(&session_id)
+        .map_err(|e| format!("Failed to normalize session: {}", e))
+}
+
+#[tauri::command]
+fn get_session_outline(state: State<AppState>, session_id: String) -> Result<Vec<db::OutlineEntry>, String> {
+    let db = state.db.lock().unwrap();
+    db.get_session_outline(&session_id)
+        .map_err(|e| format!("Failed to get session outline: {}", e))
+}
+
+#[tauri::command]
+fn get_idle_histogram(state: State<AppState>, session_id: String) -> Result<db::IdleHistogram, String> {
+    let db = state.db.lock().unwrap();
+    db.get_idle_histogram(&session_id)
+        .map_err(|e| format!("Failed to get idle histogram: {}", e))
+}
+
+// `at` is ms from session start or an RFC3339 timestamp; None when no command was running
+#[tauri::command]
+fn command_at_time(state: State<AppState>, session_id: String, at: db::TimePoint) -> Result<Option<db::Command>, String> {
+    let db = state.db.lock().unwrap();
+    db.command_at_time(&session_id, &at)
+        .map_err(|e| format!("Failed to get command: {}", e))
+}
+
+#[tauri::command]
+fn get_slowest_commands(state: State<AppState>, limit: usize) -> Result<Vec<db::TimedCommand>, String> {
+    let db = state.db.lock().unwrap();
+    db.get_slowest_commands(limit)
+        .map_err(|e| format!("Failed to get commands: {}", e))
+}
+
+#[tauri::command]
+fn get_failed_commands(
+    state: State<AppState>,
+    session_id: Option<String>,
+    limit: usize,
+) -> Result<Vec<db::Command>, String> {
+    let db = state.db.lock().unwrap();
+    db.get_failed_commands(session_id.as_deref(), limit)
+        .map_err(|e| format!("Failed to get commands: {}", e))
+}
+
+// Failed commands with trimmed snippets of their error output, then any error lines
+// printed outside them; see errors::session_errors
+#[tauri::command]
+fn get_session_errors(state: State<AppState>, session_id: String) -> Result<Vec<errors::SessionError>, String> {
+    let db = state.db.lock().unwrap();
+    let commands = db.get_commands(&session_id)
+        .map_err(|e| format!("Failed to get commands: {}", e))?;
+    let events = db.get_events_of_kind(&session_id, "pty_out")
+        .map_err(|e| format!("Failed to get events: {}", e))?;
+
+    Ok(errors::session_errors(&commands, &events))
+}
+
+#[derive(serde::Serialize)]
+struct RedactedScreen {
+    cols: u16,
+    rows: u16,
+    lines: Vec<String>, // Plain text per row
+    ansi: String, // Draws the screen, styles included, into a blank terminal
+}
+
+// The session's final screen with cell rectangles blanked out, for screenshots.
+// Works on the reconstructed grid; the recording itself is untouched.
+#[tauri::command]
+fn replay_session_redacted(
+    state: State<AppState>,
+    session_id: String,
+    regions: Vec<screen::CellRect>,
+    placeholder: Option<char>,
+) -> Result<RedactedScreen, String> {
+    let db = state.db.lock().unwrap();
+    let session = db.get_session(&session_id)
+        .map_err(|e| format!("Failed to get session: {}", e))?
+        .ok_or("Session not found")?;
+    let events = db.get_events(&session_id)
+        .map_err(|e| format!("Failed to get events: {}", e))?;
+    drop(db);
+
+    let mut grid = screen::replay(&events, session.cols.unwrap_or(80), session.rows.unwrap_or(24));
+    for region in &regions {
+        grid.redact(region, placeholder.unwrap_or(screen::DEFAULT_REDACTION_CHAR));
+    }
+    let (cols, rows) = grid.size();
+    Ok(RedactedScreen {
+        cols,
+        rows,
+        lines: grid.text_lines(),
+        ansi: grid.render(),
+    })
+}
+
+#[derive(serde::Serialize)]
+struct ReflowedScreen {
+    cols: u16,
+    rows: u16,
+    reflowed: bool, // False when the screen was kept at its recorded width
+    lines: Vec<String>,
+    ansi: String,
+}
+
+// The session's final screen rewrapped to `new_cols`, for replaying on a display of
+// another width. Stays at the recorded width when the recording can't be reflowed:
+// its width was never recorded, or it ends inside a full-screen app.
+#[tauri::command]
+fn replay_session_reflowed(state: State<AppState>, session_id: String, new_cols: u16) -> Result<ReflowedScreen, String> {
+    if new_cols == 0 {
+        return Err("Width must be at least 1 column".to_string());
+    }
+    let db = state.db.lock().unwrap();
+    let session = db.get_session(&session_id)
+        .map_err(|e| format!("Failed to get session: {}", e))?
+        .ok_or("Session not found")?;
+    let events = db.get_events(&session_id)
+        .map_err(|e| format!("Failed to get events: {}", e))?;
+    drop(db);
+
+    // Wrap points are only meaningful at the width the output was actually drawn at
+    let width_known = session.cols.is_some() || events.iter().any(|e| e.kind == "resize");
+    let original = screen::replay(&events, session.cols.unwrap_or(80), session.rows.unwrap_or(24));
+    let reflowed = width_known.then(|| original.reflow(new_cols)).flatten();
+    let grid = reflowed.as_ref().unwrap_or(&original);
+    let (cols, rows) = grid.size();
+    Ok(ReflowedScreen {
+        cols,
+        rows,
+        reflowed: reflowed.is_some(),
+        lines: grid.text_lines(),
+        ansi: grid.render(),
+    })
+}
+
+#[tauri::command]
+fn export_session_json(state: State<AppState>, session_id: String) -> Result<String, String> {
+    let db = state.db.lock().unwrap();
+
+    // Get session
+    let session = db.get_session(&session_id)
+        .map_err(|e| format!("Failed to get session: {}", e))?
+        .ok_or("Session not found")?;
+
+    // Get commands
+    let commands = db.get_commands(&session_id)
+        .map_err(|e| format!("Failed to get commands: {}", e))?;
+
+    // Get events
+    let events = db.get_events(&session_id)
+        .map_err(|e| format!("Failed to get events: {}", e))?;
+
+    // Build export structure
+    let export = serde_json::json!({
+        "version": "1.0",
+        "exported_at": chrono::Utc::now().to_rfc3339(),
+        "session": session,
+        "commands": commands,
+        "events": events,
+    });
+
+    let json = serde_json::to_string_pretty(&export)
+        .map_err(|e| format!("Failed to serialize: {}", e))?;
+    // An exported session is saved elsewhere, so a pending prune no longer applies
+    db.clear_prune_notice(&session_id).ok();
+    Ok(json)
+}
+
+#[tauri::command]
+fn estimate_export_size(state: State<AppState>, session_id: String, format: String) -> Result<u64, String> {
+    let db = state.db.lock().unwrap();
+    db.get_session(&session_id)
+        .map_err(|e| format!("Failed to get session: {}", e))?
+        .ok_or("Session not found")?;
+    let stats = db
+        .get_export_stats(&session_id)
+        .map_err(|e| format!("Failed to get export stats: {}", e))?;
+
+    export::estimate_size(&stats, &format)
+}
+
+#[tauri::command]
+fn export_session_cast(state: State<AppState>, session_id: String) -> Result<String, String> {
+    let db = state.db.lock().unwrap();
+
+    let session = db.get_session(&session_id)
+        .map_err(|e| format!("Failed to get session: {}", e))?
+        .ok_or("Session not found")?;
+
+    let events = db.get_events(&session_id)
+        .map_err(|e| format!("Failed to get events: {}", e))?;
+
+    let cast = cast::build_cast(&session, &events, None)
+        .map_err(|e| format!("Failed to build cast: {}", e))?;
+    db.clear_prune_notice(&session_id).ok();
+    Ok(cast)
+}
+
+#[derive(serde::Serialize)]
+struct SignedExport {
+    path: String,
+    signature_path: String, // `path` plus ".sig"
+    public_key: String, // PEM, for recipients to pass to verify_export
+}
+
+// Writes the session as a cast (default) or JSON export plus a detached Ed25519
+// signature over the written bytes. `key_path` is a PKCS#8 PEM private key file, or
+// "keychain:<service>/<account>" for one stored in the OS keychain.
+#[tauri::command]
+fn export_session_signed(
+    state: State<AppState>,
+    session_id: String,
+    path: String,
+    key_path: String,
+    format: Option<String>,
+) -> Result<SignedExport, String> {
+    let pem = signing::KeySource::parse(&key_path)
+        .and_then(|source| source.read_pem())
+        .map_err(|e| format!("Failed to read signing key: {:#}", e))?;
+    let key_pair = signing::load_key_pair(&pem).map_err(|e| format!("Failed to load signing key: {:#}", e))?;
+
+    let text = match format.as_deref().unwrap_or("cast") {
+        "cast" => export_session_cast(state, session_id)?,
+        "json" => export_session_json(state, session_id)?,
+        other => return Err(format!("Unknown export format: {}", other)),
+    };
+    let signature_path = format!("{}.sig", path);
+    std::fs::write(&path, &text)
+        .map_err(|e| format!("Failed to write {}: {}", path, e))?;
+    std::fs::write(&signature_path, signing::sign(&key_pair, text.as_bytes()) + "\n")
+        .map_err(|e| format!("Failed to write {}: {}", signature_path, e))?;
+
+    Ok(SignedExport {
+        path,
+        signature_path,
+        public_key: signing::public_key_pem(&key_pair),
+    })
+}
+
+// Checks an export against its signature file and the signer's PEM public key;
+// Ok(false) if the file was altered or signed with a different key
+#[tauri::command]
+fn verify_export(path: String, signature_path: String, public_key_path: String) -> Result<bool, String> {
+    let read = |path: &str| std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e));
+    let data = read(&path)?;
+    let signature = String::from_utf8_lossy(&read(&signature_path)?).to_string();
+    let public_key = String::from_utf8_lossy(&read(&public_key_path)?).to_string();
+    signing::verify(&data, &signature, &public_key).map_err(|e| format!("Failed to verify {}: {:#}", path, e))
+}
+
+// Writes the session as a .cast next to `path` and, when `render` is set and agg is
+// installed, a .gif too. Without agg the cast and a command line to run later are returned.
+// agg can take minutes on a long session, so it runs on its own thread: this returns
+// with `rendering` set and the finished export follows in an "agg-rendered" event.
+#[tauri::command]
+fn export_session_for_agg(
+    app: tauri::AppHandle,
+    state: State<AppState>,
+    session_id: String,
+    path: String,
+    render: Option<bool>,
+) -> Result<agg::AggExport, String> {
+    let cast = export_session_cast(state, session_id)?;
+    let cast_path = std::path::Path::new(&path).with_extension("cast");
+    let gif_path = cast_path.with_extension("gif");
+    std::fs::write(&cast_path, cast)
+        .map_err(|e| format!("Failed to write {}: {}", cast_path.display(), e))?;
+
+    let mut export = agg::AggExport {
+        cast_path: cast_path.to_string_lossy().to_string(),
+        gif_path: gif_path.to_string_lossy().to_string(),
+        command: agg::command_line(&cast_path, &gif_path),
+        ran: false,
+        rendered: false,
+        rendering: false,
+        message: None,
+    };
+    if render.unwrap_or(true) {
+        match agg::find_agg() {
+            Some(binary) => {
+                export.rendering = true;
+                let mut finished = export.clone();
+                // Not tied to a live session, so it isn't reported as one's orphan
+                threads::spawn("agg render", None, move || {
+                    match agg::render(&binary, &cast_path, &gif_path) {
+                        Ok(outcome) => {
+                            finished.ran = true;
+                            finished.rendered = outcome.is_none();
+                            finished.message = outcome;
+                        }
+                        Err(e) => finished.message = Some(format!("{:#}", e)),
+                    }
+                    finished.rendering = false;
+                    app.emit_all("agg-rendered", &finished).ok();
+                });
+            }
+            None => export.message = Some("agg not found on PATH; run the command after installing it".to_string()),
+        }
+    }
+    Ok(export)
+}
+
+// Store an asciinema v2 .cast as an ended session. Its events are timed from the
+// header's timestamp (or now); the cwd is the file's directory.
+#[tauri::command]
+fn import_asciinema(state: State<AppState>, path: String) -> Result<db::Session, String> {
+    let text = std::fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let mut recording = cast::parse_cast(&text).map_err(|e| format!("{:#}", e))?;
+
+    let note = match recording.title {
+        Some(ref title) => format!("Imported from {} ({})", path, title),
+        None => format!("Imported from {}", path),
+    };
+    recording.events.insert(0, cast::RecordedEvent { offset_ms: 0, kind: "marker", data: note });
+
+    let started_at = recording
+        .timestamp
+        .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+        .unwrap_or_else(chrono::Utc::now);
+    let cwd = std::path::Path::new(&path)
+        .parent()
+        .map(|dir| dir.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let shell = recording.shell.clone().unwrap_or_else(|| "asciinema".to_string());
+
+    let db = state.db.lock().unwrap();
+    db.import_recording(&recording, started_at, &cwd, &shell)
+        .map_err(|e| format!("Failed to import cast: {}", e))
+}
+
+#[tauri::command]
+fn export_session_html(
+    state: State<AppState>,
+    session_id: String,
+    path: String,
+    include_input: Option<bool>,
+    clear_separator: Option<String>,
+    line_endings: Option<String>,
+) -> Result<(), String> {
+    let endings = export::LineEndings::parse(line_endings.as_deref())?;
+    let db = state.db.lock().unwrap();
+
+    let session = db.get_session(&session_id)
+        .map_err(|e| format!("Failed to get session: {}", e))?
+        .ok_or("Session not found")?;
+
+    let events = db.get_events(&session_id)
+        .map_err(|e| format!("Failed to get events: {}", e))?;
+
+    let separator = export::clear_separator(clear_separator.as_deref());
+    let html = export::session_html(&session, &events, include_input.unwrap_or(false), separator, endings);
+    std::fs::write(&path, html)
+        .map_err(|e| format!("Failed to write {}: {}", path, e))?;
+    db.clear_prune_notice(&session_id).ok();
+    Ok(())
+}
+
+// Markdown write-up of a session: details, a command table and, unless
+// include_output is false, the key output of each failed command
+#[tauri::command]
+fn export_session_markdown(
+    state: State<AppState>,
+    session_id: String,
+    path: String,
+    include_output: Option<bool>,
+    line_endings: Option<String>,
+) -> Result<(), String> {
+    let endings = export::LineEndings::parse(line_endings.as_deref())?;
+    let db = state.db.lock().unwrap();
+
+    let session = db.get_session(&session_id)
+        .map_err(|e| format!("Failed to get session: {}", e))?
+        .ok_or("Session not found")?;
+    let tags = db.get_session_tags(&session_id)
+        .map_err(|e| format!("Failed to get tags: {}", e))?;
+    let commands = db.get_commands(&session_id)
+        .map_err(|e| format!("Failed to get commands: {}", e))?;
+
+    let failures = if include_output.unwrap_or(true) {
+        let events = db.get_events_of_kind(&session_id, "pty_out")
+            .map_err(|e| format!("Failed to get events: {}", e))?;
+        Some(errors::session_errors(&commands, &events))
+    } else {
+        None
+    };
+    let markdown = export::session_markdown(&session, &tags, &commands, failures.as_deref(), endings);
+    std::fs::write(&path, markdown)
+        .map_err(|e| format!("Failed to write {}: {}", path, e))?;
+    db.clear_prune_notice(&session_id).ok();
+    Ok(())
+}
+
+#[tauri::command]
+fn export_commands_script(
+    state: State<AppState>,
+    session_id: String,
+    path: String,
+    comment_failures: Option<bool>,
+) -> Result<(), String> {
+    let db = state.db.lock().unwrap();
+
+    let session = db.get_session(&session_id)
+        .map_err(|e| format!("Failed to get session: {}", e))?
+        .ok_or("Session not found")?;
+
+    let commands = db.get_commands(&session_id)
+        .map_err(|e| format!("Failed to get commands: {}", e))?;
+
+    let script = export::commands_script(&session, &commands, comment_failures.unwrap_or(true));
+    std::fs::write(&path, script)
+        .map_err(|e| format!("Failed to write {}: {}", path, e))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))
+            .map_err(|e| format!("Failed to make {} executable: {}", path, e))?;
+    }
+    Ok(())
+}
+
+#[derive(serde::Serialize)]
+struct HistoryExport {
+    written: usize,
+    skipped_secrets: usize, // Left out for matching a secret or redaction pattern
+}
+
+// Append recorded commands to a history file in zsh extended or bash format, from one
+// session or (with no session_id) all of them. Appends so an existing history is kept.
+#[tauri::command]
+fn export_shell_history(
+    state: State<AppState>,
+    session_id: Option<String>,
+    path: String,
+    format: String,
+) -> Result<HistoryExport, String> {
+    let db = state.db.lock().unwrap();
+    let commands = match session_id {
+        Some(ref id) => db.get_commands(id),
+        None => db.get_all_commands(),
+    }
+    .map_err(|e| format!("Failed to get commands: {}", e))?;
+
+    let redactions: Vec<regex::Regex> = db
+        .get_setting(settings::REDACTION_PATTERNS)
+        .map_err(|e| e.to_string())?
+        .as_array()
+        .map(|patterns| {
+            patterns
+                .iter()
+                .filter_map(|p| p.as_str().and_then(|p| regex::Regex::new(p).ok()))
+                .collect()
+        })
+        .unwrap_or_default();
+    drop(db);
+
+    let (commands, secret): (Vec<db::Command>, Vec<db::Command>) = commands
+        .into_iter()
+        .filter(|c| c.input.as_deref().is_some_and(|input| !input.trim().is_empty()))
+        .partition(|c| {
+            let input = c.input.as_deref().unwrap_or("");
+            !secrets::contains_secret(input) && !redactions.iter().any(|r| r.is_match(input))
+        });
+    let history = export::shell_history(&commands, &format)?;
+
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(|e| format!("Failed to open {}: {}", path, e))?;
+    std::io::Write::write_all(&mut file, &history)
+        .map_err(|e| format!("Failed to write {}: {}", path, e))?;
+
+    Ok(HistoryExport {
+        written: commands.len(),
+        skipped_secrets: secret.len(),
+    })
 }
 
 #[tauri::command]
-fn start_session(
+fn export_command(
     state: State<AppState>,
-    cols: u16,
-    rows: u16,
-) -> Result<String, String> {
-    let cwd = std::env::current_dir()
-        .unwrap_or_else(|_| std::path::PathBuf::from("/"))
-        .to_string_lossy()
-        .to_string();
+    command_id: String,
+    path: String,
+    format: Option<String>,
+    clear_separator: Option<String>,
+    line_endings: Option<String>,
+) -> Result<(), String> {
+    let endings = export::LineEndings::parse(line_endings.as_deref())?;
+    let db = state.db.lock().unwrap();
 
-    let shell = if cfg!(target_os = "macos") {
-        "/bin/zsh".to_string()
-    } else {
-        std::env::var("SHELL").unwrap_or_else(|_| "/bin/bash".to_string())
+    let command = db.get_command(&command_id)
+        .map_err(|e| format!("Failed to get command: {}", e))?
+        .ok_or("Command not found")?;
+
+    let events = db.get_events_between(&command.session_id, command.started_at, command.ended_at)
+        .map_err(|e| format!("Failed to get events: {}", e))?;
+
+    let contents = match format.as_deref().unwrap_or("text") {
+        "text" => {
+            let separator = export::clear_separator(clear_separator.as_deref());
+            export::command_text(&command, &events, separator, endings)
+        }
+        "cast" => {
+            let mut session = db.get_session(&command.session_id)
+                .map_err(|e| format!("Failed to get session: {}", e))?
+                .ok_or("Session not found")?;
+            // Time the cast from the command start
+            session.started_at = db::Database::millis_to_ts(command.started_at);
+
+            let title = export::command_header(&command)
+                .iter()
+                .map(|(key, value)| format!("{}: {}", key, value))
+                .collect::<Vec<_>>()
+                .join(" | ");
+
+            cast::build_cast(&session, &events, Some(&title))
+                .map_err(|e| format!("Failed to build cast: {}", e))?
+        }
+        other => return Err(format!("Unsupported export format: {}", other)),
     };
 
-    // Create session in database
-    let db = state.db.lock().unwrap();
-    let session = db
-        .create_session(&cwd, &shell)
-        .map_err(|e| format!("Failed to create session: {}", e))?;
+    std::fs::write(&path, contents)
+        .map_err(|e| format!("Failed to write {}: {}", path, e))
+}
 
-    let session_id = session.id.clone();
+#[tauri::command]
+fn validate_replay(state: State<AppState>, session_id: String) -> Result<vt::ReplayReport, String> {
+    let db = state.db.lock().unwrap();
+    let events = db.get_events(&session_id)
+        .map_err(|e| format!("Failed to get events: {}", e))?;
 
-    // Create PTY
-    let pty_session = PtySession::new(session_id.clone(), cols, rows)
-        .map_err(|e| format!("Failed to create PTY: {}", e))?;
+    Ok(vt::validate_events(&events))
+}
 
-    let mut pty = state.pty.lock().unwrap();
-    *pty = Some(pty_session);
+const CLASSIFY_SAMPLE_EVENTS: usize = 200;
+const ENTROPY_SAMPLE_EVENTS: usize = 1000;
 
-    Ok(session_id)
+#[derive(serde::Serialize)]
+struct SessionEntropy {
+    #[serde(flatten)]
+    report: vt::EntropyReport,
+    total_output_bytes: u64,
+    sampled: bool, // Computed from a sample rather than all output
 }
 
+// Byte entropy of a session's output; large sessions are sampled
 #[tauri::command]
-fn send_input(state: State<AppState>, data: Vec<u8>) -> Result<(), String> {
-    let pty = state.pty.lock().unwrap();
-    if let Some(ref session) = *pty {
-        session
-            .write_input(&data)
-            .map_err(|e| format!("Failed to write input: {}", e))?;
+fn get_session_entropy(state: State<AppState>, session_id: String) -> Result<SessionEntropy, String> {
+    let db = state.db.lock().unwrap();
+    db.get_session(&session_id)
+        .map_err(|e| format!("Failed to get session: {}", e))?
+        .ok_or("Session not found")?;
+    let output = db
+        .get_export_stats(&session_id)
+        .map_err(|e| format!("Failed to get output stats: {}", e))?
+        .by_kind
+        .remove("pty_out")
+        .unwrap_or_default();
+    let samples = db
+        .sample_output(&session_id, ENTROPY_SAMPLE_EVENTS)
+        .map_err(|e| format!("Failed to sample output: {}", e))?;
 
-        // Log input to database
+    Ok(SessionEntropy {
+        sampled: (samples.len() as u64) < output.count,
+        report: vt::output_entropy(&samples),
+        total_output_bytes: output.bytes,
+    })
+}
+
+// Ranked guesses like "rust" or "node" from the commands run and marker files in the cwd
+#[tauri::command]
+fn infer_session_context(state: State<AppState>, session_id: String) -> Result<Vec<context::ContextGuess>, String> {
+    let (session, commands) = {
         let db = state.db.lock().unwrap();
-        let data_str = String::from_utf8_lossy(&data).to_string();
-        db.add_event(&session.session_id, "user_in", &data_str)
-            .map_err(|e| format!("Failed to log input: {}", e))?;
-    }
-    Ok(())
+        let session = db
+            .get_session(&session_id)
+            .map_err(|e| format!("Failed to get session: {}", e))?
+            .ok_or("Session not found")?;
+        let commands: Vec<String> = db
+            .get_commands(&session_id)
+            .map_err(|e| format!("Failed to get commands: {}", e))?
+            .into_iter()
+            .filter_map(|c| c.input)
+            .collect();
+        (session, commands)
+    };
+
+    // The directory may be gone by now; commands alone still count
+    let cwd_files: Vec<String> = std::fs::read_dir(&session.cwd)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Ok(context::infer_context(&commands, &cwd_files))
 }
 
 #[tauri::command]
-fn read_output(state: State<AppState>) -> Result<Option<Vec<u8>>, String> {
-    let pty = state.pty.lock().unwrap();
-    if let Some(ref session) = *pty {
-        if let Some(data) = session.read_output() {
-            // Log output to database
-            let db = state.db.lock().unwrap();
-            let data_str = String::from_utf8_lossy(&data).to_string();
-            db.add_event(&session.session_id, "pty_out", &data_str)
-                .ok(); // Don't fail on log errors
+fn classify_session(state: State<AppState>, session_id: String) -> Result<vt::TextProfile, String> {
+    let db = state.db.lock().unwrap();
+    db.get_session(&session_id)
+        .map_err(|e| format!("Failed to get session: {}", e))?
+        .ok_or("Session not found")?;
+    let samples = db
+        .sample_output(&session_id, CLASSIFY_SAMPLE_EVENTS)
+        .map_err(|e| format!("Failed to sample output: {}", e))?;
 
-            return Ok(Some(data));
-        }
-    }
-    Ok(None)
+    Ok(vt::classify_output(&samples))
 }
 
 #[tauri::command]
-fn process_osc_events(state: State<AppState>) -> Result<(), String> {
-    let pty = state.pty.lock().unwrap();
-    if let Some(ref session) = *pty {
-        let events = session.read_osc_events();
-        let session_id = session.session_id.clone();
-        drop(pty); // Release lock before database operations
+fn scan_session_secrets(
+    state: State<AppState>,
+    session_id: String,
+    patterns: Option<Vec<String>>,
+) -> Result<Vec<secrets::SecretMatch>, String> {
+    let db = state.db.lock().unwrap();
+    let events = db.get_events(&session_id)
+        .map_err(|e| format!("Failed to get events: {}", e))?;
 
-        for event in events {
-            match event {
-                OscEvent::CommandText(cmd_text) => {
-                    // Command starting - create new command record
-                    let db = state.db.lock().unwrap();
-                    match db.create_command(&session_id, &cmd_text) {
-                        Ok(cmd_id) => {
-                            println!("🎵 Command started: {}", cmd_text);
-                            let mut current = state.current_command_id.lock().unwrap();
-                            *current = Some(cmd_id);
-                        }
-                        Err(e) => eprintln!("Failed to create command: {}", e),
-                    }
-                }
-                OscEvent::CommandEnd(exit_code) => {
-                    // Command ended - update with exit code
-                    let db = state.db.lock().unwrap();
-                    if let Err(e) = db.end_command(&session_id, exit_code) {
-                        eprintln!("Failed to end command: {}", e);
-                    } else {
-                        let emoji = if exit_code == 0 { "✅" } else { "❌" };
-                        println!("{} Command completed with exit code: {}", emoji, exit_code);
-                    }
-                    let mut current = state.current_command_id.lock().unwrap();
-                    *current = None;
-                }
-                _ => {} // Ignore other OSC events for now
-            }
-        }
-    }
-    Ok(())
+    Ok(secrets::scan_events(&events, patterns.as_deref()))
 }
 
+// Commands that mentioned a path from the sensitive_paths setting (e.g. ~/.ssh),
+// for audits. Only command lines are checked; file contents are never read.
 #[tauri::command]
-fn resize_pty(state: State<AppState>, cols: u16, rows: u16) -> Result<(), String> {
-    let mut pty = state.pty.lock().unwrap();
-    if let Some(ref mut session) = *pty {
-        session
-            .resize(cols, rows)
-            .map_err(|e| format!("Failed to resize: {}", e))?;
-    }
-    Ok(())
+fn get_sensitive_accesses(state: State<AppState>, session_id: String) -> Result<Vec<secrets::SensitiveAccess>, String> {
+    let db = state.db.lock().unwrap();
+    let paths: Vec<String> = db
+        .get_setting(settings::SENSITIVE_PATHS)
+        .map_err(|e| e.to_string())?
+        .as_array()
+        .map(|paths| paths.iter().filter_map(|p| p.as_str().map(str::to_string)).collect())
+        .unwrap_or_default();
+    let commands = db.get_commands(&session_id)
+        .map_err(|e| format!("Failed to get commands: {}", e))?;
+
+    Ok(secrets::sensitive_accesses(&commands, &paths))
 }
 
+// File paths (with any :line:col) mentioned in a session's output, using the
+// path_pattern setting when set
 #[tauri::command]
-fn end_session(state: State<AppState>) -> Result<(), String> {
-    let mut pty = state.pty.lock().unwrap();
-    if let Some(session) = pty.take() {
-        let db = state.db.lock().unwrap();
-        db.end_session(&session.session_id)
-            .map_err(|e| format!("Failed to end session: {}", e))?;
-    }
-    Ok(())
+fn extract_paths(state: State<AppState>, session_id: String) -> Result<Vec<paths::PathMention>, String> {
+    let db = state.db.lock().unwrap();
+    let custom = match db.get_setting(settings::PATH_PATTERN).map_err(|e| e.to_string())? {
+        serde_json::Value::String(pattern) => Some(paths::compile(&pattern).map_err(|e| e.to_string())?),
+        _ => None,
+    };
+    let events = db.get_events_of_kind(&session_id, "pty_out")
+        .map_err(|e| format!("Failed to get events: {}", e))?;
+
+    Ok(paths::extract_paths(&events, custom.as_ref()))
 }
 
+// http(s) URLs printed in a session's output, deduplicated, in order of first appearance
 #[tauri::command]
-fn get_recent_sessions(state: State<AppState>, limit: usize) -> Result<Vec<db::Session>, String> {
+fn extract_urls(state: State<AppState>, session_id: String) -> Result<Vec<urls::UrlMention>, String> {
     let db = state.db.lock().unwrap();
-    db.get_recent_sessions(limit)
-        .map_err(|e| format!("Failed to get sessions: {}", e))
+    let events = db.get_events_of_kind(&session_id, "pty_out")
+        .map_err(|e| format!("Failed to get events: {}", e))?;
+
+    Ok(urls::extract_urls(&events))
 }
 
 #[tauri::command]
-fn get_session_events(state: State<AppState>, session_id: String) -> Result<Vec<db::Event>, String> {
+fn find_sessions_mentioning_path(state: State<AppState>, path: String) -> Result<Vec<db::PathSessionMatch>, String> {
     let db = state.db.lock().unwrap();
-    db.get_events(&session_id)
-        .map_err(|e| format!("Failed to get events: {}", e))
+    db.find_sessions_mentioning_path(&path)
+        .map_err(|e| format!("Failed to search sessions: {}", e))
 }
 
 #[tauri::command]
-fn get_sessions_with_commands(state: State<AppState>, limit: usize) -> Result<Vec<db::SessionSummary>, String> {
+fn check_integrity(state: State<AppState>) -> Result<db::IntegrityReport, String> {
     let db = state.db.lock().unwrap();
-    db.get_sessions_with_commands(limit)
-        .map_err(|e| format!("Failed to get sessions: {}", e))
+    db.check_integrity()
+        .map_err(|e| format!("Failed to check integrity: {}", e))
 }
 
 #[tauri::command]
-fn get_commands(state: State<AppState>, session_id: String) -> Result<Vec<db::Command>, String> {
+fn check_referential_integrity(state: State<AppState>) -> Result<Vec<db::OrphanReport>, String> {
     let db = state.db.lock().unwrap();
-    db.get_commands(&session_id)
-        .map_err(|e| format!("Failed to get commands: {}", e))
+    db.check_referential_integrity()
+        .map_err(|e| format!("Failed to check referential integrity: {}", e))
 }
 
 #[tauri::command]
-fn export_session_json(state: State<AppState>, session_id: String) -> Result<String, String> {
+fn repair_orphans(state: State<AppState>, delete: bool) -> Result<Vec<db::OrphanReport>, String> {
     let db = state.db.lock().unwrap();
+    db.repair_orphans(delete)
+        .map_err(|e| format!("Failed to repair orphans: {}", e))
+}
 
-    // Get session
-    let session = db.get_session(&session_id)
-        .map_err(|e| format!("Failed to get session: {}", e))?
-        .ok_or("Session not found")?;
+#[tauri::command]
+fn get_usage_metrics(state: State<AppState>, start: String, end: String) -> Result<db::UsageMetrics, String> {
+    let db = state.db.lock().unwrap();
+    db.get_usage_metrics(&start, &end)
+        .map_err(|e| format!("Failed to get usage metrics: {}", e))
+}
 
-    // Get commands
-    let commands = db.get_commands(&session_id)
-        .map_err(|e| format!("Failed to get commands: {}", e))?;
+#[tauri::command]
+fn add_session_tag(state: State<AppState>, session_id: String, tag: String) -> Result<(), String> {
+    let db = state.db.lock().unwrap();
+    db.add_session_tag(&session_id, &tag)
+        .map_err(|e| format!("Failed to add tag: {}", e))
+}
 
-    // Get events
-    let events = db.get_events(&session_id)
-        .map_err(|e| format!("Failed to get events: {}", e))?;
+#[tauri::command]
+fn reapply_auto_tags(state: State<AppState>) -> Result<Vec<autotag::AutoTagged>, String> {
+    let db = state.db.lock().unwrap();
+    db.reapply_auto_tags()
+        .map_err(|e| format!("Failed to apply auto-tag rules: {}", e))
+}
 
-    // Build export structure
-    let export = serde_json::json!({
-        "version": "1.0",
-        "exported_at": chrono::Utc::now().to_rfc3339(),
-        "session": session,
-        "commands": commands,
-        "events": events,
-    });
+#[tauri::command]
+fn remove_session_tag(state: State<AppState>, session_id: String, tag: String) -> Result<(), String> {
+    let db = state.db.lock().unwrap();
+    db.remove_session_tag(&session_id, &tag)
+        .map_err(|e| format!("Failed to remove tag: {}", e))
+}
+
+#[tauri::command]
+fn get_session_tags(state: State<AppState>, session_id: String) -> Result<Vec<String>, String> {
+    let db = state.db.lock().unwrap();
+    db.get_session_tags(&session_id)
+        .map_err(|e| format!("Failed to get tags: {}", e))
+}
+
+#[tauri::command]
+fn delete_sessions_where(state: State<AppState>, filter: db::SessionFilter) -> Result<usize, String> {
+    let db = state.db.lock().unwrap();
+    db.delete_sessions_where(&filter)
+        .map_err(|e| format!("Failed to delete sessions: {}", e))
+}
+
+// Output bytes over time in fixed buckets, for a sparkline in the session gallery
+#[tauri::command]
+fn get_session_sparkline(state: State<AppState>, session_id: String) -> Result<Vec<u64>, String> {
+    let db = state.db.lock().unwrap();
+    db.session_sparkline(&session_id)
+        .map_err(|e| format!("Failed to get sparkline: {}", e))
+}
+
+#[tauri::command]
+fn session_content_hash(
+    state: State<AppState>,
+    session_id: String,
+    include_input: Option<bool>,
+) -> Result<String, String> {
+    let db = state.db.lock().unwrap();
+    db.session_content_hash(&session_id, include_input.unwrap_or(false))
+        .map_err(|e| format!("Failed to hash session: {}", e))
+}
+
+#[tauri::command]
+fn find_duplicate_sessions(state: State<AppState>) -> Result<Vec<db::DuplicateGroup>, String> {
+    let db = state.db.lock().unwrap();
+    db.find_duplicate_sessions()
+        .map_err(|e| format!("Failed to find duplicates: {}", e))
+}
+
+#[tauri::command]
+fn search_events(state: State<AppState>, query: String, limit: usize) -> Result<Vec<db::Event>, String> {
+    let db = state.db.lock().unwrap();
+    db.search_events(&query, limit)
+        .map_err(|e| format!("Failed to search events: {}", e))
+}
+
+#[tauri::command]
+fn rebuild_search_index(state: State<AppState>) -> Result<usize, String> {
+    let db = state.db.lock().unwrap();
+    db.rebuild_search_index()
+        .map_err(|e| format!("Failed to rebuild search index: {}", e))
+}
 
-    serde_json::to_string_pretty(&export)
-        .map_err(|e| format!("Failed to serialize: {}", e))
+#[tauri::command]
+fn get_integration_snippet(shell: String) -> Result<String, String> {
+    integration::snippet(&shell)
+        .map(str::to_string)
+        .ok_or_else(|| format!("Unsupported shell: {}", shell))
 }
 
 // Interaction tracking commands
@@ -262,33 +2423,356 @@ fn get_friction_points(
         .map_err(|e| format!("Failed to get friction points: {}", e))
 }
 
+// Size limit checks: on this cadence, or sooner after this much new event data
+const SIZE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+const SIZE_CHECK_TICK: Duration = Duration::from_secs(2);
+const SIZE_CHECK_AFTER_BYTES: u64 = 4 * 1024 * 1024;
+
+// Keep the database under max_db_bytes, evicting old sessions when it grows past it.
+// An eviction's VACUUM holds the db lock, stalling recording and commands meanwhile.
+fn spawn_size_guard(app: tauri::AppHandle) {
+    threads::spawn("size guard", None, move || {
+        let state = app.state::<AppState>();
+        let mut last_check: Option<Instant> = None;
+        loop {
+            let written = {
+                let mut db = state.db.lock().unwrap();
+                // Nothing is written while the idle connection is closed, so sleep until
+                // it reopens. The wait releases the lock.
+                while !db.connection_open() {
+                    db = state.db_maintenance.wait(db).unwrap();
+                }
+                db.written_since_size_check()
+            };
+            let due = last_check.is_none_or(|t| t.elapsed() >= SIZE_CHECK_INTERVAL) || written >= SIZE_CHECK_AFTER_BYTES;
+            if due {
+                last_check = Some(Instant::now());
+                let live_id = state.pty.lock().unwrap().as_ref().map(|s| s.session_id.clone());
+                let mut db = state.db.lock().unwrap();
+                match db.enforce_size_limit(live_id.as_deref()) {
+                    Ok(Some(report)) => println!(
+                        "Size limit: evicted {} sessions, {} -> {} bytes",
+                        report.evicted_session_ids.len(),
+                        report.bytes_before,
+                        report.bytes_after
+                    ),
+                    Ok(None) => {}
+                    Err(e) => eprintln!("Failed to enforce size limit: {}", e),
+                }
+            }
+            std::thread::sleep(SIZE_CHECK_TICK);
+        }
+    });
+}
+
+// WAL checkpoints: every checkpoint_interval_secs, or sooner after this much new event
+// data; read_output wakes the checkpointer once it's been written
+const CHECKPOINT_AFTER_BYTES: u64 = 32 * 1024 * 1024;
+
+// Fold the WAL back into the database file so long, busy sessions don't grow it unbounded.
+// Sleeps until the interval is next up, or until the interval setting changes.
+fn spawn_checkpointer(app: tauri::AppHandle) {
+    threads::spawn("checkpointer", None, move || {
+        let state = app.state::<AppState>();
+        let mut db = state.db.lock().unwrap();
+        let mut last_checkpoint = Instant::now();
+        loop {
+            let interval = db
+                .get_setting(settings::CHECKPOINT_INTERVAL_SECS)
+                .ok()
+                .and_then(|v| v.as_u64())
+                .map_or(Duration::from_secs(300), Duration::from_secs);
+            if last_checkpoint.elapsed() >= interval || db.written_since_checkpoint() >= CHECKPOINT_AFTER_BYTES {
+                last_checkpoint = Instant::now();
+                // Closing the idle connection checkpointed it already
+                if db.connection_open() {
+                    if let Err(e) = db.checkpoint() {
+                        eprintln!("Failed to checkpoint database: {}", e);
+                    }
+                }
+            }
+            // The wait releases the lock
+            let wait = interval.saturating_sub(last_checkpoint.elapsed());
+            db = state.db_maintenance.wait_timeout(db, wait).unwrap().0;
+        }
+    });
+}
+
+#[tauri::command]
+fn checkpoint_database(state: State<AppState>) -> Result<db::CheckpointReport, String> {
+    let db = state.db.lock().unwrap();
+    db.checkpoint()
+        .map_err(|e| format!("Failed to checkpoint database: {}", e))
+}
+
+// Maintenance: merge runs of identical consecutive output events to save space.
+// Lossless for replay and export, which expand them again.
+#[tauri::command]
+fn collapse_repeats(state: State<AppState>, session_id: String) -> Result<db::CollapseReport, String> {
+    let db = state.db.lock().unwrap();
+    db.collapse_repeats(&session_id)
+        .map_err(|e| format!("Failed to collapse repeats: {}", e))
+}
+
+// Run the max_db_bytes check now; None when no limit is set or it isn't exceeded
+#[tauri::command]
+fn enforce_db_size_limit(state: State<AppState>) -> Result<Option<db::EvictionReport>, String> {
+    let live_id = state.pty.lock().unwrap().as_ref().map(|s| s.session_id.clone());
+    let mut db = state.db.lock().unwrap();
+    db.enforce_size_limit(live_id.as_deref())
+        .map_err(|e| format!("Failed to enforce size limit: {}", e))
+}
+
+// Most recent eviction made to stay under max_db_bytes, by either path, since launch
+#[tauri::command]
+fn get_last_eviction(state: State<AppState>) -> Option<db::EvictionReport> {
+    state.db.lock().unwrap().last_eviction()
+}
+
+#[derive(serde::Serialize)]
+struct ThreadReport {
+    total: usize,
+    by_kind: std::collections::BTreeMap<String, usize>,
+    app: Vec<threads::ThreadInfo>, // Not tied to a session
+    live_session: Vec<threads::ThreadInfo>,
+    orphaned: Vec<threads::ThreadInfo>, // Still running for a session that is no longer live
+}
+
+// Background threads running right now, for spotting leaks. A session end hook
+// shows up as orphaned while it runs; anything else there is a thread that outlived
+// its session.
+#[tauri::command]
+fn get_thread_report(state: State<AppState>) -> ThreadReport {
+    let live_id = state.pty.lock().unwrap().as_ref().map(|s| s.session_id.clone());
+    let running = threads::running();
+    let mut report = ThreadReport {
+        total: running.len(),
+        by_kind: std::collections::BTreeMap::new(),
+        app: Vec::new(),
+        live_session: Vec::new(),
+        orphaned: Vec::new(),
+    };
+    for thread in running {
+        *report.by_kind.entry(thread.kind.clone()).or_insert(0) += 1;
+        match thread.session_id {
+            None => report.app.push(thread),
+            Some(ref id) if live_id.as_ref() == Some(id) => report.live_session.push(thread),
+            Some(_) => report.orphaned.push(thread),
+        }
+    }
+    report
+}
+
+// Closes the database connection once it has been idle for db_idle_close_secs. Sleeps
+// until the connection could next be idle long enough, or until the setting changes.
+fn spawn_db_idle_closer(app: tauri::AppHandle) {
+    threads::spawn("db idle closer", None, move || {
+        let state = app.state::<AppState>();
+        let mut db = state.db.lock().unwrap();
+        loop {
+            let idle = db
+                .get_setting(settings::DB_IDLE_CLOSE_SECS)
+                .ok()
+                .and_then(|v| v.as_u64())
+                .map(Duration::from_secs);
+            let wait = idle.map(|idle| {
+                let remaining = idle.saturating_sub(db.idle_for());
+                if !remaining.is_zero() && db.connection_open() {
+                    return remaining;
+                }
+                if let Err(e) = db.close_if_idle(idle) {
+                    eprintln!("Failed to close idle database: {}", e);
+                }
+                // Closed (or failed to): a query may reopen it meanwhile, so look again
+                // a full period later
+                idle
+            });
+            // The wait releases the lock
+            db = match wait {
+                Some(wait) => state.db_maintenance.wait_timeout(db, wait).unwrap().0,
+                None => state.db_maintenance.wait(db).unwrap(),
+            };
+        }
+    });
+}
+
+// Write batched session metadata at most once per metadata_flush_ms
+fn spawn_metadata_flusher(app: tauri::AppHandle) {
+    threads::spawn("metadata flusher", None, move || loop {
+        let interval = {
+            let state = app.state::<AppState>();
+            let mut db = state.db.lock().unwrap();
+            // Closing the idle connection flushed everything, so sleep until it reopens
+            // or metadata is staged. The wait releases the lock.
+            while !db.connection_open() && !db.has_pending_metadata() {
+                db = state.db_maintenance.wait(db).unwrap();
+            }
+            if let Err(e) = db.flush_session_metadata() {
+                eprintln!("Failed to save session metadata: {}", e);
+            }
+            db.get_setting(settings::METADATA_FLUSH_MS)
+                .ok()
+                .and_then(|v| v.as_u64())
+                .unwrap_or(1000)
+        };
+        std::thread::sleep(Duration::from_millis(interval));
+    });
+}
+
 fn main() {
     // Initialize database
-    let db = Database::new().expect("Failed to initialize database");
+    let mut db = Database::new().expect("Failed to initialize database");
+    let db_maintenance = Arc::new(Condvar::new());
+    db.set_maintenance_signal(db_maintenance.clone());
+    let recording_off = !db.persistence_enabled();
+
+    // Apply the retention setting once per launch
+    if let Some(days) = db.get_setting(settings::RETENTION_DAYS).ok().and_then(|v| v.as_u64()) {
+        // Too early for the window to hear an event; it asks get_pending_prunes instead
+        match db.prune_sessions(days, None, prune_grace(&db)) {
+            Ok(outcome) if outcome.deleted == 0 => {}
+            Ok(outcome) => println!("Pruned {} sessions older than {} days", outcome.deleted, days),
+            Err(e) => eprintln!("Failed to prune sessions: {}", e),
+        }
+    }
 
     tauri::Builder::default()
         .manage(AppState {
             db: Mutex::new(db),
             pty: Mutex::new(None),
             current_command_id: Mutex::new(None),
+            allow_clipboard_write: Mutex::new(false),
+            recording_notice_pending: Mutex::new(recording_off),
+            replays: Mutex::new(HashMap::new()),
+            diff_capture: Mutex::new(None),
+            input_modes: Mutex::new(None),
+            focus: Mutex::new(None),
+            runtime_limit: Mutex::new(None),
+            db_maintenance,
         })
         .invoke_handler(tauri::generate_handler![
             start_session,
+            set_session_template,
+            get_templates,
+            set_favorite,
+            get_favorite_sessions,
+            prune_sessions,
+            get_pending_prunes,
+            enforce_db_size_limit,
+            checkpoint_database,
+            collapse_repeats,
+            get_last_eviction,
+            get_thread_report,
+            create_from_template,
+            get_session_tree,
+            reveal_session_cwd,
             send_input,
+            broadcast_input,
+            rerun_last_command,
+            start_stepped_replay,
+            step_replay,
+            replay_inputs_into,
             read_output,
+            attach_observer,
+            read_observer_output,
+            detach_observer,
+            get_scrollback,
+            measure_latency,
+            set_tee,
+            set_abbreviations_enabled,
+            create_input_fifo,
+            start_session_server,
+            stop_session_server,
             process_osc_events,
+            get_session_launch,
+            get_session_env_exports,
+            get_recording_status,
+            acknowledge_recording_degradation,
+            get_persistence_enabled,
+            set_persistence_enabled,
+            get_setting,
+            get_settings,
+            set_setting,
+            get_clipboard_events,
+            get_mode_changes,
+            get_subsessions,
+            get_clipboard_policy,
+            set_clipboard_policy,
             resize_pty,
             end_session,
             get_recent_sessions,
+            get_backend_info,
+            get_live_sessions,
+            get_runtime_status,
+            get_recent_sessions_detailed,
+            get_sessions_sorted,
             get_session_events,
+            get_events_in_window,
+            get_around_marker,
+            get_output_slice,
+            get_styled_runs,
             get_sessions_with_commands,
             get_commands,
+            reparse_markers,
+            normalize_session,
+            validate_timestamps,
+            normalize_timestamps,
+            get_session_outline,
+            get_idle_histogram,
+            command_at_time,
+            get_slowest_commands,
+            get_failed_commands,
+            get_session_errors,
+            replay_session_redacted,
+            replay_session_reflowed,
             export_session_json,
+            estimate_export_size,
+            export_session_cast,
+            export_session_signed,
+            verify_export,
+            export_session_html,
+            export_session_for_agg,
+            import_asciinema,
+            export_command,
+            export_session_markdown,
+            export_commands_script,
+            export_shell_history,
+            validate_replay,
+            classify_session,
+            infer_session_context,
+            get_session_entropy,
+            scan_session_secrets,
+            get_sensitive_accesses,
+            extract_paths,
+            extract_urls,
+            find_sessions_mentioning_path,
+            check_integrity,
+            check_referential_integrity,
+            repair_orphans,
+            session_content_hash,
+            get_session_sparkline,
+            find_duplicate_sessions,
+            search_events,
+            rebuild_search_index,
+            get_integration_snippet,
+            get_usage_metrics,
+            add_session_tag,
+            remove_session_tag,
+            reapply_auto_tags,
+            get_session_tags,
+            delete_sessions_where,
             track_interaction,
             get_interaction_patterns,
             get_common_patterns,
             get_friction_points,
         ])
+        .setup(|app| {
+            spawn_metadata_flusher(app.handle());
+            spawn_size_guard(app.handle());
+            spawn_checkpointer(app.handle());
+            spawn_db_idle_closer(app.handle());
+            Ok(())
+        })
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
@@ -0,0 +1,274 @@
+// Optional best-effort copy of recorded sessions and events to a central archive
+// Records are queued to a background thread, so the local write path never waits on the
+// mirror. While the mirror is down or slow the queue fills, and records past QUEUE_MAX
+// are dropped and counted rather than held in memory.
+
+use anyhow::{bail, Context, Result};
+use crossbeam_channel::{bounded, Receiver, RecvTimeoutError, Sender};
+use rusqlite::{params, Connection};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::db::{Event, Session};
+use crate::threads;
+
+// A batch is sent once it is this large or this old, whichever comes first
+const BATCH_MAX: usize = 500;
+const BATCH_WINDOW: Duration = Duration::from_secs(2);
+
+// Records waiting for the mirror thread before new ones are dropped
+const QUEUE_MAX: usize = 100_000;
+
+// Failed sends are retried with exponential backoff, then the batch is dropped
+const SEND_ATTEMPTS: u32 = 4;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const HTTP_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Where mirrored records go, parsed from the `mirror` setting:
+/// `{"url": "...", "token": "..."}` or `{"sqlite_path": "..."}`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    Http { url: String, token: Option<String> },
+    Sqlite { path: PathBuf },
+}
+
+impl Target {
+    /// None when mirroring is off (a null setting)
+    pub fn from_setting(value: &Value) -> Result<Option<Target>> {
+        if value.is_null() {
+            return Ok(None);
+        }
+        let str_field = |key: &str| value.get(key).and_then(Value::as_str).filter(|s| !s.trim().is_empty());
+
+        match (str_field("url"), str_field("sqlite_path")) {
+            (Some(url), None) => {
+                if !(url.starts_with("http://") || url.starts_with("https://")) {
+                    bail!("Mirror url must be http:// or https://");
+                }
+                let token = str_field("token").map(str::to_string);
+                if token.is_some() && url.starts_with("http://") {
+                    bail!("Mirror token would be sent unencrypted; use an https:// url");
+                }
+                Ok(Some(Target::Http { url: url.to_string(), token }))
+            }
+            (None, Some(path)) => Ok(Some(Target::Sqlite { path: PathBuf::from(path) })),
+            _ => bail!("Mirror needs exactly one of url or sqlite_path"),
+        }
+    }
+}
+
+enum Record {
+    Session(Session),
+    Event(Event),
+}
+
+/// Handle to the mirror thread; dropping it flushes what's queued and stops the thread
+pub struct Mirror {
+    tx: Sender<Record>,
+    dropped: Arc<AtomicU64>, // Records refused by a full queue, not yet reported
+}
+
+impl Mirror {
+    pub fn start(target: Target) -> Result<Mirror> {
+        // Open the SQLite mirror up front so a bad path is reported to the caller
+        let mut sink = match target {
+            Target::Http { url, token } => Sink::Http {
+                agent: ureq::Agent::config_builder()
+                    .timeout_global(Some(HTTP_TIMEOUT))
+                    .build()
+                    .into(),
+                url,
+                token,
+            },
+            Target::Sqlite { path } => Sink::Sqlite(open_sqlite(&path)?),
+        };
+
+        let (tx, rx) = bounded(QUEUE_MAX);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let overflow = Arc::clone(&dropped);
+        threads::spawn("mirror", None, move || {
+            while let Some(batch) = next_batch(&rx) {
+                if let Err(e) = sink.send_with_retry(&batch) {
+                    eprintln!("Mirror: dropped {} records: {}", batch.len(), e);
+                }
+                let skipped = overflow.swap(0, Ordering::Relaxed);
+                if skipped > 0 {
+                    eprintln!("Mirror: dropped {} records while the queue was full", skipped);
+                }
+            }
+        });
+
+        Ok(Mirror { tx, dropped })
+    }
+
+    /// Queue a session's current metadata; the receiving side upserts by id
+    pub fn session(&self, session: &Session) {
+        self.queue(Record::Session(session.clone()));
+    }
+
+    pub fn event(&self, event: &Event) {
+        self.queue(Record::Event(event.clone()));
+    }
+
+    fn queue(&self, record: Record) {
+        if self.tx.try_send(record).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+// Block for the first record, then gather more until the batch is full or its window passes.
+// None once the handle is dropped and the queue is empty.
+fn next_batch(rx: &Receiver<Record>) -> Option<Vec<Record>> {
+    let mut batch = vec![rx.recv().ok()?];
+    let deadline = Instant::now() + BATCH_WINDOW;
+
+    while batch.len() < BATCH_MAX {
+        match rx.recv_deadline(deadline) {
+            Ok(record) => batch.push(record),
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    Some(batch)
+}
+
+enum Sink {
+    Http {
+        agent: ureq::Agent,
+        url: String,
+        token: Option<String>,
+    },
+    Sqlite(Connection),
+}
+
+impl Sink {
+    fn send_with_retry(&mut self, batch: &[Record]) -> Result<()> {
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempt = 1;
+        loop {
+            match self.send(batch) {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt >= SEND_ATTEMPTS => {
+                    return Err(e.context(format!("giving up after {} attempts", attempt)))
+                }
+                Err(_) => {
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, batch: &[Record]) -> Result<()> {
+        match self {
+            Sink::Http { agent, url, token } => {
+                // {"sessions": [...], "events": [...]}, same shapes as the JSON export
+                let mut sessions = Vec::new();
+                let mut events = Vec::new();
+                for record in batch {
+                    match record {
+                        Record::Session(session) => sessions.push(session),
+                        Record::Event(event) => events.push(event),
+                    }
+                }
+                let body = serde_json::json!({ "sessions": sessions, "events": events }).to_string();
+
+                let mut request = agent.post(url.as_str()).content_type("application/json");
+                if let Some(token) = token {
+                    request = request.header("Authorization", format!("Bearer {}", token));
+                }
+                request.send(body.as_str()).context("Mirror request failed")?;
+                Ok(())
+            }
+            Sink::Sqlite(conn) => {
+                let tx = conn.transaction()?;
+                for record in batch {
+                    match record {
+                        Record::Session(s) => {
+                            tx.execute(
+                                "INSERT OR REPLACE INTO sessions
+                                 (id, started_at, ended_at, cwd, shell, cols, rows, parent_session_id)
+                                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                                params![s.id, s.started_at, s.ended_at, s.cwd, s.shell, s.cols, s.rows, s.parent_session_id],
+                            )?;
+                        }
+                        Record::Event(e) => {
+                            tx.execute(
+                                "INSERT OR IGNORE INTO events (id, session_id, ts, kind, data, ts_ms)
+                                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                                params![e.id, e.session_id, e.ts, e.kind, e.data, e.ts_ms],
+                            )?;
+                        }
+                    }
+                }
+                tx.commit()?;
+                Ok(())
+            }
+        }
+    }
+}
+
+// A plain copy of the sessions and events tables, shared by every machine that mirrors into it
+fn open_sqlite(path: &Path) -> Result<Connection> {
+    let conn = Connection::open(path)
+        .with_context(|| format!("Failed to open mirror database {}", path.display()))?;
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS sessions (
+            id TEXT PRIMARY KEY,
+            started_at TEXT NOT NULL,
+            ended_at TEXT,
+            cwd TEXT NOT NULL,
+            shell TEXT NOT NULL,
+            cols INTEGER,
+            rows INTEGER,
+            parent_session_id TEXT
+        );
+        CREATE TABLE IF NOT EXISTS events (
+            id TEXT PRIMARY KEY,
+            session_id TEXT NOT NULL,
+            ts TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            data TEXT NOT NULL,
+            ts_ms INTEGER
+        );
+        CREATE INDEX IF NOT EXISTS idx_events_session_ts ON events(session_id, ts);",
+    )?;
+    Ok(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_target_from_setting() {
+        assert_eq!(Target::from_setting(&Value::Null).unwrap(), None);
+        assert_eq!(
+            Target::from_setting(&json!({"url": "https://archive.example/ingest", "token": "t"})).unwrap(),
+            Some(Target::Http {
+                url: "https://archive.example/ingest".to_string(),
+                token: Some("t".to_string()),
+            })
+        );
+        assert_eq!(
+            Target::from_setting(&json!({"sqlite_path": "/tmp/mirror.db"})).unwrap(),
+            Some(Target::Sqlite { path: PathBuf::from("/tmp/mirror.db") })
+        );
+        assert!(Target::from_setting(&json!({"url": "ftp://x"})).is_err());
+        assert!(Target::from_setting(&json!({"url": "https://x", "sqlite_path": "/tmp/m.db"})).is_err());
+        assert!(Target::from_setting(&json!("https://x")).is_err());
+    }
+
+    #[test]
+    fn test_token_needs_https() {
+        assert!(Target::from_setting(&json!({"url": "http://archive.example/ingest", "token": "t"})).is_err());
+        assert!(Target::from_setting(&json!({"url": "http://archive.example/ingest"})).is_ok());
+        assert!(Target::from_setting(&json!({"url": "https://archive.example/ingest", "token": "t"})).is_ok());
+    }
+}
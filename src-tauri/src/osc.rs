@@ -3,12 +3,16 @@
 
 use base64::{Engine as _, engine::general_purpose};
 
+use crate::vt::{self, Token};
+
 #[derive(Debug, Clone)]
 pub enum OscEvent {
     PromptStart,          // OSC 133;A
+    PromptEnd,            // OSC 133;B, where typed input begins
     CommandStart,         // OSC 133;C
     CommandEnd(i32),      // OSC 133;D;<exit_code>
     CommandText(String),  // OSC 133;VIBE;CMD;<base64>
+    ClipboardSet { selection: String, bytes: usize }, // OSC 52;<selection>;<base64>
 }
 
 pub struct OscParser {
@@ -84,6 +88,19 @@ impl OscParser {
     fn parse_payload(&self) -> Option<OscEvent> {
         let payload = String::from_utf8_lossy(&self.buffer);
 
+        // Clipboard writes come from arbitrary programs, so they aren't nonce-gated
+        if let Some(rest) = payload.strip_prefix("52;") {
+            let (selection, data) = rest.split_once(';')?;
+            if data == "?" {
+                return None; // Clipboard read request
+            }
+            let bytes = general_purpose::STANDARD.decode(data).ok()?.len();
+            return Some(OscEvent::ClipboardSet {
+                selection: selection.to_string(),
+                bytes,
+            });
+        }
+
         // Check if it's an OSC 133 sequence with our nonce
         if !payload.contains(&format!("vibe={}", self.nonce)) {
             return None;
@@ -96,6 +113,7 @@ impl OscParser {
 
         match parts[1] {
             "A" => Some(OscEvent::PromptStart),
+            "B" => Some(OscEvent::PromptEnd),
             "C" => Some(OscEvent::CommandStart),
             part if part.starts_with("D") => {
                 // Extract exit code: "D;0" or just "D"
@@ -125,6 +143,86 @@ impl OscParser {
     }
 }
 
+/// A command rebuilt from recorded output by recorded_commands
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedCommand {
+    pub started_at: i64, // Unix ms of the output chunk that completed its 133;C
+    pub ended_at: Option<i64>,
+    pub exit_code: Option<i32>,
+    pub input: Option<String>,
+}
+
+/// Commands marked in stored output chunks, given as (unix ms, text) oldest first.
+/// Unlike OscParser this takes OSC 133 from any integration, nonce or not, since
+/// the output is already history. Command text comes from 133;VIBE;CMD when present,
+/// otherwise from the echoed line between 133;B and 133;C.
+pub fn recorded_commands(chunks: &[(i64, &str)]) -> Vec<RecordedCommand> {
+    // Sequences can straddle chunk boundaries, so scan the joined text
+    let mut text = String::new();
+    let mut chunk_starts = Vec::with_capacity(chunks.len());
+    for (ms, chunk) in chunks {
+        chunk_starts.push((text.len(), *ms));
+        text.push_str(chunk);
+    }
+    let time_at = |offset: usize| {
+        let idx = chunk_starts.partition_point(|(start, _)| *start <= offset);
+        chunk_starts[idx.saturating_sub(1)].1
+    };
+
+    let mut commands: Vec<RecordedCommand> = Vec::new();
+    let mut open = false; // Last command has started and not ended
+    let mut typed: Option<String> = None; // Echoed input since 133;B
+    for (token, span) in vt::tokenize_spans(&text) {
+        match token {
+            Token::Text(t) => {
+                if let Some(ref mut line) = typed {
+                    line.push_str(t);
+                }
+            }
+            Token::Osc(payload) => {
+                let Some(rest) = payload.strip_prefix("133;") else {
+                    continue;
+                };
+                let parts: Vec<&str> = rest.split(';').collect();
+                let at = time_at(span.end - 1); // When it was complete, as seen live
+                match parts[0] {
+                    "A" => typed = None,
+                    "B" => typed = Some(String::new()),
+                    "C" => {
+                        let input = typed.take().map(|line| line.trim().to_string()).filter(|line| !line.is_empty());
+                        commands.push(RecordedCommand {
+                            started_at: at,
+                            ended_at: None,
+                            exit_code: None,
+                            input,
+                        });
+                        open = true;
+                    }
+                    "D" if open => {
+                        if let Some(command) = commands.last_mut() {
+                            command.ended_at = Some(at);
+                            command.exit_code = Some(parts.get(1).and_then(|code| code.parse().ok()).unwrap_or(0));
+                        }
+                        open = false;
+                    }
+                    "VIBE" if open && parts.get(1) == Some(&"CMD") => {
+                        let decoded = parts
+                            .get(2)
+                            .and_then(|b64| general_purpose::STANDARD.decode(b64).ok())
+                            .and_then(|bytes| String::from_utf8(bytes).ok());
+                        if let (Some(command), Some(input)) = (commands.last_mut(), decoded) {
+                            command.input = Some(input);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+    commands
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -150,4 +248,36 @@ mod tests {
             panic!("Expected CommandEnd event");
         }
     }
+
+    #[test]
+    fn test_clipboard_set() {
+        let mut parser = OscParser::new("test123".to_string());
+        let events = parser.feed(b"\x1b]52;c;aGVsbG8=\x07\x1b]52;c;?\x07");
+        assert_eq!(events.len(), 1);
+        if let OscEvent::ClipboardSet { ref selection, bytes } = events[0] {
+            assert_eq!(selection, "c");
+            assert_eq!(bytes, 5);
+        } else {
+            panic!("Expected ClipboardSet event");
+        }
+    }
+
+    #[test]
+    fn test_recorded_commands() {
+        let chunks = [
+            (1000, "\x1b]133;A\x07$ \x1b]133;B\x07git st"),
+            (2000, "atus\r\n\x1b]133;C\x07On branch main\r\n\x1b]13"),
+            (3000, "3;D;1\x07\x1b]133;A;vibe=x\x07$ "),
+            (4000, "\x1b]133;C;vibe=x\x07\x1b]133;VIBE;CMD;bHM=;vibe=x\x07a b\r\n\x1b]133;D;0;vibe=x\x07"),
+            (5000, "\x1b]133;D;0\x07"), // No command open
+        ];
+        let commands = recorded_commands(&chunks);
+        assert_eq!(
+            commands,
+            vec![
+                RecordedCommand { started_at: 2000, ended_at: Some(3000), exit_code: Some(1), input: Some("git status".to_string()) },
+                RecordedCommand { started_at: 4000, ended_at: Some(4000), exit_code: Some(0), input: Some("ls".to_string()) },
+            ]
+        );
+    }
 }
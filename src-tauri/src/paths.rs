@@ -0,0 +1,142 @@
+// File path detection in recorded output, for jump-to-file navigation
+// Matches run on escape-stripped text so colored compiler output parses cleanly
+
+use anyhow::{bail, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use crate::db::Event;
+use crate::vt;
+
+/// Path-like tokens with an optional :line[:col] suffix. Custom patterns must have a
+/// `path` group and may have `line` and `col` groups.
+pub const DEFAULT_PATTERN: &str = r"(?P<path>[\w.~/@+-]*[\w/@+-])(?::(?P<line>\d+)(?::(?P<col>\d+))?)?";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PathMention {
+    pub path: String,
+    pub line: Option<u32>,
+    pub col: Option<u32>,
+    pub event_ids: Vec<String>, // Output events it appears in, in order
+}
+
+/// Compile a path pattern, checking it has the groups extract_paths reads
+pub fn compile(pattern: &str) -> Result<Regex> {
+    let regex = Regex::new(pattern)?;
+    if !regex.capture_names().any(|name| name == Some("path")) {
+        bail!("Path pattern needs a named group `path`");
+    }
+    Ok(regex)
+}
+
+fn default_regex() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| compile(DEFAULT_PATTERN).expect("invalid built-in path pattern"))
+}
+
+/// Paths mentioned in pty_out events, one entry per distinct path/line/col in order
+/// of first appearance. `custom` replaces the default pattern and its filtering.
+pub fn extract_paths(events: &[Event], custom: Option<&Regex>) -> Vec<PathMention> {
+    let regex = custom.unwrap_or_else(|| default_regex());
+    let mut mentions: Vec<PathMention> = Vec::new();
+    let mut index: HashMap<(String, Option<u32>, Option<u32>), usize> = HashMap::new();
+
+    for event in events.iter().filter(|e| e.kind == "pty_out") {
+        let text = vt::strip_ansi(&event.data);
+        for caps in regex.captures_iter(&text) {
+            let Some(path) = caps.name("path") else {
+                continue;
+            };
+            let number = |group: &str| caps.name(group).and_then(|m| m.as_str().parse::<u32>().ok());
+            let (line, col) = (number("line"), number("col"));
+
+            let preceded_by_colon = text[..path.start()].ends_with(':');
+            if custom.is_none() && !looks_like_path(path.as_str(), line.is_some(), preceded_by_colon) {
+                continue;
+            }
+
+            let key = (path.as_str().to_string(), line, col);
+            let slot = *index.entry(key).or_insert_with(|| {
+                mentions.push(PathMention {
+                    path: path.as_str().to_string(),
+                    line,
+                    col,
+                    event_ids: Vec::new(),
+                });
+                mentions.len() - 1
+            });
+            let ids = &mut mentions[slot].event_ids;
+            if ids.last() != Some(&event.id) {
+                ids.push(event.id.clone());
+            }
+        }
+    }
+    mentions
+}
+
+/// Occurrences of `path` in escape-stripped text that aren't part of a longer name.
+/// A leading directory is allowed, so "main.rs" counts inside "src/main.rs".
+pub fn count_mentions(text: &str, path: &str) -> usize {
+    if path.is_empty() {
+        return 0;
+    }
+    let text = vt::strip_ansi(text);
+    let continues_name = |c: char| c.is_alphanumeric() || c == '_' || c == '-';
+    text.match_indices(path)
+        .filter(|(start, _)| {
+            let before = text[..*start].chars().next_back();
+            let after = text[start + path.len()..].chars().next();
+            !before.is_some_and(|c| continues_name(c) || c == '.') && !after.is_some_and(continues_name)
+        })
+        .count()
+}
+
+// The default pattern matches any word; keep ones with a directory part, or a
+// file name with an extension and a line number. URLs ("https://...") are skipped.
+fn looks_like_path(path: &str, has_line: bool, preceded_by_colon: bool) -> bool {
+    if preceded_by_colon || path.starts_with("//") {
+        return false;
+    }
+    let has_dir = path.contains('/') && path.trim_matches('/').contains(|c: char| c.is_alphanumeric());
+    let file = path.rsplit('/').next().unwrap_or(path);
+    let has_extension = file.rfind('.').is_some_and(|dot| dot > 0 && dot < file.len() - 1);
+    has_dir || (has_extension && has_line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::event;
+
+    #[test]
+    fn test_extract_paths() {
+        let events = [
+            event("a", "error[E0308]: mismatched types\r\n  \x1b[1;34m-->\x1b[0m src/main.rs:42:7\r\n"),
+            event("b", "see https://example.com/docs and ./build.sh, e.g. lib.rs:9\r\n"),
+            event("c", "warning at src/main.rs:42:7 again; /tmp/\r\n"),
+        ];
+        let found: Vec<(String, Option<u32>, Option<u32>, usize)> = extract_paths(&events, None)
+            .into_iter()
+            .map(|m| (m.path, m.line, m.col, m.event_ids.len()))
+            .collect();
+
+        assert_eq!(
+            found,
+            vec![
+                ("src/main.rs".to_string(), Some(42), Some(7), 2),
+                ("./build.sh".to_string(), None, None, 1),
+                ("lib.rs".to_string(), Some(9), None, 1),
+                ("/tmp/".to_string(), None, None, 1),
+            ]
+        );
+        assert!(compile(r"(?P<file>\S+)").is_err());
+    }
+
+    #[test]
+    fn test_count_mentions() {
+        assert_eq!(count_mentions("src/main.rs:42 main.rs domain.rs main.rsx main.rs.", "main.rs"), 3);
+        assert_eq!(count_mentions("\x1b[35msrc/main.rs\x1b[0m", "src/main.rs"), 1);
+    }
+}
@@ -1,56 +1,179 @@
 use anyhow::{Context, Result};
-use crossbeam_channel::{unbounded, Receiver, Sender};
-use portable_pty::{CommandBuilder, NativePtySystem, PtyPair, PtySize, PtySystem};
+use bytes::{Bytes, BytesMut};
+use crossbeam_channel::{bounded, unbounded, Receiver, Sender, TryRecvError};
+use chrono::Utc;
+use portable_pty::{Child, CommandBuilder, NativePtySystem, PtyPair, PtySystem};
+use std::collections::HashMap;
+use std::fs::File;
 use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 
+use crate::abbrev::Expander;
+use crate::echo::EchoFilter;
+use crate::fifo::InputFifo;
+use crate::focus::Foreground;
 use crate::osc::{OscEvent, OscParser};
+use crate::threads;
+use crate::vt;
+use crate::ws::SessionServer;
 use crate::zdotdir::ZdotdirSetup;
 
+// Cells plus, when the frontend knows them, the canvas size in pixels (0 = unknown)
+pub use portable_pty::PtySize;
+
+// Messages for the writer thread; Shutdown lets it exit even while senders remain
+enum WriterMsg {
+    Data(Bytes),
+    Shutdown,
+}
+
+/// Cursor position request (DSR) sent to the frontend's terminal to time its round trip
+pub const LATENCY_QUERY: &[u8] = b"\x1b[6n";
+
+// An outstanding latency measurement, answered by the terminal's cursor position report
+struct LatencyProbe {
+    queued_at: Instant,
+    delivered: bool, // Handed to the frontend via read_output
+    done_tx: Sender<Duration>,
+}
+
+// Optional live copy of raw output; a failed write disables it and leaves a note
+#[derive(Default)]
+struct Tee {
+    file: Option<File>,
+    error: Option<String>,
+}
+
+// In-memory scrollback kept for reattach; the DB log is the permanent record
+const SCROLLBACK_LIMIT: usize = 1024 * 1024;
+
+// The reader buffer holds several reads so chunks can be split off without copying
+const READS_PER_BUFFER: usize = 8;
+
+pub const DEFAULT_READ_BYTES: usize = 8192;
+pub const DEFAULT_COALESCE_MS: u64 = 0;
+pub const DEFAULT_BATCH_BYTES: usize = 64 * 1024;
+
+/// Latency/throughput tradeoff for the reader, read from settings at spawn.
+/// Each chunk handed on is one read_output result and one pty_out row, so merging
+/// reads also batches the log writes.
+#[derive(Debug, Clone, Copy)]
+pub struct ReadTuning {
+    pub read_bytes: usize, // Largest single PTY read
+    pub coalesce: Duration, // How long to wait for more output to merge into a chunk; zero sends each read at once
+    pub batch_bytes: usize, // A merged chunk is sent once it reaches this size
+}
+
+impl Default for ReadTuning {
+    fn default() -> Self {
+        ReadTuning {
+            read_bytes: DEFAULT_READ_BYTES,
+            coalesce: Duration::from_millis(DEFAULT_COALESCE_MS),
+            batch_bytes: DEFAULT_BATCH_BYTES,
+        }
+    }
+}
+
+// Chunks an observer may have unread before it's detached for falling behind
+const OBSERVER_BACKLOG: usize = 4096;
+
+// How often an idle reader wakes to check for shutdown
+const READ_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// How long a dropped session waits for its writer to drain, before and again after
+// killing the shell
+const WRITER_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
 pub struct PtySession {
     pub session_id: String,
     pub nonce: String,
+    pub argv: Vec<String>, // Command line the shell was spawned with
     pty_pair: PtyPair,
-    output_rx: Receiver<Vec<u8>>,
+    child: Mutex<Box<dyn Child + Send + Sync>>,
+    last_activity_ms: Arc<AtomicI64>, // Unix ms of the latest output or input
+    output_bytes: Arc<AtomicU64>, // Total read from the PTY
+    rate_sample: Mutex<(Instant, u64)>, // output_bytes when the rate was last sampled
+    idle_gap_threshold_ms: Arc<AtomicU64>, // 0 = idle gaps aren't reported
+    idle_gap: Arc<Mutex<Option<Duration>>>, // Silence that just ended, awaiting a marker
+    output_rx: Receiver<Bytes>,
     osc_events_rx: Receiver<OscEvent>,
-    writer_tx: Sender<Vec<u8>>,
+    writer_tx: Sender<WriterMsg>,
+    scrollback: Arc<Mutex<Vec<u8>>>,
+    observers: Arc<Mutex<HashMap<String, Sender<Bytes>>>>, // Read-only viewers, fed by the reader
+    observer_rx: Mutex<HashMap<String, Receiver<Bytes>>>,
+    latency_probe: Mutex<Option<LatencyProbe>>,
+    echo_filter: Mutex<Option<EchoFilter>>, // Set while suppress_echo is on
+    abbreviations: Arc<Mutex<Expander>>, // The reader keeps it told where the prompt is
+    tee: Arc<Mutex<Tee>>,
+    input_fifo: Mutex<Option<InputFifo>>, // Removed along with the session
+    session_server: Mutex<Option<SessionServer>>, // Stopped along with the session
     zdotdir: ZdotdirSetup,
-    _reader_handle: thread::JoinHandle<()>,
-    _writer_handle: thread::JoinHandle<()>,
+    reader_shutdown: Arc<AtomicBool>,
+    reader_handle: Option<thread::JoinHandle<()>>,
+    writer_handle: Option<thread::JoinHandle<()>>,
+}
+
+/// Platform default shell
+pub fn default_shell() -> String {
+    if cfg!(target_os = "macos") {
+        "/bin/zsh".to_string()
+    } else {
+        std::env::var("SHELL").unwrap_or_else(|_| "/bin/bash".to_string())
+    }
+}
+
+/// Pick the shell to spawn, falling back when the requested binary is gone.
+/// Returns the shell to use and, if it was substituted, a note explaining why.
+pub fn resolve_shell(requested: Option<&str>) -> (String, Option<String>) {
+    let requested = requested.map(str::to_string).unwrap_or_else(default_shell);
+    if Path::new(&requested).is_file() {
+        return (requested, None);
+    }
+
+    let fallback = [default_shell().as_str(), "/bin/zsh", "/bin/bash", "/bin/sh"]
+        .into_iter()
+        .find(|candidate| Path::new(candidate).is_file())
+        .unwrap_or("/bin/sh")
+        .to_string();
+
+    let note = format!("Shell {} not found; using {}", requested, fallback);
+    (fallback, Some(note))
 }
 
 impl PtySession {
-    pub fn new(session_id: String, cols: u16, rows: u16) -> Result<Self> {
-        let pty_system = NativePtySystem::default();
-
-        // Create PTY
-        let pty_pair = pty_system
-            .openpty(PtySize {
-                rows,
-                cols,
-                pixel_width: 0,
-                pixel_height: 0,
-            })
-            .context("Failed to create PTY")?;
+    pub fn new(
+        session_id: String,
+        shell: &str,
+        cwd: &Path,
+        env: &[(String, String)],
+        size: PtySize,
+        tee_file: Option<File>,
+        tuning: ReadTuning,
+    ) -> Result<Self> {
+        let pty_pair = open_pty(size)?;
 
         // Set up ZDOTDIR wrapper for shell integration
         let zdotdir = ZdotdirSetup::create(&session_id)?;
 
-        // Determine shell
-        let shell = if cfg!(target_os = "macos") {
-            "/bin/zsh".to_string()
-        } else {
-            std::env::var("SHELL").unwrap_or_else(|_| "/bin/bash".to_string())
-        };
-
-        // Get current directory
-        let cwd = std::env::current_dir().unwrap_or_else(|_| std::path::PathBuf::from("/"));
-
-        // Spawn shell with ZDOTDIR
-        let mut cmd = CommandBuilder::new(&shell);
+        // Spawn shell with extra env layered over the inherited one, then ZDOTDIR
+        let mut cmd = CommandBuilder::new(shell);
         cmd.cwd(cwd);
+        for (key, value) in env {
+            cmd.env(key, value);
+        }
         cmd.env("ZDOTDIR", zdotdir.zdotdir_path.to_str().unwrap());
+        // Also exported directly so manually installed rc snippets can emit markers
+        cmd.env("VIBE_NONCE", &zdotdir.nonce);
+        cmd.env("VIBE_SESSION_ID", &session_id);
+        let argv = cmd
+            .get_argv()
+            .iter()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect();
 
         let child = pty_pair
             .slave
@@ -64,9 +187,9 @@ impl PtySession {
         );
 
         // Create channels
-        let (output_tx, output_rx) = unbounded::<Vec<u8>>();
+        let (output_tx, output_rx) = unbounded::<Bytes>();
         let (osc_events_tx, osc_events_rx) = unbounded::<OscEvent>();
-        let (writer_tx, writer_rx) = unbounded::<Vec<u8>>();
+        let (writer_tx, writer_rx) = unbounded::<WriterMsg>();
 
         // Create OSC parser
         let nonce = zdotdir.nonce.clone();
@@ -78,19 +201,90 @@ impl PtySession {
             .try_clone_reader()
             .context("Failed to clone PTY reader")?;
 
-        let reader_handle = thread::spawn(move || {
-            let mut buf = [0u8; 8192];
+        let scrollback = Arc::new(Mutex::new(Vec::new()));
+        let reader_scrollback = Arc::clone(&scrollback);
+
+        let observers: Arc<Mutex<HashMap<String, Sender<Bytes>>>> = Arc::new(Mutex::new(HashMap::new()));
+        let reader_observers = Arc::clone(&observers);
+
+        let tee = Arc::new(Mutex::new(Tee {
+            file: tee_file,
+            error: None,
+        }));
+        let reader_tee = Arc::clone(&tee);
+
+        let last_activity_ms = Arc::new(AtomicI64::new(Utc::now().timestamp_millis()));
+        let reader_activity = Arc::clone(&last_activity_ms);
+        let output_bytes = Arc::new(AtomicU64::new(0));
+        let reader_output_bytes = Arc::clone(&output_bytes);
+
+        let idle_gap_threshold_ms = Arc::new(AtomicU64::new(0));
+        let idle_gap = Arc::new(Mutex::new(None));
+        let (reader_idle_threshold, reader_idle_gap) = (Arc::clone(&idle_gap_threshold_ms), Arc::clone(&idle_gap));
+
+        let abbreviations = Arc::new(Mutex::new(Expander::default()));
+        let reader_abbreviations = Arc::clone(&abbreviations);
+
+        let reader_fd = master_fd(&pty_pair);
+        let reader_shutdown = Arc::new(AtomicBool::new(false));
+        let shutdown = Arc::clone(&reader_shutdown);
+
+        let reader_handle = threads::spawn("pty reader", Some(session_id.clone()), move || {
+            // Each chunk is split off the shared buffer and handed on as-is; the
+            // allocation is reclaimed once every consumer has dropped its chunks
+            let buffer_bytes = tuning.read_bytes * READS_PER_BUFFER;
+            let mut buf = BytesMut::with_capacity(buffer_bytes);
+            let mut last_output = Instant::now();
             loop {
-                match reader.read(&mut buf) {
+                // Only read once data is waiting, so a child that holds the PTY open
+                // without writing can't keep this thread from seeing shutdown
+                if shutdown.load(Ordering::Relaxed) {
+                    break;
+                }
+                if !wait_readable(reader_fd, READ_POLL_INTERVAL) {
+                    continue;
+                }
+                match read_into(&mut reader, &mut buf, tuning.read_bytes, buffer_bytes) {
                     Ok(0) => {
                         println!("PTY reader: EOF");
                         break;
                     }
-                    Ok(n) => {
-                        let data = buf[..n].to_vec();
+                    Ok(_) => {
+                        // Merge output that follows within the window. Needs an fd to poll,
+                        // or the extra read would block; EOF and errors surface on the next read.
+                        if reader_fd.is_some() && !tuning.coalesce.is_zero() {
+                            let deadline = Instant::now() + tuning.coalesce;
+                            while buf.len() < tuning.batch_bytes {
+                                let remaining = deadline.saturating_duration_since(Instant::now());
+                                if remaining.is_zero() || !wait_readable(reader_fd, remaining) {
+                                    break;
+                                }
+                                let room = tuning.read_bytes.min(tuning.batch_bytes - buf.len());
+                                if !matches!(read_into(&mut reader, &mut buf, room, buffer_bytes), Ok(n) if n > 0) {
+                                    break;
+                                }
+                            }
+                        }
+                        let data = buf.split().freeze();
+                        reader_activity.store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+                        reader_output_bytes.fetch_add(data.len() as u64, Ordering::Relaxed);
+
+                        // Output resuming after a long silence; noted before this chunk is handed on
+                        let threshold = reader_idle_threshold.load(Ordering::Relaxed);
+                        let silence = last_output.elapsed();
+                        if threshold > 0 && silence >= Duration::from_millis(threshold) {
+                            if let Ok(mut gap) = reader_idle_gap.lock() {
+                                *gap = Some(silence);
+                            }
+                        }
+                        last_output = Instant::now();
 
                         // Parse OSC sequences
                         let events = osc_parser.feed(&data);
+                        if let Ok(mut expander) = reader_abbreviations.lock() {
+                            events.iter().for_each(|event| expander.osc(event));
+                            expander.output(&data);
+                        }
                         for event in events {
                             if osc_events_tx.send(event).is_err() {
                                 println!("OSC events channel closed");
@@ -98,6 +292,39 @@ impl PtySession {
                             }
                         }
 
+                        // Mirror into scrollback; a full clear starts it over
+                        if let Ok(mut sb) = reader_scrollback.lock() {
+                            match vt::last_clear_offset(&data) {
+                                Some(offset) => {
+                                    sb.clear();
+                                    sb.extend_from_slice(&data[offset..]);
+                                }
+                                None => sb.extend_from_slice(&data),
+                            }
+                            if sb.len() > SCROLLBACK_LIMIT {
+                                let excess = sb.len() - SCROLLBACK_LIMIT;
+                                sb.drain(..excess);
+                            }
+
+                            // Fan out under the scrollback lock so a new observer's seed
+                            // and its first chunk neither overlap nor leave a gap
+                            if let Ok(mut observers) = reader_observers.lock() {
+                                observers.retain(|_, tx| tx.try_send(data.clone()).is_ok());
+                            }
+                        }
+
+                        // Append to the tee file; on error (e.g. disk full) stop teeing, keep the session
+                        if let Ok(mut tee) = reader_tee.lock() {
+                            let result = match tee.file {
+                                Some(ref mut file) => file.write_all(&data),
+                                None => Ok(()),
+                            };
+                            if let Err(e) = result {
+                                tee.file = None;
+                                tee.error = Some(format!("Tee disabled after write error: {}", e));
+                            }
+                        }
+
                         // Send raw output to frontend
                         if output_tx.send(data).is_err() {
                             println!("PTY reader: output channel closed");
@@ -120,8 +347,8 @@ impl PtySession {
                 .context("Failed to take PTY writer")?,
         ));
 
-        let writer_handle = thread::spawn(move || {
-            while let Ok(data) = writer_rx.recv() {
+        let writer_handle = threads::spawn("pty writer", Some(session_id.clone()), move || {
+            while let Ok(WriterMsg::Data(data)) = writer_rx.recv() {
                 if let Ok(mut w) = writer.lock() {
                     if w.write_all(&data).is_err() {
                         break;
@@ -134,21 +361,194 @@ impl PtySession {
         Ok(PtySession {
             session_id,
             nonce,
+            argv,
             pty_pair,
+            child: Mutex::new(child),
+            last_activity_ms,
+            output_bytes,
+            rate_sample: Mutex::new((Instant::now(), 0)),
+            idle_gap_threshold_ms,
+            idle_gap,
             output_rx,
             osc_events_rx,
             writer_tx,
+            scrollback,
+            observers,
+            observer_rx: Mutex::new(HashMap::new()),
+            latency_probe: Mutex::new(None),
+            echo_filter: Mutex::new(None),
+            abbreviations,
+            tee,
+            input_fifo: Mutex::new(None),
+            session_server: Mutex::new(None),
             zdotdir,
-            _reader_handle: reader_handle,
-            _writer_handle: writer_handle,
+            reader_shutdown,
+            // Without an fd to poll the read can't be interrupted, so leave the thread detached
+            reader_handle: reader_fd.map(|_| reader_handle),
+            writer_handle: Some(writer_handle),
         })
     }
 
     /// Read output from PTY (for frontend display)
-    pub fn read_output(&self) -> Option<Vec<u8>> {
+    pub fn read_output(&self) -> Option<Bytes> {
         self.output_rx.try_recv().ok()
     }
 
+    /// Current screen's output since the last full clear (for reattach)
+    pub fn scrollback(&self) -> Vec<u8> {
+        self.scrollback.lock().map(|sb| sb.clone()).unwrap_or_default()
+    }
+
+    /// Add a read-only viewer of this session's output, starting from the current
+    /// screen. Returns its id for read_observer and detach_observer.
+    pub fn attach_observer(&self) -> String {
+        let id = uuid::Uuid::new_v4().to_string();
+        let (tx, rx) = bounded(OBSERVER_BACKLOG);
+
+        let scrollback = self.scrollback.lock().unwrap();
+        if !scrollback.is_empty() {
+            tx.try_send(Bytes::copy_from_slice(&scrollback)).ok();
+        }
+        self.observers.lock().unwrap().insert(id.clone(), tx);
+        drop(scrollback);
+
+        self.observer_rx.lock().unwrap().insert(id.clone(), rx);
+        id
+    }
+
+    /// Next output chunk for an observer, if any. Errors once the observer is gone,
+    /// including when it fell more than OBSERVER_BACKLOG chunks behind.
+    pub fn read_observer(&self, observer_id: &str) -> Result<Option<Bytes>> {
+        let mut receivers = self.observer_rx.lock().unwrap();
+        let rx = receivers
+            .get(observer_id)
+            .ok_or_else(|| anyhow::anyhow!("Observer not found"))?;
+        match rx.try_recv() {
+            Ok(data) => Ok(Some(data)),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => {
+                receivers.remove(observer_id);
+                anyhow::bail!("Observer fell behind and was detached")
+            }
+        }
+    }
+
+    /// Stop feeding an observer; false if it wasn't attached
+    pub fn detach_observer(&self, observer_id: &str) -> bool {
+        self.observers.lock().unwrap().remove(observer_id);
+        self.observer_rx.lock().unwrap().remove(observer_id).is_some()
+    }
+
+    /// Start (or with None, stop) appending raw output to a file
+    pub fn set_tee(&self, file: Option<File>) {
+        if let Ok(mut tee) = self.tee.lock() {
+            tee.file = file;
+        }
+    }
+
+    pub fn input_fifo_path(&self) -> Option<PathBuf> {
+        self.input_fifo.lock().unwrap().as_ref().map(|fifo| fifo.path.clone())
+    }
+
+    /// Tie an input FIFO's lifetime to this session
+    pub fn set_input_fifo(&self, fifo: InputFifo) {
+        *self.input_fifo.lock().unwrap() = Some(fifo);
+    }
+
+    pub fn session_server_url(&self) -> Option<String> {
+        self.session_server.lock().unwrap().as_ref().map(|server| server.url.clone())
+    }
+
+    /// Tie a WebSocket server's lifetime to this session; None stops the current one
+    pub fn set_session_server(&self, server: Option<SessionServer>) -> bool {
+        std::mem::replace(&mut *self.session_server.lock().unwrap(), server).is_some()
+    }
+
+    /// Report output that resumes after at least `threshold` of silence (None = never)
+    pub fn set_idle_gap_threshold(&self, threshold: Option<Duration>) {
+        let ms = threshold.map(|t| t.as_millis() as u64).unwrap_or(0);
+        self.idle_gap_threshold_ms.store(ms, Ordering::Relaxed);
+    }
+
+    /// Start or stop tracking input so its echo can be dropped from logged output
+    pub fn set_echo_suppression(&self, enabled: bool) {
+        let mut filter = self.echo_filter.lock().unwrap();
+        if enabled != filter.is_some() {
+            *filter = enabled.then(EchoFilter::default);
+        }
+    }
+
+    /// Replace the abbreviations typed input is expanded from
+    pub fn set_abbreviations(&self, abbreviations: HashMap<String, String>) {
+        self.abbreviations.lock().unwrap().set_abbreviations(abbreviations);
+    }
+
+    /// Turn abbreviation expansion on or off for this session only
+    pub fn set_abbreviations_enabled(&self, enabled: bool) {
+        self.abbreviations.lock().unwrap().set_enabled(enabled);
+    }
+
+    /// Output text for the log, minus the echo of recent input when suppression is on
+    pub fn strip_echo(&self, output: String) -> String {
+        match *self.echo_filter.lock().unwrap() {
+            Some(ref mut filter) => filter.filter(&output),
+            None => output,
+        }
+    }
+
+    /// Length of the idle gap that just ended, reported once
+    pub fn take_idle_gap(&self) -> Option<Duration> {
+        self.idle_gap.lock().ok().and_then(|mut gap| gap.take())
+    }
+
+    /// Why the tee was disabled, reported once
+    pub fn take_tee_error(&self) -> Option<String> {
+        self.tee.lock().ok().and_then(|mut tee| tee.error.take())
+    }
+
+    /// Queue a DSR for the terminal; the receiver yields the round trip once it answers
+    pub fn start_latency_probe(&self) -> Result<Receiver<Duration>> {
+        let mut probe = self.latency_probe.lock().unwrap();
+        if probe.is_some() {
+            anyhow::bail!("A latency measurement is already in progress");
+        }
+        let (done_tx, done_rx) = bounded(1);
+        *probe = Some(LatencyProbe {
+            queued_at: Instant::now(),
+            delivered: false,
+            done_tx,
+        });
+        Ok(done_rx)
+    }
+
+    pub fn cancel_latency_probe(&self) {
+        *self.latency_probe.lock().unwrap() = None;
+    }
+
+    /// The pending DSR, returned once so it reaches the terminal ahead of regular output
+    pub fn take_latency_query(&self) -> Option<&'static [u8]> {
+        match *self.latency_probe.lock().unwrap() {
+            Some(ref mut probe) if !probe.delivered => {
+                probe.delivered = true;
+                Some(LATENCY_QUERY)
+            }
+            _ => None,
+        }
+    }
+
+    /// If `data` answers the pending DSR, record the round trip and return true
+    /// so the caller keeps the report away from the shell
+    pub fn complete_latency_probe(&self, data: &[u8]) -> bool {
+        let mut probe = self.latency_probe.lock().unwrap();
+        let answered = matches!(*probe, Some(ref p) if p.delivered) && is_cursor_report(data);
+        if answered {
+            if let Some(p) = probe.take() {
+                let _ = p.done_tx.send(p.queued_at.elapsed());
+            }
+        }
+        answered
+    }
+
     /// Read OSC events (for command tracking)
     pub fn read_osc_events(&self) -> Vec<OscEvent> {
         let mut events = Vec::new();
@@ -159,30 +559,364 @@ impl PtySession {
     }
 
     /// Write input to PTY
-    pub fn write_input(&self, data: &[u8]) -> Result<()> {
+    pub fn write_input(&self, data: impl Into<Bytes>) -> Result<()> {
+        let data = self.intercept(data.into(), false);
+        self.send(data)
+    }
+
+    /// write_input for keys typed in the window, where a space or Enter ending an
+    /// abbreviation is written as its expansion. Returns what was written, for the log.
+    pub fn write_typed_input(&self, data: impl Into<Bytes>) -> Result<Bytes> {
+        let data = self.intercept(data.into(), true);
+        // Cloning a Bytes made from a Vec allocates, so only the caller that needs
+        // the data back pays for it
+        self.send(data.clone())?;
+        Ok(data)
+    }
+
+    // Input as it should reach the shell: abbreviations expanded, and noted by the
+    // echo filter
+    fn intercept(&self, data: Bytes, typed: bool) -> Bytes {
+        let expansion = self
+            .abbreviations
+            .lock()
+            .unwrap()
+            .input(&String::from_utf8_lossy(&data), typed);
+        let data = expansion.map(Bytes::from).unwrap_or(data);
+        if let Some(ref mut filter) = *self.echo_filter.lock().unwrap() {
+            filter.input(&String::from_utf8_lossy(&data));
+        }
+        data
+    }
+
+    fn send(&self, data: Bytes) -> Result<()> {
         self.writer_tx
-            .send(data.to_vec())
+            .send(WriterMsg::Data(data))
             .context("Failed to send input to PTY")?;
+        self.last_activity_ms.store(Utc::now().timestamp_millis(), Ordering::Relaxed);
         Ok(())
     }
 
+    /// Shell process id, if the platform reports one
+    pub fn pid(&self) -> Option<u32> {
+        self.child.lock().unwrap().process_id()
+    }
+
+    /// Kill the shell outright (SIGKILL on unix) rather than hanging it up
+    pub fn kill(&self) -> Result<()> {
+        self.child.lock().unwrap().kill().context("Failed to kill shell")
+    }
+
+    /// Whether the shell is still running
+    pub fn is_alive(&self) -> bool {
+        matches!(self.child.lock().unwrap().try_wait(), Ok(None))
+    }
+
+    /// Current (cols, rows)
+    pub fn size(&self) -> Result<(u16, u16)> {
+        let size = self.pty_pair.master.get_size().context("Failed to get PTY size")?;
+        Ok((size.cols, size.rows))
+    }
+
+    /// Unix ms of the latest output or input
+    pub fn last_activity_ms(&self) -> i64 {
+        self.last_activity_ms.load(Ordering::Relaxed)
+    }
+
+    /// Bytes held for reattach (at most SCROLLBACK_LIMIT)
+    pub fn scrollback_bytes(&self) -> usize {
+        self.scrollback.lock().map(|sb| sb.len()).unwrap_or(0)
+    }
+
+    /// Output bytes per second since the previous call (or since spawn, the first time)
+    pub fn sample_output_rate(&self) -> f64 {
+        let total = self.output_bytes.load(Ordering::Relaxed);
+        let mut sample = self.rate_sample.lock().unwrap();
+        let elapsed = sample.0.elapsed().as_secs_f64();
+        let rate = if elapsed > 0.0 { (total - sample.1) as f64 / elapsed } else { 0.0 };
+        *sample = (Instant::now(), total);
+        rate
+    }
+
+    /// Whether the shell or a program it started holds the terminal's foreground
+    #[cfg(unix)]
+    pub fn foreground(&self) -> Foreground {
+        let Some(pgid) = self.pty_pair.master.process_group_leader() else {
+            return Foreground::Unknown;
+        };
+        if self.child.lock().unwrap().process_id() == Some(pgid as u32) {
+            Foreground::Shell
+        } else {
+            Foreground::Program(pgid)
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn foreground(&self) -> Foreground {
+        Foreground::Unknown
+    }
+
     /// Resize PTY
-    pub fn resize(&mut self, cols: u16, rows: u16) -> Result<()> {
+    pub fn resize(&mut self, size: PtySize) -> Result<()> {
         self.pty_pair
             .master
-            .resize(PtySize {
-                rows,
-                cols,
-                pixel_width: 0,
-                pixel_height: 0,
-            })
+            .resize(size)
             .context("Failed to resize PTY")?;
         Ok(())
     }
 }
 
+// Transient openpty failures are retried this many times, backing off from the first delay
+const OPEN_PTY_RETRIES: u32 = 3;
+const OPEN_PTY_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Why a PTY couldn't be opened, with the OS error number when there was one
+#[derive(Debug)]
+pub struct PtyOpenError {
+    pub errno: Option<i32>,
+    pub exhausted: bool, // Out of PTYs or file descriptors, as opposed to other failures
+    pub attempts: u32,
+    source: anyhow::Error,
+}
+
+impl std::fmt::Display for PtyOpenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.exhausted {
+            write!(f, "Out of PTYs or file descriptors")?;
+        } else {
+            write!(f, "Failed to open a PTY")?;
+        }
+        if let Some(errno) = self.errno {
+            write!(f, " (errno {})", errno)?;
+        }
+        if self.attempts > 1 {
+            write!(f, " after {} attempts", self.attempts)?;
+        }
+        write!(f, ": {}", self.source)?;
+        if self.exhausted {
+            write!(f, "; close other terminals or raise the open file limit")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for PtyOpenError {}
+
+// Open a PTY pair, retrying errors that tend to clear up on their own
+fn open_pty(size: PtySize) -> std::result::Result<PtyPair, PtyOpenError> {
+    let mut delay = OPEN_PTY_RETRY_DELAY;
+    let mut attempts = 1;
+    loop {
+        let error = match NativePtySystem::default().openpty(size) {
+            Ok(pair) => return Ok(pair),
+            Err(e) => e,
+        };
+        let errno = open_errno(&error);
+        let (exhausted, transient) = classify_errno(errno);
+        if !transient || attempts > OPEN_PTY_RETRIES {
+            return Err(PtyOpenError {
+                errno,
+                exhausted,
+                attempts,
+                source: error,
+            });
+        }
+        thread::sleep(delay);
+        delay *= 2;
+        attempts += 1;
+    }
+}
+
+// portable-pty reports openpty failures as text ("failed to openpty: Os { code: 24, .. }"),
+// so the errno is read back from that when there's no io::Error to take it from
+fn open_errno(error: &anyhow::Error) -> Option<i32> {
+    if let Some(errno) = error.downcast_ref::<std::io::Error>().and_then(|e| e.raw_os_error()) {
+        return Some(errno);
+    }
+    let text = format!("{:?}", error);
+    let code = text.split("code: ").nth(1)?;
+    code.split(|c: char| !c.is_ascii_digit()).next()?.parse().ok()
+}
+
+// (out of PTYs or descriptors, worth retrying)
+#[cfg(unix)]
+fn classify_errno(errno: Option<i32>) -> (bool, bool) {
+    match errno {
+        Some(libc::EAGAIN) | Some(libc::EINTR) => (errno == Some(libc::EAGAIN), true),
+        Some(libc::EMFILE) | Some(libc::ENFILE) | Some(libc::ENOSPC) => (true, false),
+        _ => (false, false),
+    }
+}
+
+#[cfg(not(unix))]
+fn classify_errno(_errno: Option<i32>) -> (bool, bool) {
+    (false, false)
+}
+
+/// PTY master and slave descriptors open in this process, where the platform lists
+/// them (Linux, macOS); None elsewhere
+#[cfg(target_os = "linux")]
+pub fn open_pty_fds() -> Option<usize> {
+    let entries = std::fs::read_dir("/proc/self/fd").ok()?;
+    let count = entries
+        .filter_map(|entry| std::fs::read_link(entry.ok()?.path()).ok())
+        .filter(|target| target == Path::new("/dev/ptmx") || target.starts_with("/dev/pts"))
+        .count();
+    Some(count)
+}
+
+// No /proc: /dev/fd lists the descriptors, and F_GETPATH says what each one is. Masters
+// show as /dev/ptmx (or a legacy /dev/pty*), slaves as /dev/ttys*.
+#[cfg(target_os = "macos")]
+pub fn open_pty_fds() -> Option<usize> {
+    let entries = std::fs::read_dir("/dev/fd").ok()?;
+    let count = entries
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<i32>().ok())
+        .filter(|&fd| {
+            let mut buf = [0 as libc::c_char; libc::PATH_MAX as usize];
+            if unsafe { libc::fcntl(fd, libc::F_GETPATH, buf.as_mut_ptr()) } == -1 {
+                return false;
+            }
+            let path = unsafe { std::ffi::CStr::from_ptr(buf.as_ptr()) }.to_bytes();
+            path == b"/dev/ptmx" || path.starts_with(b"/dev/pty") || path.starts_with(b"/dev/ttys")
+        })
+        .count();
+    Some(count)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn open_pty_fds() -> Option<usize> {
+    None
+}
+
+/// A process's command name, where the platform can tell (Linux, macOS)
+#[cfg(target_os = "linux")]
+pub fn process_name(pid: i32) -> Option<String> {
+    let name = std::fs::read_to_string(format!("/proc/{}/comm", pid)).ok()?;
+    Some(name.trim_end().to_string()).filter(|n| !n.is_empty())
+}
+
+#[cfg(target_os = "macos")]
+pub fn process_name(pid: i32) -> Option<String> {
+    let mut buf = [0u8; 256];
+    let len = unsafe { libc::proc_name(pid, buf.as_mut_ptr().cast(), buf.len() as u32) };
+    (len > 0).then(|| String::from_utf8_lossy(&buf[..len as usize]).to_string())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn process_name(_pid: i32) -> Option<String> {
+    None
+}
+
+/// Open a tee target for appending, creating it if needed
+pub fn open_tee(path: &Path) -> Result<File> {
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open tee file {}", path.display()))
+}
+
+#[cfg(unix)]
+fn master_fd(pty_pair: &PtyPair) -> Option<i32> {
+    pty_pair.master.as_raw_fd()
+}
+
+#[cfg(not(unix))]
+fn master_fd(_pty_pair: &PtyPair) -> Option<i32> {
+    None
+}
+
+// Wait up to `timeout` for output. True when read() won't block, or may (no fd to
+// poll, e.g. on Windows, where the reader stays blocking); hangups and errors count
+// as ready so read() reports them.
+#[cfg(unix)]
+fn wait_readable(fd: Option<i32>, timeout: Duration) -> bool {
+    let Some(fd) = fd else {
+        return true;
+    };
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let ready = unsafe { libc::poll(&mut pollfd, 1, timeout.as_millis() as libc::c_int) };
+    match ready {
+        0 => false,
+        n if n < 0 => std::io::Error::last_os_error().kind() != std::io::ErrorKind::Interrupted,
+        _ => true,
+    }
+}
+
+#[cfg(not(unix))]
+fn wait_readable(_fd: Option<i32>, _timeout: Duration) -> bool {
+    true
+}
+
+// Append one read of up to `max` bytes to `buf`, topping up its allocation when low
+fn read_into(reader: &mut impl Read, buf: &mut BytesMut, max: usize, buffer_bytes: usize) -> std::io::Result<usize> {
+    if buf.capacity() - buf.len() < max {
+        buf.reserve(buffer_bytes);
+    }
+    let start = buf.len();
+    buf.resize(start + max, 0);
+    let result = reader.read(&mut buf[start..]);
+    buf.truncate(start + *result.as_ref().unwrap_or(&0));
+    result
+}
+
+// A cursor position report, ESC [ <row> ; <col> R
+fn is_cursor_report(data: &[u8]) -> bool {
+    let text = String::from_utf8_lossy(data);
+    matches!(
+        vt::tokenize(&text).as_slice(),
+        [vt::Token::Csi { params, final_byte: 'R' }]
+            if params.split(';').count() == 2 && params.split(';').all(|p| p.parse::<u16>().is_ok())
+    )
+}
+
+// Joins `handle` if its thread finishes within `timeout`, or hands it back
+fn join_within(handle: thread::JoinHandle<()>, timeout: Duration) -> Result<thread::Result<()>, thread::JoinHandle<()>> {
+    let deadline = Instant::now() + timeout;
+    while !handle.is_finished() {
+        if Instant::now() >= deadline {
+            return Err(handle);
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    Ok(handle.join())
+}
+
+// Blocks until the reader and writer threads exit, or for up to twice
+// WRITER_SHUTDOWN_TIMEOUT if the writer is stuck, so a session should be taken out
+// of any shared state and dropped after the lock is released
 impl Drop for PtySession {
     fn drop(&mut self) {
+        // Stop the writer thread. Shutdown queues behind any pending input, and a child
+        // that stopped reading stdin leaves the writer blocked on a full PTY buffer, so
+        // the wait is bounded: past it the shell is killed, which ends the block unless
+        // another process still holds the terminal, and then the writer is detached.
+        let _ = self.writer_tx.send(WriterMsg::Shutdown);
+        if let Some(handle) = self.writer_handle.take() {
+            let joined = join_within(handle, WRITER_SHUTDOWN_TIMEOUT).or_else(|handle| {
+                let _ = self.kill();
+                join_within(handle, WRITER_SHUTDOWN_TIMEOUT)
+            });
+            match joined {
+                Ok(Ok(())) => {}
+                Ok(Err(_)) => eprintln!("PTY writer thread panicked"),
+                Err(_) => eprintln!("PTY writer still blocked after killing the shell; detaching it"),
+            }
+        }
+
+        // The reader notices within one poll interval, even if the child is hung
+        self.reader_shutdown.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.reader_handle.take() {
+            if handle.join().is_err() {
+                eprintln!("PTY reader thread panicked");
+            }
+        }
+
         // Clean up ZDOTDIR
         if let Err(e) = self.zdotdir.cleanup() {
             eprintln!("Failed to cleanup ZDOTDIR: {}", e);
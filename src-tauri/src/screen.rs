@@ -0,0 +1,829 @@
+// Headless terminal screen: a cell grid driven by recorded output
+// Covers the cursor, erase, scroll and alternate-screen sequences shells and
+// full-screen apps rely on; anything else is ignored. Every character is one cell wide.
+
+use serde::{Deserialize, Serialize};
+
+use crate::db::Event;
+use crate::vt::{self, Color, Style, Token};
+
+// Covers redacted cells unless the caller picks another character
+pub const DEFAULT_REDACTION_CHAR: char = '█';
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub ch: char,
+    pub style: Style,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Cell { ch: ' ', style: Style::default() }
+    }
+}
+
+/// A block of cells, zero-based from the top-left
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct CellRect {
+    pub row: usize,
+    pub col: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+// Cursor and pen saved by DECSC (ESC 7, CSI s) and on alternate-screen entry
+#[derive(Debug, Clone, Copy)]
+struct SavedCursor {
+    row: usize,
+    col: usize,
+    style: Style,
+}
+
+// The primary screen's contents while the alternate screen is shown
+#[derive(Debug, Clone)]
+struct Primary {
+    lines: Vec<Vec<Cell>>,
+    wrapped: Vec<bool>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Screen {
+    cols: usize,
+    rows: usize,
+    lines: Vec<Vec<Cell>>,
+    wrapped: Vec<bool>, // Row continues onto the next one (an autowrap, not a newline)
+    row: usize,
+    col: usize,
+    wrap_pending: bool, // Last column written; the next character wraps first
+    style: Style,
+    scroll_top: usize,
+    scroll_bottom: usize, // Inclusive
+    saved: Option<SavedCursor>,
+    primary: Option<Primary>, // Set while the alternate screen is active
+    autowrap: bool,
+    cursor_visible: bool,
+}
+
+impl Screen {
+    pub fn new(cols: u16, rows: u16) -> Self {
+        let (cols, rows) = (cols.max(1) as usize, rows.max(1) as usize);
+        Screen {
+            cols,
+            rows,
+            lines: vec![vec![Cell::default(); cols]; rows],
+            wrapped: vec![false; rows],
+            row: 0,
+            col: 0,
+            wrap_pending: false,
+            style: Style::default(),
+            scroll_top: 0,
+            scroll_bottom: rows - 1,
+            saved: None,
+            primary: None,
+            autowrap: true,
+            cursor_visible: true,
+        }
+    }
+
+    pub fn size(&self) -> (u16, u16) {
+        (self.cols as u16, self.rows as u16)
+    }
+
+    /// (row, col), zero-based
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.row, self.col)
+    }
+
+    /// Resize the grid, keeping the top-left content; the cursor is clamped
+    pub fn resize(&mut self, cols: u16, rows: u16) {
+        let (cols, rows) = (cols.max(1) as usize, rows.max(1) as usize);
+        for line in &mut self.lines {
+            line.resize(cols, Cell::default());
+        }
+        self.lines.resize(rows, vec![Cell::default(); cols]);
+        self.wrapped.resize(rows, false);
+        if let Some(ref mut primary) = self.primary {
+            for line in &mut primary.lines {
+                line.resize(cols, Cell::default());
+            }
+            primary.lines.resize(rows, vec![Cell::default(); cols]);
+            primary.wrapped.resize(rows, false);
+        }
+        self.cols = cols;
+        self.rows = rows;
+        self.row = self.row.min(rows - 1);
+        self.col = self.col.min(cols - 1);
+        self.wrap_pending = false;
+        self.scroll_top = 0;
+        self.scroll_bottom = rows - 1;
+    }
+
+    /// Apply a chunk of output
+    pub fn feed(&mut self, data: &str) {
+        for token in vt::tokenize(data) {
+            self.apply(&token);
+        }
+    }
+
+    /// Each row's characters, without trailing blanks
+    pub fn text_lines(&self) -> Vec<String> {
+        self.lines
+            .iter()
+            .map(|line| line.iter().map(|c| c.ch).collect::<String>().trim_end().to_string())
+            .collect()
+    }
+
+    /// Overwrite the cells of `rect` that are on screen with `placeholder`, unstyled
+    /// so colors don't hint at what was there
+    pub fn redact(&mut self, rect: &CellRect, placeholder: char) {
+        let rows = rect.row.min(self.rows)..rect.row.saturating_add(rect.height).min(self.rows);
+        let cols = rect.col.min(self.cols)..rect.col.saturating_add(rect.width).min(self.cols);
+        for line in &mut self.lines[rows] {
+            for cell in &mut line[cols.clone()] {
+                *cell = Cell { ch: placeholder, style: Style::default() };
+            }
+        }
+    }
+
+    /// This screen `cols` wide: rows joined by autowraps are rejoined and wrapped again
+    /// at the new width, while rows ended by a newline stay separate. Rows that no longer
+    /// fit scroll off the top, as in a terminal. None while the alternate screen is
+    /// shown, since full-screen apps lay out for one width.
+    pub fn reflow(&self, cols: u16) -> Option<Screen> {
+        if self.primary.is_some() {
+            return None;
+        }
+        let new_cols = cols.max(1) as usize;
+
+        // Logical lines, each with the row it starts on
+        let mut logical: Vec<(usize, Vec<Cell>)> = Vec::new();
+        for (row, line) in self.lines.iter().enumerate() {
+            match logical.last_mut() {
+                Some((_, cells)) if self.wrapped[row - 1] => cells.extend_from_slice(line),
+                _ => logical.push((row, line.clone())),
+            }
+        }
+
+        let mut lines: Vec<Vec<Cell>> = Vec::new();
+        let mut wrapped: Vec<bool> = Vec::new();
+        let (mut cursor, mut wrap_pending) = ((0, 0), false);
+        for (start, mut cells) in logical {
+            let spans_cursor = (start..start + cells.len() / self.cols).contains(&self.row);
+            let cursor_offset =
+                spans_cursor.then(|| (self.row - start) * self.cols + self.col + self.wrap_pending as usize);
+            let used = cells.iter().rposition(|c| *c != Cell::default()).map_or(0, |i| i + 1);
+            cells.truncate(used.max(cursor_offset.unwrap_or(0)));
+
+            let first = lines.len();
+            for chunk in cells.chunks(new_cols) {
+                let mut line = chunk.to_vec();
+                line.resize(new_cols, Cell::default());
+                lines.push(line);
+                wrapped.push(true);
+            }
+            if cells.is_empty() {
+                lines.push(vec![Cell::default(); new_cols]);
+                wrapped.push(true);
+            }
+            *wrapped.last_mut().unwrap() = false;
+
+            if let Some(offset) = cursor_offset {
+                // A cursor just past a full last row stays on it, about to wrap
+                wrap_pending = offset > 0 && offset == cells.len() && offset % new_cols == 0;
+                let offset = offset - wrap_pending as usize;
+                cursor = (first + offset / new_cols, offset % new_cols);
+            }
+        }
+
+        // Blank rows below the cursor go first, then rows off the top
+        while lines.len() > self.rows
+            && lines.len() - 1 > cursor.0
+            && lines.last().is_some_and(|line| line.iter().all(|c| *c == Cell::default()))
+        {
+            lines.pop();
+            wrapped.pop();
+        }
+        let dropped = lines.len().saturating_sub(self.rows);
+        lines.drain(..dropped);
+        wrapped.drain(..dropped);
+        lines.resize(self.rows, vec![Cell::default(); new_cols]);
+        wrapped.resize(self.rows, false);
+
+        let mut screen = self.clone();
+        screen.cols = new_cols;
+        screen.lines = lines;
+        screen.wrapped = wrapped;
+        screen.row = cursor.0.saturating_sub(dropped);
+        screen.col = cursor.1;
+        screen.wrap_pending = wrap_pending;
+        screen.scroll_top = 0;
+        screen.scroll_bottom = self.rows - 1;
+        if let Some(ref mut saved) = screen.saved {
+            saved.col = saved.col.min(new_cols - 1);
+        }
+        Some(screen)
+    }
+
+    /// Apply one token of output
+    pub fn apply(&mut self, token: &Token) {
+        match token {
+            Token::Text(text) => {
+                for ch in text.chars() {
+                    self.put_char(ch);
+                }
+            }
+            Token::Control(c) => match c {
+                '\r' => self.carriage_return(),
+                '\n' | '\x0b' | '\x0c' => self.linefeed(),
+                '\x08' => {
+                    self.col = self.col.saturating_sub(1);
+                    self.wrap_pending = false;
+                }
+                '\t' => {
+                    self.col = ((self.col / 8 + 1) * 8).min(self.cols - 1);
+                    self.wrap_pending = false;
+                }
+                _ => {}
+            },
+            Token::Csi { params, final_byte } => self.csi(params, *final_byte),
+            Token::Esc { intermediates: "", final_byte } => match final_byte {
+                '7' => self.save_cursor(),
+                '8' => self.restore_cursor(),
+                'D' => self.linefeed(),
+                'E' => {
+                    self.carriage_return();
+                    self.linefeed();
+                }
+                'M' => self.reverse_index(),
+                'c' => *self = Screen::new(self.cols as u16, self.rows as u16),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    fn put_char(&mut self, ch: char) {
+        if self.wrap_pending {
+            self.wrapped[self.row] = true;
+            self.carriage_return();
+            self.linefeed();
+        }
+        self.lines[self.row][self.col] = Cell { ch, style: self.style };
+        if self.col + 1 < self.cols {
+            self.col += 1;
+        } else if self.autowrap {
+            self.wrap_pending = true;
+        }
+    }
+
+    fn carriage_return(&mut self) {
+        self.col = 0;
+        self.wrap_pending = false;
+    }
+
+    fn linefeed(&mut self) {
+        self.wrap_pending = false;
+        if self.row == self.scroll_bottom {
+            self.scroll_up(1);
+        } else if self.row + 1 < self.rows {
+            self.row += 1;
+        }
+    }
+
+    fn reverse_index(&mut self) {
+        self.wrap_pending = false;
+        if self.row == self.scroll_top {
+            self.scroll_down(1);
+        } else {
+            self.row = self.row.saturating_sub(1);
+        }
+    }
+
+    // Blank cell in the current background, as erases leave behind
+    fn blank(&self) -> Cell {
+        Cell {
+            ch: ' ',
+            style: Style { bg: self.style.bg, ..Style::default() },
+        }
+    }
+
+    fn scroll_up(&mut self, n: usize) {
+        let (top, bottom) = (self.scroll_top, self.scroll_bottom);
+        let blank = vec![self.blank(); self.cols];
+        for _ in 0..n.min(bottom - top + 1) {
+            self.lines.remove(top);
+            self.lines.insert(bottom, blank.clone());
+            self.wrapped.remove(top);
+            self.wrapped.insert(bottom, false);
+        }
+    }
+
+    fn scroll_down(&mut self, n: usize) {
+        let (top, bottom) = (self.scroll_top, self.scroll_bottom);
+        let blank = vec![self.blank(); self.cols];
+        for _ in 0..n.min(bottom - top + 1) {
+            self.lines.remove(bottom);
+            self.lines.insert(top, blank.clone());
+            self.wrapped.remove(bottom);
+            self.wrapped.insert(top, false);
+        }
+    }
+
+    fn save_cursor(&mut self) {
+        self.saved = Some(SavedCursor { row: self.row, col: self.col, style: self.style });
+    }
+
+    fn restore_cursor(&mut self) {
+        if let Some(saved) = self.saved {
+            self.row = saved.row.min(self.rows - 1);
+            self.col = saved.col.min(self.cols - 1);
+            self.style = saved.style;
+        }
+        self.wrap_pending = false;
+    }
+
+    fn move_to(&mut self, row: usize, col: usize) {
+        self.row = row.min(self.rows - 1);
+        self.col = col.min(self.cols - 1);
+        self.wrap_pending = false;
+    }
+
+    fn erase(&mut self, row: usize, cols: std::ops::Range<usize>) {
+        let blank = self.blank();
+        let end = cols.end.min(self.cols);
+        for cell in &mut self.lines[row][cols.start.min(end)..end] {
+            *cell = blank;
+        }
+        if end == self.cols {
+            self.wrapped[row] = false;
+        }
+    }
+
+    fn csi(&mut self, params: &str, final_byte: char) {
+        let private = params.starts_with(['?', '>', '<', '=']);
+        let body = params.trim_start_matches(['?', '>', '<', '=']);
+        if body.contains(|c: char| !c.is_ascii_digit() && c != ';' && c != ':') {
+            return; // Intermediate bytes: not a sequence this grid models
+        }
+        let nums: Vec<usize> = body.split(';').map(|p| p.parse().unwrap_or(0)).collect();
+        // Parameter n, with 0 or missing meaning `default`
+        let arg = |n: usize, default: usize| nums.get(n).copied().filter(|v| *v > 0).unwrap_or(default);
+
+        if private {
+            if params.starts_with('?') && (final_byte == 'h' || final_byte == 'l') {
+                for mode in &nums {
+                    self.set_mode(*mode, final_byte == 'h');
+                }
+            }
+            return;
+        }
+
+        match final_byte {
+            'A' => self.move_to(self.row.saturating_sub(arg(0, 1)), self.col),
+            'B' => self.move_to(self.row.saturating_add(arg(0, 1)), self.col),
+            'C' => self.move_to(self.row, self.col.saturating_add(arg(0, 1))),
+            'D' => self.move_to(self.row, self.col.saturating_sub(arg(0, 1))),
+            'E' => self.move_to(self.row.saturating_add(arg(0, 1)), 0),
+            'F' => self.move_to(self.row.saturating_sub(arg(0, 1)), 0),
+            'G' | '`' => self.move_to(self.row, arg(0, 1) - 1),
+            'd' => self.move_to(arg(0, 1) - 1, self.col),
+            'H' | 'f' => self.move_to(arg(0, 1) - 1, arg(1, 1) - 1),
+            'J' => {
+                let (row, col) = (self.row, self.col);
+                match nums[0] {
+                    0 => {
+                        self.erase(row, col..self.cols);
+                        for r in row + 1..self.rows {
+                            self.erase(r, 0..self.cols);
+                        }
+                    }
+                    1 => {
+                        for r in 0..row {
+                            self.erase(r, 0..self.cols);
+                        }
+                        self.erase(row, 0..col + 1);
+                    }
+                    _ => {
+                        for r in 0..self.rows {
+                            self.erase(r, 0..self.cols);
+                        }
+                    }
+                }
+            }
+            'K' => {
+                let (row, col) = (self.row, self.col);
+                match nums[0] {
+                    0 => self.erase(row, col..self.cols),
+                    1 => self.erase(row, 0..col + 1),
+                    _ => self.erase(row, 0..self.cols),
+                }
+            }
+            'X' => {
+                let (row, col) = (self.row, self.col);
+                self.erase(row, col..col.saturating_add(arg(0, 1)));
+            }
+            '@' => {
+                let n = arg(0, 1).min(self.cols - self.col);
+                let blank = self.blank();
+                let line = &mut self.lines[self.row];
+                line.truncate(self.cols - n);
+                line.splice(self.col..self.col, std::iter::repeat_n(blank, n));
+            }
+            'P' => {
+                let n = arg(0, 1).min(self.cols - self.col);
+                let blank = self.blank();
+                let line = &mut self.lines[self.row];
+                line.drain(self.col..self.col + n);
+                line.extend(std::iter::repeat_n(blank, n));
+            }
+            'L' | 'M' if (self.scroll_top..=self.scroll_bottom).contains(&self.row) => {
+                // Insert/delete lines: scroll the part of the region below the cursor
+                let top = self.scroll_top;
+                self.scroll_top = self.row;
+                if final_byte == 'L' {
+                    self.scroll_down(arg(0, 1));
+                } else {
+                    self.scroll_up(arg(0, 1));
+                }
+                self.scroll_top = top;
+                self.col = 0;
+                self.wrap_pending = false;
+            }
+            'S' => self.scroll_up(arg(0, 1)),
+            'T' => self.scroll_down(arg(0, 1)),
+            'r' => {
+                let top = arg(0, 1) - 1;
+                let bottom = arg(1, self.rows).min(self.rows) - 1;
+                if top < bottom {
+                    self.scroll_top = top;
+                    self.scroll_bottom = bottom;
+                    self.move_to(0, 0);
+                }
+            }
+            'm' => self.style.apply_sgr(params),
+            's' => self.save_cursor(),
+            'u' => self.restore_cursor(),
+            _ => {}
+        }
+    }
+
+    fn set_mode(&mut self, mode: usize, on: bool) {
+        match mode {
+            7 => self.autowrap = on,
+            25 => self.cursor_visible = on,
+            47 | 1047 | 1049 => {
+                if on == self.primary.is_some() {
+                    return;
+                }
+                if on {
+                    if mode == 1049 {
+                        self.save_cursor();
+                    }
+                    let blank = vec![vec![Cell::default(); self.cols]; self.rows];
+                    self.primary = Some(Primary {
+                        lines: std::mem::replace(&mut self.lines, blank),
+                        wrapped: std::mem::replace(&mut self.wrapped, vec![false; self.rows]),
+                    });
+                } else {
+                    if let Some(primary) = self.primary.take() {
+                        self.lines = primary.lines;
+                        self.wrapped = primary.wrapped;
+                    }
+                    if mode == 1049 {
+                        self.restore_cursor();
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Output that draws this screen from any starting state: clear, every row, then
+    /// the cursor and pen. Starts with a full clear, so it marks a clear point too.
+    pub fn render(&self) -> String {
+        let mut out = String::from("\x1b[0m\x1b[H\x1b[2J");
+        let mut pen = Some(Style::default());
+        for row in 0..self.rows {
+            let line = &self.lines[row];
+            let end = line.iter().rposition(|c| *c != Cell::default()).map_or(0, |i| i + 1);
+            if end > 0 {
+                out.push_str(&format!("\x1b[{};1H", row + 1));
+                self.draw_cells(&mut out, &mut pen, row, 0..end);
+            }
+        }
+        self.finish(&mut out, pen, None);
+        out
+    }
+
+    /// Output that turns `prev` (an earlier state of this screen) into this one by
+    /// redrawing only changed cells. Falls back to render() if the sizes differ.
+    pub fn diff(&self, prev: &Screen) -> String {
+        if self.size() != prev.size() {
+            return self.render();
+        }
+        let mut out = String::new();
+        let mut pen = None; // Unknown until the first SGR goes out
+        for row in 0..self.rows {
+            let (now, before) = (&self.lines[row], &prev.lines[row]);
+            let Some(first) = (0..self.cols).find(|&c| now[c] != before[c]) else {
+                continue;
+            };
+            let last = (0..self.cols).rposition(|c| now[c] != before[c]).unwrap_or(first);
+            out.push_str(&format!("\x1b[{};{}H", row + 1, first + 1));
+            self.draw_cells(&mut out, &mut pen, row, first..last + 1);
+        }
+        if out.is_empty() && self.cursor() == prev.cursor() && self.cursor_visible == prev.cursor_visible {
+            return out;
+        }
+        self.finish(&mut out, pen, Some(prev));
+        out
+    }
+
+    fn draw_cells(&self, out: &mut String, pen: &mut Option<Style>, row: usize, cols: std::ops::Range<usize>) {
+        for cell in &self.lines[row][cols] {
+            if *pen != Some(cell.style) {
+                out.push_str(&sgr(&cell.style));
+                *pen = Some(cell.style);
+            }
+            out.push(cell.ch);
+        }
+    }
+
+    // Leave the pen, cursor and cursor visibility as this screen has them
+    fn finish(&self, out: &mut String, pen: Option<Style>, prev: Option<&Screen>) {
+        if pen != Some(self.style) {
+            out.push_str(&sgr(&self.style));
+        }
+        out.push_str(&format!("\x1b[{};{}H", self.row + 1, self.col + 1));
+        if prev.is_none_or(|p| p.cursor_visible != self.cursor_visible) {
+            out.push_str(if self.cursor_visible { "\x1b[?25h" } else { "\x1b[?25l" });
+        }
+    }
+}
+
+/// The screen at the end of a recording, starting at `cols`x`rows` and following
+/// its resize events
+pub fn replay(events: &[Event], cols: u16, rows: u16) -> Screen {
+    let mut screen = Screen::new(cols, rows);
+    for event in events {
+        match event.kind.as_str() {
+            "pty_out" => screen.feed(&event.data),
+            "resize" => {
+                let size = event.data.split_once('x').and_then(|(c, r)| Some((c.parse().ok()?, r.parse().ok()?)));
+                if let Some((cols, rows)) = size {
+                    screen.resize(cols, rows);
+                }
+            }
+            _ => {}
+        }
+    }
+    screen
+}
+
+/// SGR sequence that sets exactly `style`, starting from a reset
+pub fn sgr(style: &Style) -> String {
+    let mut codes = vec!["0".to_string()];
+    for (on, code) in [
+        (style.bold, "1"),
+        (style.dim, "2"),
+        (style.italic, "3"),
+        (style.underline, "4"),
+        (style.inverse, "7"),
+        (style.strikethrough, "9"),
+    ] {
+        if on {
+            codes.push(code.to_string());
+        }
+    }
+    for (color, base) in [(style.fg, 38), (style.bg, 48)] {
+        match color {
+            Some(Color::Indexed(i)) => codes.push(format!("{};5;{}", base, i)),
+            Some(Color::Rgb(r, g, b)) => codes.push(format!("{};2;{};{};{}", base, r, g, b)),
+            None => {}
+        }
+    }
+    format!("\x1b[{}m", codes.join(";"))
+}
+
+/// Some(true) for sequences that switch to the alternate screen, Some(false) for ones
+/// that switch back
+pub fn alt_screen_switch(token: &Token) -> Option<bool> {
+    let Token::Csi { params, final_byte: final_byte @ ('h' | 'l') } = token else {
+        return None;
+    };
+    let modes = params.strip_prefix('?')?;
+    modes
+        .split(';')
+        .any(|m| matches!(m, "47" | "1047" | "1049"))
+        .then_some(*final_byte == 'h')
+}
+
+// Tokens kept verbatim in alt-screen logs: terminal modes, titles and bells the
+// grid doesn't model but a replaying terminal still needs
+fn is_passthrough(token: &Token) -> bool {
+    match token {
+        Token::Csi { params, final_byte } => {
+            // Private modes and reports, and the cursor shape (CSI n SP q)
+            (params.starts_with(['?', '>', '<', '=']) && "hlpqt".contains(*final_byte))
+                || (params.ends_with(' ') && *final_byte == 'q')
+        }
+        Token::Osc(_) | Token::Str { .. } => true,
+        Token::Control(c) => *c == '\x07',
+        Token::Esc { intermediates: "", final_byte } => *final_byte == '=' || *final_byte == '>',
+        _ => false,
+    }
+}
+
+// Every this many logged frames, a full snapshot replaces the diff
+pub const SNAPSHOT_EVERY_FRAMES: usize = 100;
+
+struct AltState {
+    screen: Screen, // Emulated from the raw output
+    logged: Screen, // What the log reproduces so far
+    frames: usize, // Frames logged since the last snapshot
+    snapshot_due: bool,
+}
+
+/// Rewrites output for the log while a full-screen app has the alternate screen.
+/// Each chunk logs as the cells that changed since the previous one, with a full
+/// snapshot every SNAPSHOT_EVERY_FRAMES, so redraw-heavy apps store a fraction of
+/// their raw output. Output on the normal screen passes through untouched.
+///
+/// The log replays the same screens only as far as this grid models them: frames
+/// within one chunk collapse into one, sequences the grid ignores (other than the
+/// modes, titles and bells passed through) are lost, and wide characters can drift.
+pub struct DiffCapture {
+    size: (u16, u16),
+    alt: Option<AltState>,
+    carried: String, // Incomplete escape sequence from the end of the last chunk
+}
+
+impl DiffCapture {
+    pub fn new(cols: u16, rows: u16) -> Self {
+        DiffCapture { size: (cols, rows), alt: None, carried: String::new() }
+    }
+
+    pub fn resize(&mut self, cols: u16, rows: u16) {
+        self.size = (cols, rows);
+        if let Some(ref mut alt) = self.alt {
+            alt.screen.resize(cols, rows);
+            alt.snapshot_due = true; // The replaying terminal reflows its own way
+        }
+    }
+
+    /// What to log for a chunk of output; empty when nothing visible changed
+    pub fn log_text(&mut self, chunk: &str) -> String {
+        let mut data = std::mem::take(&mut self.carried);
+        data.push_str(chunk);
+
+        let mut spans = vt::tokenize_spans(&data);
+        if let Some((Token::Invalid(rest), span)) = spans.last() {
+            if span.end == data.len() && rest.starts_with('\x1b') && rest.len() <= vt::MAX_CARRIED_BYTES {
+                self.carried = rest.to_string();
+                spans.pop();
+            }
+        }
+
+        let mut out = String::new();
+        for (token, span) in spans {
+            match (&mut self.alt, alt_screen_switch(&token)) {
+                (None, entering) => {
+                    out.push_str(&data[span]);
+                    if entering == Some(true) {
+                        let (cols, rows) = self.size;
+                        self.alt = Some(AltState {
+                            screen: Screen::new(cols, rows),
+                            logged: Screen::new(cols, rows),
+                            frames: 0,
+                            snapshot_due: true, // The cursor (and, for mode 47, content) carries over
+                        });
+                    }
+                }
+                (Some(alt), Some(false)) => {
+                    out.push_str(&Self::frame(alt));
+                    out.push_str(&data[span]);
+                    self.alt = None;
+                }
+                (Some(_), _) if is_passthrough(&token) => out.push_str(&data[span]),
+                (Some(alt), _) => alt.screen.apply(&token),
+            }
+        }
+        if let Some(ref mut alt) = self.alt {
+            out.push_str(&Self::frame(alt));
+        }
+        out
+    }
+
+    // Log the emulated screen as a diff, or a snapshot when one is due or smaller
+    fn frame(alt: &mut AltState) -> String {
+        let diff = alt.screen.diff(&alt.logged);
+        if diff.is_empty() && !alt.snapshot_due {
+            return diff;
+        }
+        let snapshot = alt.screen.render();
+        alt.logged = alt.screen.clone();
+        if alt.snapshot_due || alt.frames + 1 >= SNAPSHOT_EVERY_FRAMES || snapshot.len() <= diff.len() {
+            alt.frames = 0;
+            alt.snapshot_due = false;
+            snapshot
+        } else {
+            alt.frames += 1;
+            diff
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_screen() {
+        let mut screen = Screen::new(10, 3);
+        screen.feed("hello\r\nabcdefghijkl\x1b[1;3H\x1b[31mX\x1b[0m\x1b[1;5H\x1b[K");
+        assert_eq!(screen.text_lines(), vec!["heXl", "abcdefghij", "kl"]);
+        assert!(screen.wrapped[1]);
+        assert_eq!(screen.lines[0][2].style.fg, Some(Color::Indexed(1)));
+
+        screen.feed("\x1b[?1049h\x1b[Htop");
+        assert_eq!(screen.text_lines(), vec!["top", "", ""]);
+        screen.feed("\x1b[?1049l");
+        assert_eq!(screen.text_lines()[0], "heXl");
+        assert_eq!(screen.cursor(), (0, 4));
+
+        // A diff applied to the earlier screen reproduces the later one
+        let before = screen.clone();
+        screen.feed("\x1b[3;1H\x1b[1mnew\r\n\n\x1b[44mz");
+        let mut replayed = before.clone();
+        replayed.feed(&screen.diff(&before));
+        assert_eq!(replayed.lines, screen.lines);
+        assert_eq!(replayed.cursor(), screen.cursor());
+    }
+
+    #[test]
+    fn test_huge_counts_clamp() {
+        let mut screen = Screen::new(10, 3);
+        let max = usize::MAX;
+        screen.feed(&format!("ab\x1b[{max}C\x1b[{max}B\x1b[1;2H\x1b[{max}X\x1b[{max}Ez"));
+        assert_eq!(screen.text_lines(), vec!["a", "", "z"]);
+        assert_eq!(screen.cursor(), (2, 1));
+    }
+
+    #[test]
+    fn test_reflow() {
+        let mut screen = Screen::new(10, 4);
+        screen.feed("$ echo\r\nabcdefghijklmn\r\n$ ");
+        assert_eq!(screen.text_lines(), vec!["$ echo", "abcdefghij", "klmn", "$"]);
+
+        let wide = screen.reflow(20).unwrap();
+        assert_eq!(wide.text_lines(), vec!["$ echo", "abcdefghijklmn", "$", ""]);
+        assert_eq!(wide.cursor(), (2, 2));
+
+        // Narrower: the extra row scrolls the first one off
+        let narrow = screen.reflow(6).unwrap();
+        assert_eq!(narrow.text_lines(), vec!["abcdef", "ghijkl", "mn", "$"]);
+        assert_eq!(narrow.cursor(), (3, 2));
+        assert!(narrow.wrapped[0] && narrow.wrapped[1] && !narrow.wrapped[2]);
+
+        screen.feed("\x1b[?1049h");
+        assert!(screen.reflow(20).is_none());
+    }
+
+    #[test]
+    fn test_redact() {
+        let mut screen = Screen::new(12, 3);
+        screen.feed("$ export\r\nTOKEN=\x1b[33mab12cd\x1b[0m\r\n$ ");
+        screen.redact(&CellRect { row: 1, col: 6, width: 6, height: 1 }, '#');
+        screen.redact(&CellRect { row: 2, col: 10, width: 5, height: 4 }, '#');
+        assert_eq!(screen.text_lines(), vec!["$ export", "TOKEN=######", "$         ##"]);
+        assert_eq!(screen.lines[1][6].style, Style::default());
+    }
+
+    #[test]
+    fn test_diff_capture() {
+        let chunks: Vec<String> = std::iter::once("$ top\r\n\x1b[?1049h\x1b]0;top\x07".to_string())
+            .chain((0..150).map(|i| {
+                format!("\x1b[H\x1b[2J\x1b[1;44mtasks: 312 total, load 0.42\x1b[0m\r\n{:>5} running\r\nq quit", i)
+            }))
+            .chain(["\x1b[2;1H  9\x1b".to_string(), "[K\x1b[?1049l$ ".to_string()])
+            .collect();
+
+        let mut capture = DiffCapture::new(40, 4);
+        let (mut raw, mut logged) = (Screen::new(40, 4), Screen::new(40, 4));
+        let mut logged_bytes = 0;
+        for (i, chunk) in chunks.iter().enumerate() {
+            let text = capture.log_text(chunk);
+            logged_bytes += text.len();
+            raw.feed(chunk);
+            logged.feed(&text);
+            if i > 0 && i <= 150 {
+                assert!(capture.alt.is_some());
+                assert_eq!(logged.text_lines(), raw.text_lines());
+            }
+        }
+        assert_eq!(logged.text_lines(), raw.text_lines());
+        assert!(capture.alt.is_none());
+        assert_eq!(capture.log_text("plain\r\n"), "plain\r\n");
+        assert!(logged_bytes * 3 < chunks.iter().map(|c| c.len()).sum::<usize>());
+    }
+}
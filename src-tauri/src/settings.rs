@@ -0,0 +1,194 @@
+// Known app settings: keys, defaults and value shapes
+// Values are stored as JSON in the settings table and cached in Database
+
+use anyhow::{bail, Result};
+use serde_json::Value;
+
+pub const PERSISTENCE_ENABLED: &str = "persistence_enabled"; // bool
+pub const DEFAULT_SHELL: &str = "default_shell"; // shell path, or null for the platform default
+pub const RETENTION_DAYS: &str = "retention_days"; // whole days up to MAX_RETENTION_DAYS, or null to keep everything
+// whole hours (up to MAX_PRUNE_GRACE_HOURS) a session stays listed as about to be pruned
+// before a later prune deletes it, or null to delete on the first pass
+pub const PRUNE_GRACE_HOURS: &str = "prune_grace_hours";
+pub const MAX_DB_BYTES: &str = "max_db_bytes"; // evict oldest sessions above this size (min 1 MiB), or null
+pub const REDACTION_PATTERNS: &str = "redaction_patterns"; // list of regexes
+pub const ON_SESSION_END: &str = "on_session_end"; // hook command, or null
+pub const IDLE_MARKER_MINUTES: &str = "idle_marker_minutes"; // whole minutes up to MAX_IDLE_MARKER_MINUTES, or null for no idle markers
+pub const READABLE_INPUT: &str = "readable_input"; // bool: also log typed input as e.g. "<Up>^C"
+pub const PATH_PATTERN: &str = "path_pattern"; // regex with a `path` group, or null for the built-in one
+pub const INPUT_LOG_MAX_BYTES: &str = "input_log_max_bytes"; // longer user_in events are logged truncated, or null for no cap
+pub const METADATA_FLUSH_MS: &str = "metadata_flush_ms"; // how often batched session metadata is saved
+pub const CHECKPOINT_INTERVAL_SECS: &str = "checkpoint_interval_secs"; // how often the WAL is checkpointed: 10..86400
+// whole seconds without new events after which the database is flushed and its connection
+// closed until next needed: 10..86400, or null to keep it open
+pub const DB_IDLE_CLOSE_SECS: &str = "db_idle_close_secs";
+pub const MIRROR: &str = "mirror"; // {"url", "token"?} or {"sqlite_path"}, or null
+pub const AUTO_TAG_RULES: &str = "auto_tag_rules"; // list of {"cwd_prefix" | "command_contains", "tag"}
+pub const SENSITIVE_PATHS: &str = "sensitive_paths"; // list of paths flagged when a command mentions them
+pub const ABBREVIATIONS: &str = "abbreviations"; // map of abbreviation to expansion, expanded as typed
+// bool: log alternate-screen (full-screen app) output as periodic snapshots plus changed
+// cells rather than every byte. Much smaller for apps like htop, but replay shows the
+// screen as emulated at each read, not byte for byte; see screen::DiffCapture.
+pub const DIFF_ALT_SCREEN: &str = "diff_alt_screen";
+// bool: drop the terminal's echo of typed input from logged output, so transcripts show
+// it once, as input. Only text and Enter are matched, within echo::ECHO_WINDOW of typing.
+pub const SUPPRESS_ECHO: &str = "suppress_echo";
+// bool: log "program: start/exit" markers around each program that takes the terminal's
+// foreground, for get_subsessions. See focus::FocusTracker for how spans are bounded.
+pub const FOCUS_MODE: &str = "focus_mode";
+// bool: log "mode: <name> on|off" markers when output switches an input mode such as
+// bracketed paste or mouse reporting, for get_mode_changes. See vt::ModeTracker.
+pub const LOG_MODE_CHANGES: &str = "log_mode_changes";
+
+// PTY reader tuning, applied to sessions started afterwards. Low values favor
+// keystroke latency, higher ones fewer and larger chunks for output-heavy sessions.
+pub const READ_BUFFER_BYTES: &str = "read_buffer_bytes"; // largest single read: 1 KiB..1 MiB, default 8 KiB
+pub const OUTPUT_COALESCE_MS: &str = "output_coalesce_ms"; // wait for more output to merge: 0..250, default 0 (off)
+pub const OUTPUT_BATCH_BYTES: &str = "output_batch_bytes"; // merged chunk / log row cap: 4 KiB..4 MiB, default 64 KiB
+
+pub const KEYS: &[&str] = &[
+    PERSISTENCE_ENABLED,
+    DEFAULT_SHELL,
+    RETENTION_DAYS,
+    PRUNE_GRACE_HOURS,
+    MAX_DB_BYTES,
+    REDACTION_PATTERNS,
+    ON_SESSION_END,
+    IDLE_MARKER_MINUTES,
+    READABLE_INPUT,
+    PATH_PATTERN,
+    INPUT_LOG_MAX_BYTES,
+    METADATA_FLUSH_MS,
+    CHECKPOINT_INTERVAL_SECS,
+    DB_IDLE_CLOSE_SECS,
+    MIRROR,
+    AUTO_TAG_RULES,
+    SENSITIVE_PATHS,
+    ABBREVIATIONS,
+    DIFF_ALT_SCREEN,
+    SUPPRESS_ECHO,
+    FOCUS_MODE,
+    LOG_MODE_CHANGES,
+    READ_BUFFER_BYTES,
+    OUTPUT_COALESCE_MS,
+    OUTPUT_BATCH_BYTES,
+];
+
+// Longest retention_days accepted, about a century
+pub const MAX_RETENTION_DAYS: u64 = 36_500;
+// Longest prune_grace_hours accepted, a year
+pub const MAX_PRUNE_GRACE_HOURS: u64 = 24 * 365;
+// Longest idle_marker_minutes accepted, a week
+pub const MAX_IDLE_MARKER_MINUTES: u64 = 7 * 24 * 60;
+
+/// Value used when a known key has never been set
+pub fn default_value(key: &str) -> Result<Value> {
+    Ok(match key {
+        PERSISTENCE_ENABLED => Value::Bool(true),
+        READABLE_INPUT | DIFF_ALT_SCREEN | SUPPRESS_ECHO | FOCUS_MODE | LOG_MODE_CHANGES => Value::Bool(false),
+        METADATA_FLUSH_MS => Value::from(1000),
+        CHECKPOINT_INTERVAL_SECS => Value::from(300),
+        READ_BUFFER_BYTES => Value::from(crate::pty::DEFAULT_READ_BYTES),
+        OUTPUT_COALESCE_MS => Value::from(crate::pty::DEFAULT_COALESCE_MS),
+        OUTPUT_BATCH_BYTES => Value::from(crate::pty::DEFAULT_BATCH_BYTES),
+        DEFAULT_SHELL | RETENTION_DAYS | PRUNE_GRACE_HOURS | MAX_DB_BYTES | ON_SESSION_END | IDLE_MARKER_MINUTES | PATH_PATTERN
+        | INPUT_LOG_MAX_BYTES | DB_IDLE_CLOSE_SECS | MIRROR => Value::Null,
+        REDACTION_PATTERNS | AUTO_TAG_RULES => Value::Array(Vec::new()),
+        SENSITIVE_PATHS => Value::from(crate::secrets::DEFAULT_SENSITIVE_PATHS.to_vec()),
+        ABBREVIATIONS => Value::Object(serde_json::Map::new()),
+        _ => bail!("Unknown setting: {}", key),
+    })
+}
+
+/// Check that `value` has the shape `key` expects
+pub fn validate(key: &str, value: &Value) -> Result<()> {
+    let ok = match key {
+        PERSISTENCE_ENABLED | READABLE_INPUT | DIFF_ALT_SCREEN | SUPPRESS_ECHO | FOCUS_MODE | LOG_MODE_CHANGES => {
+            value.is_boolean()
+        }
+        DEFAULT_SHELL | ON_SESSION_END => value.is_null() || value.as_str().is_some_and(|s| !s.trim().is_empty()),
+        RETENTION_DAYS => value.is_null() || value.as_u64().is_some_and(|n| (1..=MAX_RETENTION_DAYS).contains(&n)),
+        PRUNE_GRACE_HOURS => value.is_null() || value.as_u64().is_some_and(|n| (1..=MAX_PRUNE_GRACE_HOURS).contains(&n)),
+        IDLE_MARKER_MINUTES => value.is_null() || value.as_u64().is_some_and(|n| (1..=MAX_IDLE_MARKER_MINUTES).contains(&n)),
+        MAX_DB_BYTES => value.is_null() || value.as_u64().is_some_and(|n| n >= 1024 * 1024),
+        // Truncated input can't be replayed exactly, so the cap is opt-in
+        INPUT_LOG_MAX_BYTES => value.is_null() || value.as_u64().is_some_and(|n| n >= 256),
+        METADATA_FLUSH_MS => value.as_u64().is_some_and(|ms| (50..=60_000).contains(&ms)),
+        CHECKPOINT_INTERVAL_SECS => value.as_u64().is_some_and(|secs| (10..=86_400).contains(&secs)),
+        DB_IDLE_CLOSE_SECS => value.is_null() || value.as_u64().is_some_and(|secs| (10..=86_400).contains(&secs)),
+        READ_BUFFER_BYTES => value.as_u64().is_some_and(|n| (1024..=1024 * 1024).contains(&n)),
+        OUTPUT_COALESCE_MS => value.as_u64().is_some_and(|ms| ms <= 250),
+        OUTPUT_BATCH_BYTES => value.as_u64().is_some_and(|n| (4096..=4 * 1024 * 1024).contains(&n)),
+        REDACTION_PATTERNS => match value.as_array() {
+            Some(patterns) => {
+                for pattern in patterns {
+                    let Some(pattern) = pattern.as_str() else {
+                        bail!("{} must be a list of strings", key);
+                    };
+                    if let Err(e) = regex::Regex::new(pattern) {
+                        bail!("Invalid pattern {:?}: {}", pattern, e);
+                    }
+                }
+                true
+            }
+            None => false,
+        },
+        SENSITIVE_PATHS => value
+            .as_array()
+            .is_some_and(|paths| paths.iter().all(|p| p.as_str().is_some_and(|p| !p.trim().is_empty()))),
+        PATH_PATTERN => match value.as_str() {
+            Some(pattern) => {
+                crate::paths::compile(pattern)?;
+                true
+            }
+            None => value.is_null(),
+        },
+        MIRROR => {
+            crate::mirror::Target::from_setting(value)?;
+            true
+        }
+        AUTO_TAG_RULES => {
+            crate::autotag::rules_from_setting(value)?;
+            true
+        }
+        ABBREVIATIONS => {
+            crate::abbrev::from_setting(value)?;
+            true
+        }
+        _ => bail!("Unknown setting: {}", key),
+    };
+
+    if !ok {
+        bail!("Invalid value for {}: {}", key, value);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(PERSISTENCE_ENABLED, &json!(false)).is_ok());
+        assert!(validate(PERSISTENCE_ENABLED, &json!("false")).is_err());
+        assert!(validate(RETENTION_DAYS, &json!(30)).is_ok());
+        assert!(validate(RETENTION_DAYS, &json!(-1)).is_err());
+        assert!(validate(RETENTION_DAYS, &json!(u64::MAX)).is_err());
+        assert!(validate(PRUNE_GRACE_HOURS, &json!(u64::MAX)).is_err());
+        assert!(validate(REDACTION_PATTERNS, &json!(["sk-[a-z]+"])).is_ok());
+        assert!(validate(REDACTION_PATTERNS, &json!(["("])).is_err());
+        assert!(validate(OUTPUT_COALESCE_MS, &json!(5)).is_ok());
+        assert!(validate(READ_BUFFER_BYTES, &json!(16)).is_err());
+        assert!(validate("no_such_key", &json!(1)).is_err());
+    }
+
+    #[test]
+    fn test_validate_idle_marker_minutes() {
+        assert!(validate(IDLE_MARKER_MINUTES, &json!(null)).is_ok());
+        assert!(validate(IDLE_MARKER_MINUTES, &json!(MAX_IDLE_MARKER_MINUTES)).is_ok());
+        assert!(validate(IDLE_MARKER_MINUTES, &json!(0)).is_err());
+        assert!(validate(IDLE_MARKER_MINUTES, &json!(u64::MAX / 60 + 1)).is_err());
+    }
+}
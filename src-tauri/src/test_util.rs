@@ -0,0 +1,18 @@
+// Fixtures shared by the unit tests
+
+use crate::db::Event;
+
+/// pty_out event `id` in session "s", logged at 2026-01-01T00:00:00Z without ts_ms.
+/// Tests that need another kind or time override fields with `..event(id, data)`.
+pub fn event(id: &str, data: &str) -> Event {
+    Event {
+        id: id.to_string(),
+        session_id: "s".to_string(),
+        ts: "2026-01-01T00:00:00+00:00".to_string(),
+        kind: "pty_out".to_string(),
+        data: data.to_string(),
+        ts_ms: None,
+        seq: None,
+        display: None,
+    }
+}
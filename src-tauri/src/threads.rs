@@ -0,0 +1,86 @@
+// Registry of the app's background threads, for diagnosing leaks: each thread is
+// listed with its kind and owning session while it runs and drops out when it exits
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::thread::JoinHandle;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThreadInfo {
+    pub id: u64,
+    pub kind: String, // e.g. "pty reader", "checkpointer"
+    pub session_id: Option<String>, // None for app-wide threads
+    pub started_at: String,
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+fn registry() -> &'static Mutex<HashMap<u64, ThreadInfo>> {
+    static REGISTRY: OnceLock<Mutex<HashMap<u64, ThreadInfo>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+// Removes the entry when the thread's closure returns or unwinds
+struct Registration(u64);
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        if let Ok(mut threads) = registry().lock() {
+            threads.remove(&self.0);
+        }
+    }
+}
+
+/// std::thread::spawn, with the thread named after `kind` and listed by running()
+/// until it finishes
+pub fn spawn<F, T>(kind: &'static str, session_id: Option<String>, f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    registry().lock().unwrap().insert(
+        id,
+        ThreadInfo {
+            id,
+            kind: kind.to_string(),
+            session_id,
+            started_at: chrono::Utc::now().to_rfc3339(),
+        },
+    );
+    let registration = Registration(id);
+    std::thread::Builder::new()
+        .name(format!("vibe {}", kind))
+        .spawn(move || {
+            let _registration = registration;
+            f()
+        })
+        .expect("failed to spawn thread")
+}
+
+/// Threads currently running, oldest first
+pub fn running() -> Vec<ThreadInfo> {
+    let mut threads: Vec<ThreadInfo> = registry().lock().unwrap().values().cloned().collect();
+    threads.sort_by_key(|t| t.id);
+    threads
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_spawn_registers_until_exit() {
+        let (tx, rx) = mpsc::channel::<()>();
+        let handle = spawn("test waiter", Some("s1".to_string()), move || rx.recv().ok());
+        let listed = |kind: &str| running().iter().any(|t| t.kind == kind && t.session_id.as_deref() == Some("s1"));
+        assert!(listed("test waiter"));
+
+        tx.send(()).unwrap();
+        handle.join().unwrap();
+        assert!(!listed("test waiter"));
+    }
+}
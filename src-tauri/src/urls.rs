@@ -0,0 +1,126 @@
+// Plain http(s) URLs in recorded output, for a "links in this session" list
+// Complements OSC 8 hyperlinks: most programs just print the URL
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use crate::db::Event;
+use crate::vt;
+
+// Scheme, then a dotted host name with a letters-only TLD, localhost or an IPv4
+// address, an optional port, and a path/query/fragment up to whitespace or a quote
+const URL_PATTERN: &str = r#"\bhttps?://(?:(?:[A-Za-z0-9](?:[A-Za-z0-9-]*[A-Za-z0-9])?\.)+[A-Za-z]{2,}|localhost|\d{1,3}(?:\.\d{1,3}){3})(?::\d{1,5})?(?:[/?#][^\s<>"'`]*)?"#;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UrlMention {
+    pub url: String,
+    pub first_seen: String, // Timestamp of the first output event it appears in
+    pub first_event_id: String, // That event; a URL split across events is credited to the one it starts in
+    pub count: usize,
+}
+
+fn url_regex() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| Regex::new(URL_PATTERN).expect("invalid built-in URL pattern"))
+}
+
+/// Distinct URLs in pty_out events, in order of first appearance. The output is
+/// scanned as one stream, so a URL printed across several reads is found whole.
+pub fn extract_urls(events: &[Event]) -> Vec<UrlMention> {
+    let mut text = String::new();
+    let mut starts: Vec<(usize, &Event)> = Vec::new(); // Offset in `text` where each event's output begins
+    for event in events.iter().filter(|e| e.kind == "pty_out") {
+        starts.push((text.len(), event));
+        text.push_str(&vt::strip_ansi(&event.data));
+    }
+
+    let mut mentions: Vec<UrlMention> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for found in url_regex().find_iter(&text) {
+        let url = trim_trailing(found.as_str());
+        let slot = *index.entry(url.to_string()).or_insert_with(|| {
+            // The last event starting at or before the match; empty events share an offset
+            let (_, event) = starts[starts.partition_point(|(start, _)| *start <= found.start()) - 1];
+            mentions.push(UrlMention {
+                url: url.to_string(),
+                first_seen: event.ts.clone(),
+                first_event_id: event.id.clone(),
+                count: 0,
+            });
+            mentions.len() - 1
+        });
+        mentions[slot].count += 1;
+    }
+    mentions
+}
+
+// Drop sentence punctuation and closing brackets that belong to the surrounding
+// text, as in "(see https://example.com/a)." Balanced ones, as in Wikipedia links, stay.
+fn trim_trailing(url: &str) -> &str {
+    let mut url = url;
+    while let Some(last) = url.chars().next_back() {
+        let unbalanced = |open: char| url.matches(open).count() < url.matches(last).count();
+        let trim = match last {
+            '.' | ',' | ';' | ':' | '!' | '?' => true,
+            ')' => unbalanced('('),
+            ']' => unbalanced('['),
+            _ => false,
+        };
+        if !trim {
+            break;
+        }
+        url = &url[..url.len() - last.len_utf8()];
+    }
+    url
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    // Named by its timestamp, which is what first_seen reports
+    fn event(ts: &str, data: &str) -> Event {
+        Event { ts: ts.to_string(), ..test_util::event(ts, data) }
+    }
+
+    #[test]
+    fn test_extract_urls() {
+        let events = [
+            event("t1", "origin\thttps://github.com/acme/app.git (fetch)\r\n"),
+            event("t2", "docs: \x1b[4mhttps://en.wikipedia.org/wiki/Tar_(computing)\x1b[0m.\r\n"),
+            event("t3", "again https://github.com/acme/app.git, http://localhost:8080/?q=1 and http://x\r\n"),
+            event("t4", "not a url: ftp://host/file, mailto:a@b.com, https://github.com/acme/app.git"),
+        ];
+        let found: Vec<(String, String, usize)> = extract_urls(&events)
+            .into_iter()
+            .map(|m| (m.url, m.first_seen, m.count))
+            .collect();
+
+        assert_eq!(
+            found,
+            vec![
+                ("https://github.com/acme/app.git".to_string(), "t1".to_string(), 3),
+                ("https://en.wikipedia.org/wiki/Tar_(computing)".to_string(), "t2".to_string(), 1),
+                ("http://localhost:8080/?q=1".to_string(), "t3".to_string(), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extract_urls_across_events() {
+        let events = [
+            event("t1", "Open "),
+            event("t2", "https://github.com/ac"),
+            event("t3", ""),
+            event("t4", "me/app/pull/[1m42[0m to review
+"),
+        ];
+        let found = extract_urls(&events);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].url, "https://github.com/acme/app/pull/42");
+        assert_eq!(found[0].first_event_id, "t2");
+    }
+}
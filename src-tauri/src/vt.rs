@@ -0,0 +1,824 @@
+// VT/ANSI escape sequence tokenizer for recorded output
+// Splits terminal output into text runs, C0 controls and escape sequences
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
+
+use crate::db::Event;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token<'a> {
+    Text(&'a str),
+    Control(char),                              // C0 controls: \n, \r, \t, BEL, BS...
+    Csi { params: &'a str, final_byte: char },  // ESC [ <params/intermediates> <final>
+    Osc(&'a str),                               // ESC ] <payload> (BEL | ST)
+    Str { kind: char, payload: &'a str },       // DCS (P), SOS (X), PM (^), APC (_)
+    Esc { intermediates: &'a str, final_byte: char }, // ESC <intermediates> <final>
+    Invalid(&'a str),                           // Malformed or truncated sequence
+}
+
+/// Tokenize a chunk of terminal output
+pub fn tokenize(input: &str) -> Vec<Token<'_>> {
+    tokenize_spans(input).into_iter().map(|(token, _)| token).collect()
+}
+
+/// Tokenize, also returning each token's byte range in `input`
+pub fn tokenize_spans(input: &str) -> Vec<(Token<'_>, Range<usize>)> {
+    let bytes = input.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let start = i;
+        let b = bytes[i];
+        let token = if b == 0x1b {
+            let (token, next) = escape(input, i);
+            i = next;
+            token
+        } else if b < 0x20 || b == 0x7f {
+            i += 1;
+            Token::Control(b as char)
+        } else {
+            while i < bytes.len() && bytes[i] != 0x1b && bytes[i] >= 0x20 && bytes[i] != 0x7f {
+                i += 1;
+            }
+            Token::Text(&input[start..i])
+        };
+        tokens.push((token, start..i));
+    }
+
+    tokens
+}
+
+// Parse one escape sequence starting at `start` (an ESC byte); returns token and next index
+fn escape(input: &str, start: usize) -> (Token<'_>, usize) {
+    let bytes = input.as_bytes();
+    let mut i = start + 1;
+
+    let Some(&intro) = bytes.get(i) else {
+        return (Token::Invalid(&input[start..]), bytes.len());
+    };
+
+    match intro {
+        b'[' => {
+            i += 1;
+            let params_start = i;
+            // Parameter bytes 0x30-0x3F, then intermediates 0x20-0x2F
+            while i < bytes.len() && (0x20..=0x3f).contains(&bytes[i]) {
+                i += 1;
+            }
+            match bytes.get(i) {
+                Some(&f) if (0x40..=0x7e).contains(&f) => (
+                    Token::Csi { params: &input[params_start..i], final_byte: f as char },
+                    i + 1,
+                ),
+                _ => (Token::Invalid(&input[start..i]), i),
+            }
+        }
+        b']' | b'P' | b'X' | b'^' | b'_' => {
+            i += 1;
+            let payload_start = i;
+            while i < bytes.len() {
+                if bytes[i] == 0x07 && intro == b']' {
+                    return (Token::Osc(&input[payload_start..i]), i + 1);
+                }
+                if bytes[i] == 0x1b && bytes.get(i + 1) == Some(&b'\\') {
+                    let payload = &input[payload_start..i];
+                    let token = if intro == b']' {
+                        Token::Osc(payload)
+                    } else {
+                        Token::Str { kind: intro as char, payload }
+                    };
+                    return (token, i + 2);
+                }
+                i += 1;
+            }
+            (Token::Invalid(&input[start..]), bytes.len())
+        }
+        _ => {
+            let inter_start = i;
+            while i < bytes.len() && (0x20..=0x2f).contains(&bytes[i]) {
+                i += 1;
+            }
+            match bytes.get(i) {
+                Some(&f) if (0x30..=0x7e).contains(&f) => (
+                    Token::Esc { intermediates: &input[inter_start..i], final_byte: f as char },
+                    i + 1,
+                ),
+                _ => (Token::Invalid(&input[start..i]), i),
+            }
+        }
+    }
+}
+
+// Sequences that wipe the whole visible screen
+const CLEAR_SEQUENCES: &[&[u8]] = &[
+    b"\x1b[2J", // ED 2: erase display (what `clear` and Ctrl-L emit)
+    b"\x1b[3J", // ED 3: erase display and scrollback
+    b"\x1bc",   // RIS: full reset
+];
+
+/// Whether a token wipes the whole screen (ED 2/3 or RIS)
+pub fn is_clear(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Csi { params: "2" | "3", final_byte: 'J' } | Token::Esc { intermediates: "", final_byte: 'c' }
+    )
+}
+
+/// Byte offset of the last full-screen clear in `data`, if any
+pub fn last_clear_offset(data: &[u8]) -> Option<usize> {
+    CLEAR_SEQUENCES
+        .iter()
+        .filter_map(|seq| data.windows(seq.len()).rposition(|w| w == *seq))
+        .max()
+}
+
+// CSI final bytes the frontend renderer (xterm.js) handles
+const SUPPORTED_CSI_FINALS: &str = "@ABCDEFGHIJKLMPSTXZ`abcdefghilmnpqrstuxz";
+// Two-character ESC sequences it handles (charset designations are checked separately)
+const SUPPORTED_ESC_FINALS: &str = "78=>DEHMNPZc\\";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SequenceProblem {
+    pub event_id: String,
+    pub kind: String,     // "invalid" | "unsupported"
+    pub sequence: String, // Escaped for display, e.g. "\\u{1b}[5i"
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayReport {
+    pub valid: bool,
+    pub bytes: usize,
+    pub counts: BTreeMap<String, usize>, // e.g. "csi:m", "osc:133", "control:0x0d"
+    pub invalid_count: usize,
+    pub unsupported_count: usize,
+    pub problems: Vec<SequenceProblem>, // First MAX_PROBLEMS findings
+}
+
+const MAX_PROBLEMS: usize = 100;
+
+/// Category label used in replay reports
+pub fn label(token: &Token) -> String {
+    match token {
+        Token::Text(_) => "text".to_string(),
+        Token::Control(c) => format!("control:0x{:02x}", *c as u32),
+        Token::Csi { params, final_byte } => {
+            let private = params.chars().next().filter(|c| "<=>?".contains(*c));
+            format!("csi:{}{}", private.map(String::from).unwrap_or_default(), final_byte)
+        }
+        Token::Osc(payload) => format!("osc:{}", payload.split(';').next().unwrap_or("")),
+        Token::Str { kind, .. } => match kind {
+            'P' => "dcs".to_string(),
+            'X' => "sos".to_string(),
+            '^' => "pm".to_string(),
+            _ => "apc".to_string(),
+        },
+        Token::Esc { intermediates, final_byte } => format!("esc:{}{}", intermediates, final_byte),
+        Token::Invalid(_) => "invalid".to_string(),
+    }
+}
+
+fn is_supported(token: &Token) -> bool {
+    match token {
+        Token::Csi { final_byte, .. } => SUPPORTED_CSI_FINALS.contains(*final_byte),
+        Token::Esc { intermediates, final_byte } => {
+            // ESC ( B etc. select character sets
+            if intermediates.is_empty() {
+                SUPPORTED_ESC_FINALS.contains(*final_byte)
+            } else {
+                intermediates.chars().all(|c| "()*+#%".contains(c))
+            }
+        }
+        Token::Str { kind, .. } => *kind == 'P' || *kind == '_',
+        _ => true,
+    }
+}
+
+/// Run a session's output through the tokenizer and report what it contains.
+/// Output is tokenized as one stream so sequences split across events still parse.
+pub fn validate_events(events: &[Event]) -> ReplayReport {
+    let mut output = String::new();
+    let mut boundaries: Vec<(usize, &str)> = Vec::new(); // (start offset, event id)
+    for event in events.iter().filter(|e| e.kind == "pty_out") {
+        boundaries.push((output.len(), &event.id));
+        output.push_str(&event.data);
+    }
+
+    let mut report = ReplayReport {
+        valid: true,
+        bytes: output.len(),
+        counts: BTreeMap::new(),
+        invalid_count: 0,
+        unsupported_count: 0,
+        problems: Vec::new(),
+    };
+
+    for (token, span) in tokenize_spans(&output) {
+        *report.counts.entry(label(&token)).or_insert(0) += 1;
+
+        let kind = if matches!(token, Token::Invalid(_)) {
+            report.invalid_count += 1;
+            "invalid"
+        } else if !is_supported(&token) {
+            report.unsupported_count += 1;
+            "unsupported"
+        } else {
+            continue;
+        };
+
+        if report.problems.len() < MAX_PROBLEMS {
+            let idx = boundaries.partition_point(|(start, _)| *start <= span.start).saturating_sub(1);
+            report.problems.push(SequenceProblem {
+                event_id: boundaries.get(idx).map(|(_, id)| id.to_string()).unwrap_or_default(),
+                kind: kind.to_string(),
+                sequence: output[span].escape_debug().to_string(),
+            });
+        }
+    }
+
+    report.valid = report.invalid_count == 0 && report.unsupported_count == 0;
+    report
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Color {
+    Indexed(u8), // 0-15 ANSI palette, 16-255 xterm extended
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    /// RGB value using the xterm palette for indexed colors
+    pub fn to_rgb(self) -> (u8, u8, u8) {
+        const ANSI: [(u8, u8, u8); 16] = [
+            (0, 0, 0), (205, 49, 49), (13, 188, 121), (229, 229, 16),
+            (36, 114, 200), (188, 63, 188), (17, 168, 205), (229, 229, 229),
+            (102, 102, 102), (241, 76, 76), (35, 209, 139), (245, 245, 67),
+            (59, 142, 234), (214, 112, 214), (41, 184, 219), (255, 255, 255),
+        ];
+        const CUBE: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+        match self {
+            Color::Rgb(r, g, b) => (r, g, b),
+            Color::Indexed(i @ 0..=15) => ANSI[i as usize],
+            Color::Indexed(i @ 16..=231) => {
+                let i = i - 16;
+                (CUBE[(i / 36) as usize], CUBE[(i / 6 % 6) as usize], CUBE[(i % 6) as usize])
+            }
+            Color::Indexed(i) => {
+                let level = 8 + (i - 232) * 10;
+                (level, level, level)
+            }
+        }
+    }
+
+    pub fn to_hex(self) -> String {
+        let (r, g, b) = self.to_rgb();
+        format!("#{:02x}{:02x}{:02x}", r, g, b)
+    }
+}
+
+/// Graphic rendition state tracked across SGR (CSI ... m) sequences
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Style {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub inverse: bool,
+    pub strikethrough: bool,
+}
+
+impl Style {
+    /// Apply the parameters of one SGR sequence (e.g. "1;38;5;208")
+    pub fn apply_sgr(&mut self, params: &str) {
+        if params.is_empty() {
+            *self = Style::default();
+            return;
+        }
+
+        let parts: Vec<&str> = params.split(';').collect();
+        let num = |idx: usize| parts.get(idx).and_then(|p| p.parse::<u16>().ok());
+        let mut i = 0;
+
+        while i < parts.len() {
+            // Colon sub-parameter form: 38:5:n, 38:2::r:g:b, 4:3
+            if parts[i].contains(':') {
+                let sub: Vec<u16> = parts[i].split(':').map(|p| p.parse().unwrap_or(0)).collect();
+                match sub[0] {
+                    38 | 48 => {
+                        let color = match sub.get(1) {
+                            Some(5) => sub.get(2).map(|&n| Color::Indexed(n as u8)),
+                            Some(2) if sub.len() >= 5 => {
+                                let rgb = &sub[sub.len() - 3..];
+                                Some(Color::Rgb(rgb[0] as u8, rgb[1] as u8, rgb[2] as u8))
+                            }
+                            _ => None,
+                        };
+                        if sub[0] == 38 {
+                            self.fg = color;
+                        } else {
+                            self.bg = color;
+                        }
+                    }
+                    4 => self.underline = sub.get(1).copied().unwrap_or(1) != 0,
+                    _ => {}
+                }
+                i += 1;
+                continue;
+            }
+
+            let code = num(i).unwrap_or(0);
+            match code {
+                0 => *self = Style::default(),
+                1 => self.bold = true,
+                2 => self.dim = true,
+                3 => self.italic = true,
+                4 | 21 => self.underline = true,
+                7 => self.inverse = true,
+                9 => self.strikethrough = true,
+                22 => {
+                    self.bold = false;
+                    self.dim = false;
+                }
+                23 => self.italic = false,
+                24 => self.underline = false,
+                27 => self.inverse = false,
+                29 => self.strikethrough = false,
+                30..=37 => self.fg = Some(Color::Indexed((code - 30) as u8)),
+                39 => self.fg = None,
+                40..=47 => self.bg = Some(Color::Indexed((code - 40) as u8)),
+                49 => self.bg = None,
+                90..=97 => self.fg = Some(Color::Indexed((code - 90 + 8) as u8)),
+                100..=107 => self.bg = Some(Color::Indexed((code - 100 + 8) as u8)),
+                38 | 48 => {
+                    // Extended color: 5;n (256-color) or 2;r;g;b (truecolor)
+                    let (color, used) = match num(i + 1) {
+                        Some(5) => (num(i + 2).map(|n| Color::Indexed(n as u8)), 2),
+                        Some(2) => match (num(i + 2), num(i + 3), num(i + 4)) {
+                            (Some(r), Some(g), Some(b)) => (Some(Color::Rgb(r as u8, g as u8, b as u8)), 4),
+                            _ => (None, 4),
+                        },
+                        _ => (None, 0),
+                    };
+                    if code == 38 {
+                        self.fg = color;
+                    } else {
+                        self.bg = color;
+                    }
+                    i += used;
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+    }
+}
+
+/// Remove escape sequences, keeping printable text and \n, \r, \t
+pub fn strip_ansi(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    for token in tokenize(input) {
+        match token {
+            Token::Text(text) => out.push_str(text),
+            Token::Control(c @ ('\n' | '\r' | '\t')) => out.push(c),
+            _ => {}
+        }
+    }
+    out
+}
+
+/// Text drawn in one style. Colors keep the palette index for indexed colors
+/// (None is the terminal's default); Color::to_hex resolves them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StyledRun {
+    pub text: String,
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+}
+
+/// Split output into runs of text sharing a style, after applying SGR sequences.
+/// Text, \n, \r and \t are kept as sent; other escape sequences are dropped.
+pub fn styled_runs(output: &str) -> Vec<StyledRun> {
+    let mut runs: Vec<StyledRun> = Vec::new();
+    let mut style = Style::default();
+    for (token, span) in tokenize_spans(output) {
+        match token {
+            Token::Text(_) | Token::Control('\n' | '\r' | '\t') => {}
+            Token::Csi { params, final_byte: 'm' } => {
+                style.apply_sgr(params);
+                continue;
+            }
+            _ => continue,
+        }
+        let text = &output[span];
+        match runs.last_mut() {
+            // Attributes runs don't report (inverse, dim...) don't split them
+            Some(last)
+                if (last.fg, last.bg, last.bold, last.italic, last.underline)
+                    == (style.fg, style.bg, style.bold, style.italic, style.underline) =>
+            {
+                last.text.push_str(text)
+            }
+            _ => runs.push(StyledRun {
+                text: text.to_string(),
+                fg: style.fg,
+                bg: style.bg,
+                bold: style.bold,
+                italic: style.italic,
+                underline: style.underline,
+            }),
+        }
+    }
+    runs
+}
+
+/// Readable form of typed input: named keys like <Up> and <Tab>, ^C-style controls,
+/// and other escape sequences spelled out with <Esc>. Enter becomes a line break.
+pub fn describe_input(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut ss3 = false; // After ESC O, the next character names the key
+
+    for (token, span) in tokenize_spans(input) {
+        if ss3 {
+            ss3 = false;
+            if let Token::Text(text) = token {
+                let key = text.chars().next().unwrap_or_default();
+                match key_name("", key) {
+                    Some(name) => out.push_str(name),
+                    None => out.push_str(&format!("<Esc>O{}", key)),
+                }
+                out.push_str(&text[key.len_utf8()..]);
+                continue;
+            }
+            out.push_str("<Esc>O");
+        }
+
+        let spelled = || input[span.clone()].replace('\x1b', "<Esc>");
+        match token {
+            Token::Text(text) => out.push_str(text),
+            Token::Control('\r' | '\n') => out.push('\n'),
+            Token::Control('\t') => out.push_str("<Tab>"),
+            Token::Control('\x7f' | '\x08') => out.push_str("<BS>"),
+            Token::Control(c) => {
+                out.push('^');
+                out.push(((c as u8) ^ 0x40) as char);
+            }
+            Token::Csi { params, final_byte } => match key_name(params, final_byte) {
+                Some(name) => out.push_str(name),
+                None => out.push_str(&spelled()),
+            },
+            Token::Esc { intermediates: "", final_byte: 'O' } => ss3 = true,
+            _ => out.push_str(&spelled()),
+        }
+    }
+    if ss3 {
+        out.push_str("<Esc>O");
+    }
+    out
+}
+
+// Unmodified cursor and editing keys, as sent in CSI (or, with no params, SS3) form
+fn key_name(params: &str, final_byte: char) -> Option<&'static str> {
+    Some(match (params, final_byte) {
+        ("", 'A') => "<Up>",
+        ("", 'B') => "<Down>",
+        ("", 'C') => "<Right>",
+        ("", 'D') => "<Left>",
+        ("", 'H') | ("1", '~') => "<Home>",
+        ("", 'F') | ("4", '~') => "<End>",
+        ("", 'Z') => "<S-Tab>",
+        ("2", '~') => "<Insert>",
+        ("3", '~') => "<Delete>",
+        ("5", '~') => "<PageUp>",
+        ("6", '~') => "<PageDown>",
+        _ => return None,
+    })
+}
+
+// Prefix of the marker text logged for input mode changes
+pub const MODE_MARKER_PREFIX: &str = "mode: ";
+
+// Longest incomplete escape sequence held back for the next chunk of a stream
+pub const MAX_CARRIED_BYTES: usize = 256;
+
+// DEC private modes that change what the terminal sends for keys, mouse and pastes
+const INPUT_MODES: &[(u16, &str)] = &[
+    (1, "application cursor keys"),
+    (66, "application keypad"),
+    (1000, "mouse click reporting"),
+    (1002, "mouse drag reporting"),
+    (1003, "mouse motion reporting"),
+    (1004, "focus reporting"),
+    (1005, "UTF-8 mouse encoding"),
+    (1006, "SGR mouse encoding"),
+    (1015, "urxvt mouse encoding"),
+    (2004, "bracketed paste"),
+];
+
+/// Input-affecting modes as set by a session's output. Every mode starts off, as
+/// after a reset, and only actual changes are reported. A sequence split across
+/// chunks is held back until the rest of it arrives.
+#[derive(Debug, Default)]
+pub struct ModeTracker {
+    on: BTreeSet<u16>,
+    carried: String, // Incomplete escape sequence from the end of the last chunk
+}
+
+impl ModeTracker {
+    /// Marker texts for the modes `output` switched, e.g. "mode: bracketed paste on"
+    pub fn feed(&mut self, output: &str) -> Vec<String> {
+        let mut data = std::mem::take(&mut self.carried);
+        data.push_str(output);
+
+        let mut spans = tokenize_spans(&data);
+        if let Some((Token::Invalid(rest), span)) = spans.last() {
+            if span.end == data.len() && rest.starts_with('\x1b') && rest.len() <= MAX_CARRIED_BYTES {
+                self.carried = rest.to_string();
+                spans.pop();
+            }
+        }
+
+        let mut changes = Vec::new();
+        for (token, _) in spans {
+            match token {
+                Token::Csi { params, final_byte: final_byte @ ('h' | 'l') } => {
+                    // Non-private modes (no '?') are ANSI ones like insert mode
+                    let Some(modes) = params.strip_prefix('?') else {
+                        continue;
+                    };
+                    for mode in modes.split(';').filter_map(|m| m.parse().ok()) {
+                        self.set(mode, final_byte == 'h', &mut changes);
+                    }
+                }
+                // DECKPAM / DECKPNM, the ESC form of mode 66
+                Token::Esc { intermediates: "", final_byte: '=' } => self.set(66, true, &mut changes),
+                Token::Esc { intermediates: "", final_byte: '>' } => self.set(66, false, &mut changes),
+                Token::Esc { intermediates: "", final_byte: 'c' } => {
+                    // RIS: everything back to off
+                    let on: Vec<u16> = self.on.iter().copied().collect();
+                    for mode in on {
+                        self.set(mode, false, &mut changes);
+                    }
+                }
+                _ => {}
+            }
+        }
+        changes
+    }
+
+    fn set(&mut self, mode: u16, enable: bool, changes: &mut Vec<String>) {
+        let Some(&(_, name)) = INPUT_MODES.iter().find(|(m, _)| *m == mode) else {
+            return;
+        };
+        let changed = if enable { self.on.insert(mode) } else { self.on.remove(&mode) };
+        if changed {
+            changes.push(format!("{}{} {}", MODE_MARKER_PREFIX, name, if enable { "on" } else { "off" }));
+        }
+    }
+}
+
+// Output at or above this share of printable characters counts as text
+const TEXT_RATIO_THRESHOLD: f64 = 0.9;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TextProfile {
+    pub sampled_events: usize,
+    pub text_ratio: f64, // Printable / (printable + binary) characters; 1.0 for no output
+    pub is_text: bool,
+}
+
+/// Printable-character ratio over output samples. Well-formed escape sequences count
+/// as neither; stray controls, malformed sequences and U+FFFD (undecodable bytes) are binary.
+pub fn classify_output<S: AsRef<str>>(samples: &[S]) -> TextProfile {
+    let (mut text, mut binary) = (0usize, 0usize);
+
+    for sample in samples {
+        for token in tokenize(sample.as_ref()) {
+            match token {
+                Token::Text(run) => {
+                    let replaced = run.chars().filter(|&c| c == '\u{FFFD}').count();
+                    binary += replaced;
+                    text += run.chars().count() - replaced;
+                }
+                Token::Control('\n' | '\r' | '\t' | '\x08' | '\x07') => text += 1,
+                Token::Control(_) => binary += 1,
+                Token::Invalid(raw) => binary += raw.chars().count(),
+                _ => {}
+            }
+        }
+    }
+
+    let total = text + binary;
+    let text_ratio = if total == 0 { 1.0 } else { text as f64 / total as f64 };
+    TextProfile {
+        sampled_events: samples.len(),
+        text_ratio,
+        is_text: text_ratio >= TEXT_RATIO_THRESHOLD,
+    }
+}
+
+// Most frequent bytes reported by output_entropy
+const TOP_BYTES: usize = 10;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ByteFrequency {
+    pub byte: u8,
+    pub count: u64,
+    pub share: f64, // Fraction of sampled bytes
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EntropyReport {
+    pub sampled_events: usize,
+    pub sampled_bytes: u64,
+    pub entropy: f64, // Shannon entropy in bits per byte, 0.0 to 8.0
+    pub top_bytes: Vec<ByteFrequency>,
+}
+
+/// Shannon entropy and most common bytes of output samples. Plain text and terminal
+/// chatter sit around 4-5 bits/byte; base64 approaches 6 and compressed or encrypted data 8.
+pub fn output_entropy<S: AsRef<str>>(samples: &[S]) -> EntropyReport {
+    let mut counts = [0u64; 256];
+    for sample in samples {
+        for &b in sample.as_ref().as_bytes() {
+            counts[b as usize] += 1;
+        }
+    }
+
+    let total: u64 = counts.iter().sum();
+    let entropy = counts
+        .iter()
+        .filter(|&&c| c > 0)
+        .map(|&c| {
+            let p = c as f64 / total as f64;
+            -p * p.log2()
+        })
+        .sum::<f64>();
+
+    let mut top: Vec<(u8, u64)> = (0..=255u8).zip(counts).filter(|&(_, c)| c > 0).collect();
+    top.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    let top_bytes = top
+        .into_iter()
+        .take(TOP_BYTES)
+        .map(|(byte, count)| ByteFrequency {
+            byte,
+            count,
+            share: count as f64 / total as f64,
+        })
+        .collect();
+
+    EntropyReport {
+        sampled_events: samples.len(),
+        sampled_bytes: total,
+        entropy,
+        top_bytes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::event;
+
+    #[test]
+    fn test_tokenize_mixed() {
+        let tokens = tokenize("\x1b[1;31mred\x1b[0m\r\n\x1b]0;title\x07\x1bc");
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Csi { params: "1;31", final_byte: 'm' },
+                Token::Text("red"),
+                Token::Csi { params: "0", final_byte: 'm' },
+                Token::Control('\r'),
+                Token::Control('\n'),
+                Token::Osc("0;title"),
+                Token::Esc { intermediates: "", final_byte: 'c' },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_last_clear_offset() {
+        assert_eq!(last_clear_offset(b"old\x1b[H\x1b[2Jnew"), Some(6));
+        assert_eq!(last_clear_offset(b"a\x1bcb\x1b[2Jc\x1bcd"), Some(9));
+        assert_eq!(last_clear_offset(b"\x1b[2K partial line erase"), None);
+    }
+
+    #[test]
+    fn test_validate_split_sequence() {
+        // CSI split across two chunks, then a truncated sequence at the end
+        let report = validate_events(&[event("a", "ok\x1b[3"), event("b", "1mred\x1b[0m"), event("c", "\x1b[")]);
+
+        assert_eq!(report.counts.get("csi:m"), Some(&2));
+        assert_eq!(report.invalid_count, 1);
+        assert_eq!(report.problems[0].event_id, "c");
+        assert!(!report.valid);
+    }
+
+    #[test]
+    fn test_apply_sgr() {
+        let mut style = Style::default();
+        style.apply_sgr("1;31;48;5;208");
+        assert!(style.bold);
+        assert_eq!(style.fg, Some(Color::Indexed(1)));
+        assert_eq!(style.bg, Some(Color::Indexed(208)));
+
+        style.apply_sgr("38;2;10;20;30;22");
+        assert_eq!(style.fg, Some(Color::Rgb(10, 20, 30)));
+        assert!(!style.bold);
+
+        style.apply_sgr("48:2::1:2:3");
+        assert_eq!(style.bg, Some(Color::Rgb(1, 2, 3)));
+
+        style.apply_sgr("");
+        assert_eq!(style, Style::default());
+        assert_eq!(Color::Indexed(196).to_hex(), "#ff0000");
+        assert_eq!(Color::Indexed(244).to_hex(), "#808080");
+    }
+
+    #[test]
+    fn test_styled_runs() {
+        let runs = styled_runs("plain \x1b[1;38;5;208mbold\x1b[7m orange\x1b[0m\r\n\x1b[Knext\x1b[48;2;1;2;3m!");
+        let summary: Vec<(&str, Option<Color>, Option<Color>, bool)> =
+            runs.iter().map(|r| (r.text.as_str(), r.fg, r.bg, r.bold)).collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("plain ", None, None, false),
+                ("bold orange", Some(Color::Indexed(208)), None, true),
+                ("\r\nnext", None, None, false),
+                ("!", None, Some(Color::Rgb(1, 2, 3)), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_strip_ansi() {
+        assert_eq!(strip_ansi("\x1b[?2004h$ ls\x1b[K\r\nsrc\x1b[0m"), "$ ls\r\nsrc");
+        assert_eq!(strip_ansi("héllo \x1b["), "héllo ");
+    }
+
+    #[test]
+    fn test_output_entropy() {
+        let uniform: String = (0..64u8).map(|i| (b'0' + i) as char).collect();
+        let report = output_entropy(&[uniform]);
+        assert!((report.entropy - 6.0).abs() < 1e-9);
+        assert_eq!(report.top_bytes.len(), TOP_BYTES);
+
+        let report = output_entropy(&["aaaa", "aaab"]);
+        assert_eq!(report.sampled_bytes, 8);
+        assert_eq!(report.top_bytes[0].byte, b'a');
+        assert_eq!(report.top_bytes[0].share, 0.875);
+        assert_eq!(output_entropy::<&str>(&[]).entropy, 0.0);
+    }
+
+    #[test]
+    fn test_describe_input() {
+        assert_eq!(describe_input("ls\r"), "ls\n");
+        assert_eq!(describe_input("\x1b[A\x1b[A\x03"), "<Up><Up>^C");
+        assert_eq!(describe_input("\x1bOBx\t\x7f"), "<Down>x<Tab><BS>");
+        assert_eq!(describe_input("\x1b[1;5C\x1bb\x1b"), "<Esc>[1;5C<Esc>b<Esc>");
+    }
+
+    #[test]
+    fn test_mode_tracker() {
+        let mut modes = ModeTracker::default();
+        assert_eq!(modes.feed("\x1b[?2004h$ "), vec!["mode: bracketed paste on"]);
+        assert!(modes.feed("\x1b[?2004h\x1b[4h\x1b[?25l").is_empty());
+        assert_eq!(
+            modes.feed("\x1b[?1;1000h\x1b="),
+            vec!["mode: application cursor keys on", "mode: mouse click reporting on", "mode: application keypad on"]
+        );
+        assert_eq!(modes.feed("\x1b[?1000l\x1bc").len(), 4);
+        assert!(modes.feed("\x1b[?2004l").is_empty());
+    }
+
+    #[test]
+    fn test_mode_tracker_split_sequence() {
+        let mut modes = ModeTracker::default();
+        assert!(modes.feed("$ \x1b[?20").is_empty());
+        assert_eq!(modes.feed("04h"), vec!["mode: bracketed paste on"]);
+        assert!(modes.feed("\x1b").is_empty());
+        assert_eq!(modes.feed("="), vec!["mode: application keypad on"]);
+        assert!(modes.feed("\x1b[?2004").is_empty());
+        assert_eq!(modes.feed("l\x1b[?1h"), vec!["mode: bracketed paste off", "mode: application cursor keys on"]);
+    }
+
+    #[test]
+    fn test_classify_output() {
+        let text = classify_output(&["\x1b[32mok\x1b[0m\r\n", "line two\n"]);
+        assert!(text.is_text);
+        assert_eq!(text.text_ratio, 1.0);
+
+        let binary = classify_output(&["ELF\u{FFFD}\u{FFFD}\x00\x00\x01\u{FFFD}"]);
+        assert!(!binary.is_text);
+        assert_eq!(binary.sampled_events, 1);
+    }
+}
@@ -0,0 +1,178 @@
+// Local WebSocket server that streams a live session to a browser tab or script
+// e.g. `websocat 'ws://127.0.0.1:7681/?token=…'`
+// Output goes out as binary frames starting from the current screen; text or binary
+// frames are typed into the session. Every client must present the token, and browsers
+// must come from the app itself: any web page can open a socket to localhost, and
+// WebSocket has no same-origin protection of its own.
+// Traffic, token included, is plain ws:// with no TLS, so LAN mode is only for
+// networks where everyone on them may watch and type into the session.
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tungstenite::http::StatusCode;
+use tungstenite::{Error as WsError, Message};
+
+use crate::threads;
+
+// How often idle loops look for new connections, output and the stop flag
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+// A client that hasn't finished the HTTP upgrade by then is dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+// Origins of the app's own webview; browsers send one of these from inside the app.
+// Clients that send no Origin at all (scripts, websocat) aren't browsers.
+const APP_ORIGINS: &[&str] = &["tauri://localhost", "https://tauri.localhost", "http://tauri.localhost"];
+// The dev server the webview loads from in debug builds (build.devPath)
+const DEV_ORIGIN: &str = "http://localhost:1420";
+
+/// What the server needs from the live session; implemented in main over AppState
+pub trait SessionLink: Send + Sync + 'static {
+    /// Start a new output stream; None once the session is no longer live
+    fn attach(&self) -> Option<String>;
+    /// Next output chunk for a stream; errors once the stream or session is gone
+    fn read(&self, stream_id: &str) -> Result<Option<Bytes>>;
+    /// Type into the session; false once it can't take input anymore
+    fn write(&self, data: Vec<u8>) -> bool;
+    fn detach(&self, stream_id: &str);
+}
+
+/// A listening server plus its accept thread. Dropping it stops accepting and
+/// closes every connection within one poll interval.
+pub struct SessionServer {
+    pub url: String,
+    stop: Arc<AtomicBool>,
+}
+
+impl SessionServer {
+    /// Listen on `port` (0 = any free port) on localhost, or on all interfaces
+    /// with `lan`. Clients without the token, or from another web origin, are refused.
+    pub fn start<L: SessionLink>(session_id: &str, port: u16, lan: bool, link: L) -> Result<SessionServer> {
+        let host = if lan { Ipv4Addr::UNSPECIFIED } else { Ipv4Addr::LOCALHOST };
+        let listener = TcpListener::bind((host, port))
+            .with_context(|| format!("Failed to listen on {}:{}", host, port))?;
+        listener
+            .set_nonblocking(true)
+            .context("Failed to configure listener")?;
+        let port = listener.local_addr().context("Failed to read listening port")?.port();
+
+        let token = uuid::Uuid::new_v4().simple().to_string();
+        let url = format!("ws://{}:{}/?token={}", Ipv4Addr::LOCALHOST, port, token);
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = Arc::clone(&stop);
+        let link = Arc::new(link);
+        let thread_session_id = session_id.to_string();
+        threads::spawn("session server", Some(session_id.to_string()), move || {
+            while !thread_stop.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let link = Arc::clone(&link);
+                        let stop = Arc::clone(&thread_stop);
+                        let token = token.clone();
+                        threads::spawn("session server connection", Some(thread_session_id.clone()), move || {
+                            serve(stream, &token, &*link, &stop)
+                        });
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => std::thread::sleep(POLL_INTERVAL),
+                    Err(e) => {
+                        eprintln!("Session server stopped accepting: {}", e);
+                        break;
+                    }
+                }
+            }
+        });
+
+        Ok(SessionServer { url, stop })
+    }
+}
+
+impl Drop for SessionServer {
+    fn drop(&mut self) {
+        // Not joined: connection threads may be waiting on the session lock the caller holds
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+fn app_origin(origin: &str) -> bool {
+    APP_ORIGINS.contains(&origin) || (cfg!(debug_assertions) && origin == DEV_ORIGIN)
+}
+
+// One client connection, until it closes, the session ends or the server stops
+fn serve(stream: TcpStream, token: &str, link: &dyn SessionLink, stop: &AtomicBool) {
+    if stream.set_nonblocking(false).is_err() || stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT)).is_err() {
+        return;
+    }
+
+    // The error type is tungstenite's HTTP response
+    #[allow(clippy::result_large_err)]
+    let check_client = |request: &Request, response: Response| -> Result<Response, ErrorResponse> {
+        let refuse = |status: StatusCode, reason: &str| {
+            let mut refusal = ErrorResponse::new(Some(reason.to_string()));
+            *refusal.status_mut() = status;
+            Err(refusal)
+        };
+        if let Some(origin) = request.headers().get("Origin") {
+            if !origin.to_str().is_ok_and(app_origin) {
+                return refuse(StatusCode::FORBIDDEN, "Connections from web pages are not allowed");
+            }
+        }
+        let authorized = request
+            .uri()
+            .query()
+            .is_some_and(|query| query.split('&').any(|pair| pair.strip_prefix("token=") == Some(token)));
+        if !authorized {
+            return refuse(StatusCode::UNAUTHORIZED, "Missing or wrong token");
+        }
+        Ok(response)
+    };
+    let Ok(mut socket) = tungstenite::accept_hdr(stream, check_client) else {
+        return;
+    };
+    if socket.get_ref().set_read_timeout(Some(POLL_INTERVAL)).is_err() {
+        return;
+    }
+    let Some(stream_id) = link.attach() else {
+        socket.close(None).ok();
+        return;
+    };
+
+    'connection: while !stop.load(Ordering::Relaxed) {
+        match socket.read() {
+            Ok(Message::Text(text)) => {
+                if !link.write(text.into_bytes()) {
+                    break;
+                }
+            }
+            Ok(Message::Binary(data)) => {
+                if !link.write(data) {
+                    break;
+                }
+            }
+            Ok(Message::Close(_)) => break,
+            Ok(_) => {} // Pings are answered by tungstenite
+            Err(WsError::Io(e)) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(_) => break,
+        }
+
+        loop {
+            match link.read(&stream_id) {
+                Ok(Some(chunk)) => {
+                    if socket.send(Message::Binary(chunk.to_vec())).is_err() {
+                        break 'connection;
+                    }
+                }
+                Ok(None) => break,
+                Err(_) => break 'connection,
+            }
+        }
+    }
+
+    link.detach(&stream_id);
+    socket.close(None).ok();
+    socket.flush().ok();
+}
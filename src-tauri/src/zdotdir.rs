@@ -29,17 +29,7 @@ impl ZdotdirSetup {
 
         fs::create_dir_all(&zdotdir_path).context("Failed to create ZDOTDIR")?;
 
-        // Get path to vibe.zsh - use CARGO_MANIFEST_DIR at compile time
-        let integration_path = if cfg!(debug_assertions) {
-            // Development: use project directory
-            PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-                .parent()
-                .unwrap()
-                .join("shell-integration")
-        } else {
-            // Production: shell-integration should be bundled with the app
-            PathBuf::from(&home).join(".vibecodings/shell-integration")
-        };
+        let integration_path = integration_dir(&home);
 
         // Read template
         let template_path = integration_path.join(".zshrc.template");
@@ -68,3 +58,22 @@ impl ZdotdirSetup {
         Ok(())
     }
 }
+
+/// Whether the zsh integration files are where sessions will look for them
+pub fn integration_installed() -> bool {
+    std::env::var("HOME").is_ok_and(|home| integration_dir(&home).join(".zshrc.template").is_file())
+}
+
+// Get path to vibe.zsh - use CARGO_MANIFEST_DIR at compile time
+fn integration_dir(home: &str) -> PathBuf {
+    if cfg!(debug_assertions) {
+        // Development: use project directory
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .parent()
+            .unwrap()
+            .join("shell-integration")
+    } else {
+        // Production: shell-integration should be bundled with the app
+        PathBuf::from(home).join(".vibecodings/shell-integration")
+    }
+}
@@ -8,6 +8,8 @@ interface Command {
   exit_code: number | null;
   started_at: number;
   ended_at: number | null;
+  output_first_seq: number | null;
+  output_last_seq: number | null;
 }
 
 interface SessionSummary {
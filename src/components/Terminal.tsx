@@ -176,7 +176,9 @@ const Terminal = forwardRef((props, ref) => {
       })
       .catch((error) => {
         console.error("Failed to start session:", error);
-        term.write("\r\n\x1b[31mFailed to start terminal session\x1b[0m\r\n");
+        // start_session fails with { kind, errno, attempts, message }
+        const detail = error?.message ? `: ${error.message}` : "";
+        term.write(`\r\n\x1b[31mFailed to start terminal session${detail}\x1b[0m\r\n`);
       });
 
     // Cleanup